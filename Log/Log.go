@@ -5,7 +5,6 @@ import (
 	"io"
 	api "0243179_SistemasDistribuidos/api/v1"
 	"os"
-	"path"
 	"sort"
 	"strconv"
 	"strings"
@@ -48,23 +47,25 @@ func (l *Log) setup() error {
 	}
 	var baseOffsets []uint64
 	for _, file := range files {
-		offStr := strings.TrimSuffix(
-			file.Name(),
-			path.Ext(file.Name()),
-		)
-		off, _ := strconv.ParseUint(offStr, 10, 0)
+		// cada segmento tiene un solo .store; lo demás (el state y los .key
+		// que deja el Log de log2, por ejemplo) no es un segmento
+		offStr, ok := strings.CutSuffix(file.Name(), ".store")
+		if !ok {
+			continue
+		}
+		off, err := strconv.ParseUint(offStr, 10, 0)
+		if err != nil {
+			continue
+		}
 		baseOffsets = append(baseOffsets, off)
 	}
 	sort.Slice(baseOffsets, func(i, j int) bool {
 		return baseOffsets[i] < baseOffsets[j]
 	})
-	for i := 0; i < len(baseOffsets); i++ {
-		if err = l.newSegment(baseOffsets[i]); err != nil {
+	for _, off := range baseOffsets {
+		if err = l.newSegment(off); err != nil {
 			return err
 		}
-		// baseOffset contains dup for index and store so we skip
-		// the dup
-		i++
 	}
 	if l.segments == nil {
 		if err = l.newSegment(l.Config.Segment.InitialOffset); err != nil {
@@ -3,6 +3,7 @@ package log
 import (
 	"io"
 	"os"
+	"path/filepath"
 	"testing"
 
 	"github.com/stretchr/testify/require"
@@ -16,11 +17,12 @@ func TestLog(t *testing.T) {
 	for scenario, fn := range map[string]func(
 		t *testing.T, log *Log,
 	){
-		"append and read a record succeeds": testAppendRead,
-		"offset out of range error":         testOutOfRangeErr,
-		"init with existing segments":       testInitExisting,
-		"reader":                            testReader,
-		"truncate":                          testTruncate,
+		"append and read a record succeeds":     testAppendRead,
+		"offset out of range error":             testOutOfRangeErr,
+		"init with existing segments":           testInitExisting,
+		"init skips files that aren't segments": testInitSkipsOtherFiles,
+		"reader":                                testReader,
+		"truncate":                              testTruncate,
 	} {
 		t.Run(scenario, func(t *testing.T) {
 			dir, err := os.MkdirTemp("", "store-test")
@@ -95,6 +97,29 @@ func testInitExisting(t *testing.T, o *Log) {
 
 // END: init_existing
 
+// el Log de log2 deja un state y, si cifra, un .key junto a los segmentos
+func testInitSkipsOtherFiles(t *testing.T, o *Log) {
+	append := &api.Record{
+		Value: []byte("hello world"),
+	}
+	for i := 0; i < 3; i++ {
+		_, err := o.Append(append)
+		require.NoError(t, err)
+	}
+	require.NoError(t, o.Close())
+	require.NoError(t, os.WriteFile(filepath.Join(o.Dir, "state"), []byte("state"), 0644))
+	require.NoError(t, os.WriteFile(filepath.Join(o.Dir, "2.key"), []byte("k1"), 0644))
+
+	n, err := NewLog(o.Dir, o.Config)
+	require.NoError(t, err)
+	require.Equal(t, 2, len(n.segments))
+	for i := uint64(0); i < 3; i++ {
+		read, err := n.Read(i)
+		require.NoError(t, err)
+		require.Equal(t, append.Value, read.Value)
+	}
+}
+
 // START: reader
 func testReader(t *testing.T, log *Log) {
 	append := &api.Record{
@@ -40,11 +40,33 @@ func newIndex(f *os.File, c Config) (*index, error) {
 		return nil, errMap
 	}
 
-	return &index{
+	idx := &index{
 		file: f,
 		mmap: newmmap,
-		size: sizeFile,
-	}, nil
+	}
+	//los index que no se cerraron bien traen ceros al final, solo contamos las entradas validas
+	if sizeFile > c.Segment.MaxIndexBytes {
+		sizeFile = c.Segment.MaxIndexBytes
+	}
+	idx.size = idx.usedSize(sizeFile)
+
+	return idx, nil
+}
+
+// una entrada es valida si su offset coincide con su lugar en el index,
+// la primera siempre apunta a la posición 0 del store y las demás no
+func (i *index) usedSize(sizeFile uint64) uint64 {
+	var used uint64
+	for used+entWidth <= sizeFile {
+		entry := used / entWidth
+		off := enc.Uint32(i.mmap[used : used+offWidth])
+		pos := enc.Uint64(i.mmap[used+offWidth : used+entWidth])
+		if uint64(off) != entry || (entry > 0 && pos == 0) {
+			break
+		}
+		used += entWidth
+	}
+	return used
 }
 
 func (i *index) Name() string {
@@ -96,6 +118,18 @@ func (i *index) Write(off uint32, pos uint64) error {
 
 func (i *index) Close() error {
 
+	//guardamos el mmap en disco
+	if err := i.mmap.Sync(gommap.MS_SYNC); err != nil {
+		return err
+	}
+	if err := i.file.Sync(); err != nil {
+		return err
+	}
+	//quitamos el espacio que no se usó para que el log de Rust lo pueda abrir
+	if err := i.file.Truncate(int64(i.size)); err != nil {
+		return err
+	}
+
 	//cerramos el archivo :D
 	return i.file.Close()
 
@@ -0,0 +1,61 @@
+package log
+
+import (
+	"os"
+	"path/filepath"
+	"testing"
+
+	"github.com/stretchr/testify/require"
+
+	api "0243179_SistemasDistribuidos/api/v1"
+)
+
+// testdata/interop/go tiene el formato que deja este paquete (index sin truncar)
+// y testdata/interop/rust lo escribió el Log de log2 con los mismos registros,
+// con todo y el state que deja junto a los segmentos
+func TestInterop(t *testing.T) {
+	for _, name := range []string{"go", "rust"} {
+		t.Run(name, func(t *testing.T) {
+			dir := copyFixture(t, filepath.Join("testdata", "interop", name))
+			defer os.RemoveAll(dir)
+
+			c := Config{}
+			c.Segment.MaxStoreBytes = 32
+			c.Segment.MaxIndexBytes = 1024
+			log, err := NewLog(dir, c)
+			require.NoError(t, err)
+
+			values := []string{"hello world", "adios mundo", "distributed", "commit log!"}
+			for i, value := range values {
+				record, err := log.Read(uint64(i))
+				require.NoError(t, err)
+				require.Equal(t, []byte(value), record.Value)
+				require.Equal(t, uint64(i), record.Offset)
+			}
+
+			off, err := log.Append(&api.Record{Value: []byte("desde go")})
+			require.NoError(t, err)
+			require.Equal(t, uint64(len(values)), off)
+			require.NoError(t, log.Close())
+
+			log, err = NewLog(dir, c)
+			require.NoError(t, err)
+			record, err := log.Read(off)
+			require.NoError(t, err)
+			require.Equal(t, []byte("desde go"), record.Value)
+		})
+	}
+}
+
+func copyFixture(t *testing.T, src string) string {
+	dir, err := os.MkdirTemp("", "interop-test")
+	require.NoError(t, err)
+	files, err := os.ReadDir(src)
+	require.NoError(t, err)
+	for _, file := range files {
+		b, err := os.ReadFile(filepath.Join(src, file.Name()))
+		require.NoError(t, err)
+		require.NoError(t, os.WriteFile(filepath.Join(dir, file.Name()), b, 0644))
+	}
+	return dir
+}
@@ -5,8 +5,13 @@
 fn main() {
     let proto_file = "src/log.proto";
 
-    prost_build::Config::new()
+    // el descriptor es para server reflection (ver comp/health.rs)
+    let descriptor = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap())
+        .join("record_descriptor.bin");
+
+    tonic_build::configure()
         .out_dir("src/comp")
-        .compile_protos(&[proto_file], &["proto/"])
+        .file_descriptor_set_path(descriptor)
+        .compile(&[proto_file], &["src/"])
         .expect("Failed to compile Protobuf files");
 }
@@ -0,0 +1,173 @@
+use std::collections::HashSet;
+use std::io;
+use tonic::Request;
+
+// Lo mismo que auth/authorizer.go pero sin casbin: solo entendemos el modelo
+// de cert/model.conf, donde una regla "p, sub, obj, act" permite exactamente
+// ese subject, objeto y acción. Con otro modelo el servidor no arranca.
+pub const OBJECT_WILDCARD: &str = "*";
+pub const PRODUCE_ACTION: &str = "produce";
+pub const CONSUME_ACTION: &str = "consume";
+// el servicio Admin, ver comp/admin.rs
+pub const ADMIN_ACTION: &str = "admin";
+
+const MATCHER: &str = "r.sub == p.sub && r.obj == p.obj && r.act == p.act";
+
+#[derive(Debug)]
+pub struct Authorizer {
+    policies: HashSet<(String, String, String)>,
+}
+
+impl Authorizer {
+    pub fn new(model_file: &str, policy_file: &str) -> io::Result<Self> {
+        check_model(&read(model_file)?).map_err(|msg| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: unsupported ACL model, {}", model_file, msg),
+            )
+        })?;
+        Authorizer::parse(&read(policy_file)?)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", policy_file, e)))
+    }
+
+    pub fn parse(policy: &str) -> io::Result<Self> {
+        let mut policies = HashSet::new();
+        for (n, line) in policy.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            match fields[..] {
+                ["p", sub, obj, act] => {
+                    policies.insert((sub.to_string(), obj.to_string(), act.to_string()));
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line {}: expected \"p, <sub>, <obj>, <act>\"", n + 1),
+                    ))
+                }
+            }
+        }
+        Ok(Authorizer { policies })
+    }
+
+    // PermissionDenied con el mismo mensaje que el servidor de Go
+    pub fn authorize(&self, subject: &str, object: &str, action: &str) -> io::Result<()> {
+        let rule = (subject.to_string(), object.to_string(), action.to_string());
+        if self.policies.contains(&rule) {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} not permitted to {} to {}", subject, action, object),
+        ))
+    }
+}
+
+fn read(path: &str) -> io::Result<String> {
+    std::fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))
+}
+
+fn check_model(model: &str) -> Result<(), String> {
+    let mut section = "";
+    let mut found = 0;
+    for line in model.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            section = line;
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("can't read {:?}", line));
+        };
+        let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+        let expected = match (section, key.trim()) {
+            ("[request_definition]", "r") | ("[policy_definition]", "p") => "sub, obj, act",
+            ("[policy_effect]", "e") => "some(where (p.eft == allow))",
+            ("[matchers]", "m") => MATCHER,
+            _ => return Err(format!("{} {} is not supported", section, key.trim())),
+        };
+        if value != expected {
+            return Err(format!("expected {} = {}", key.trim(), expected));
+        }
+        found += 1;
+    }
+    if found != 4 {
+        return Err("it needs r, p, e and m".to_string());
+    }
+    Ok(())
+}
+
+// El subject es el CN del certificado del cliente, como en server.go. Sin TLS
+// (o sin certificado, como el gateway HTTP) es "" y ninguna regla lo permite.
+pub fn subject<T>(req: &Request<T>) -> String {
+    let Some(certs) = req.peer_certs() else {
+        return String::new();
+    };
+    certs
+        .first()
+        .and_then(|cert| common_name(cert.get_ref()))
+        .unwrap_or_default()
+}
+
+pub fn common_name(der: &[u8]) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    let cn = cert.subject().iter_common_name().next()?;
+    cn.as_str().ok().map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // los archivos de prueba del servidor de Go
+    const CERT_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../cert");
+
+    fn cert_file(name: &str) -> String {
+        format!("{}/{}", CERT_DIR, name)
+    }
+
+    #[test]
+    fn authorize_with_go_acl() {
+        let authorizer =
+            Authorizer::new(&cert_file("model.conf"), &cert_file("policy.csv")).unwrap();
+        authorizer
+            .authorize("root", OBJECT_WILDCARD, PRODUCE_ACTION)
+            .unwrap();
+        authorizer
+            .authorize("root", OBJECT_WILDCARD, CONSUME_ACTION)
+            .unwrap();
+        let err = authorizer
+            .authorize("nobody", OBJECT_WILDCARD, PRODUCE_ACTION)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(err.to_string(), "nobody not permitted to produce to *");
+        assert!(authorizer
+            .authorize("", OBJECT_WILDCARD, CONSUME_ACTION)
+            .is_err());
+
+        assert!(Authorizer::parse("g, alice, admin\n").is_err());
+        let dir = tempfile::tempdir().unwrap();
+        let model = dir.path().join("model.conf");
+        std::fs::write(&model, "[matchers]\nm = r.sub == p.sub\n").unwrap();
+        let err = Authorizer::new(model.to_str().unwrap(), &cert_file("policy.csv")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn common_name_of_client_certs() {
+        for (file, cn) in [("root-client.pem", "root"), ("nobody-client.pem", "nobody")] {
+            let pem = std::fs::read_to_string(cert_file(file)).unwrap();
+            let der = x509_parser::pem::parse_x509_pem(pem.as_bytes())
+                .unwrap()
+                .1
+                .contents;
+            assert_eq!(common_name(&der).as_deref(), Some(cn));
+        }
+        assert_eq!(subject(&Request::new(())), "");
+    }
+}
@@ -0,0 +1,295 @@
+use crate::comp::config::Config;
+use crate::comp::log::Log;
+use crate::comp::tiered;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+struct Topic {
+    partitions: Vec<Arc<RwLock<Log>>>,
+    // para los registros sin key, se reparten por turnos
+    next: AtomicU32,
+}
+
+// Maneja muchos topics, cada uno con N particiones. En disco cada topic es un
+// directorio y cada partición un Log dentro de él: <dir>/<topic>/<partición>/.
+pub struct Broker {
+    dir: PathBuf,
+    config: Config,
+    topics: RwLock<HashMap<String, Topic>>,
+}
+
+impl Broker {
+    pub async fn new(dir: &str, config: Config) -> io::Result<Self> {
+        config.validate()?;
+        let dir = Path::new(dir).to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut topics = HashMap::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            if validate_name(&name).is_err() {
+                continue;
+            }
+            let mut count = 0;
+            for p in std::fs::read_dir(entry.path())? {
+                if p?.file_name().to_string_lossy().parse::<u32>().is_ok() {
+                    count += 1;
+                }
+            }
+            if count == 0 {
+                continue;
+            }
+            topics.insert(
+                name.clone(),
+                open_topic(&dir.join(&name), count, &config).await?,
+            );
+        }
+
+        Ok(Broker {
+            dir,
+            config,
+            topics: RwLock::new(topics),
+        })
+    }
+
+    pub async fn create_topic(&self, name: &str, partitions: u32) -> io::Result<()> {
+        validate_name(name)?;
+        if partitions == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a topic needs at least one partition",
+            ));
+        }
+        let mut topics = self.topics.write().await;
+        if topics.contains_key(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("topic already exists: {}", name),
+            ));
+        }
+        let topic = open_topic(&self.dir.join(name), partitions, &self.config).await?;
+        topics.insert(name.to_string(), topic);
+        Ok(())
+    }
+
+    pub async fn delete_topic(&self, name: &str) -> io::Result<()> {
+        let topic = self
+            .topics
+            .write()
+            .await
+            .remove(name)
+            .ok_or_else(|| topic_not_found(name))?;
+        // remove y no close para que también se borre lo que está en el object store
+        for partition in &topic.partitions {
+            partition.write().await.remove().await?;
+        }
+        std::fs::remove_dir_all(self.dir.join(name))
+    }
+
+    // (nombre, particiones) de cada topic, ordenados por nombre
+    pub async fn list_topics(&self) -> Vec<(String, u32)> {
+        let mut topics: Vec<(String, u32)> = self
+            .topics
+            .read()
+            .await
+            .iter()
+            .map(|(name, t)| (name.clone(), t.partitions.len() as u32))
+            .collect();
+        topics.sort();
+        topics
+    }
+
+    pub async fn partition(&self, topic: &str, partition: u32) -> io::Result<Arc<RwLock<Log>>> {
+        let topics = self.topics.read().await;
+        let t = topics.get(topic).ok_or_else(|| topic_not_found(topic))?;
+        t.partitions
+            .get(partition as usize)
+            .cloned()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("partition {} not found in topic {}", partition, topic),
+                )
+            })
+    }
+
+    // la misma key siempre cae en la misma partición; sin key se reparten por turnos
+    pub async fn partition_for(&self, topic: &str, key: &[u8]) -> io::Result<u32> {
+        let topics = self.topics.read().await;
+        let t = topics.get(topic).ok_or_else(|| topic_not_found(topic))?;
+        let n = t.partitions.len() as u32;
+        if key.is_empty() {
+            return Ok(t.next.fetch_add(1, Ordering::Relaxed) % n);
+        }
+        Ok(partition_for_key(key, n))
+    }
+
+    // la retención de cada partición, ver Log::retain
+    pub async fn retain(&self, max_records: u64) -> io::Result<()> {
+        for topic in self.topics.read().await.values() {
+            for partition in &topic.partitions {
+                partition.write().await.retain(max_records).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn close(&self) -> io::Result<()> {
+        for topic in self.topics.read().await.values() {
+            for partition in &topic.partitions {
+                partition.write().await.close().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn open_topic(dir: &Path, partitions: u32, config: &Config) -> io::Result<Topic> {
+    let mut logs = vec![];
+    for p in 0..partitions {
+        let path = dir.join(p.to_string());
+        std::fs::create_dir_all(&path)?;
+        let name = dir.file_name().unwrap_or_default().to_string_lossy();
+        let config = tiered::under(config, &format!("{}/{}", name, p));
+        let log = Log::new(path.to_str().unwrap(), config).await?;
+        logs.push(Arc::new(RwLock::new(log)));
+    }
+    Ok(Topic {
+        partitions: logs,
+        next: AtomicU32::new(0),
+    })
+}
+
+// el nombre se vuelve un directorio, así que nada de separadores ni "." / ".."
+fn validate_name(name: &str) -> io::Result<()> {
+    let valid = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid topic name: {:?}", name),
+        ))
+    }
+}
+
+fn topic_not_found(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("topic not found: {}", name),
+    )
+}
+
+// FNV-1a de 32 bits: es estable entre versiones y entre el cliente y el servidor
+pub fn partition_for_key(key: &[u8], partitions: u32) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for b in key {
+        hash ^= *b as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash % partitions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comp::config::test_config;
+    use crate::comp::record::Record;
+
+    #[tokio::test]
+    async fn create_list_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let broker = Broker::new(path, test_config()).await.unwrap();
+
+        broker.create_topic("pedidos", 3).await.unwrap();
+        broker.create_topic("pagos", 1).await.unwrap();
+        let err = broker.create_topic("pedidos", 2).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        for bad in ["", "..", "a/b", "con espacio"] {
+            let err = broker.create_topic(bad, 1).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(
+            broker.create_topic("vacio", 0).await.unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        let want = vec![("pagos".to_string(), 1), ("pedidos".to_string(), 3)];
+        assert_eq!(broker.list_topics().await, want);
+        assert!(dir.path().join("pedidos/2").is_dir());
+
+        let log = broker.partition("pedidos", 2).await.unwrap();
+        log.write()
+            .await
+            .append(Record {
+                value: b"hola".to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+        assert!(broker.partition("pedidos", 3).await.is_err());
+        broker.close().await.unwrap();
+
+        // al reabrir siguen ahí los topics con sus registros
+        let broker = Broker::new(path, test_config()).await.unwrap();
+        assert_eq!(broker.list_topics().await, want);
+        let log = broker.partition("pedidos", 2).await.unwrap();
+        assert_eq!(
+            log.read().await.read(0).await.unwrap().value,
+            b"hola".to_vec()
+        );
+
+        broker.delete_topic("pedidos").await.unwrap();
+        assert!(!dir.path().join("pedidos").exists());
+        assert_eq!(broker.list_topics().await, want[..1].to_vec());
+        let err = broker.partition("pedidos", 0).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn partition_by_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = Broker::new(dir.path().to_str().unwrap(), test_config())
+            .await
+            .unwrap();
+        broker.create_topic("eventos", 4).await.unwrap();
+
+        let p = broker.partition_for("eventos", b"cliente-1").await.unwrap();
+        for _ in 0..10 {
+            assert_eq!(
+                broker.partition_for("eventos", b"cliente-1").await.unwrap(),
+                p
+            );
+        }
+        assert_eq!(p, partition_for_key(b"cliente-1", 4));
+
+        // sin key se reparten por turnos
+        let mut seen = vec![];
+        for _ in 0..4 {
+            seen.push(broker.partition_for("eventos", b"").await.unwrap());
+        }
+        seen.sort();
+        assert_eq!(seen, [0, 1, 2, 3]);
+
+        // muchas keys distintas llegan a todas las particiones
+        let mut used = [false; 4];
+        for i in 0..100 {
+            used[partition_for_key(format!("key-{}", i).as_bytes(), 4) as usize] = true;
+        }
+        assert!(used.iter().all(|u| *u));
+    }
+}
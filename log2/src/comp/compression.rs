@@ -0,0 +1,106 @@
+use crate::comp::config::Compression;
+use std::io;
+use std::str::FromStr;
+
+// nivel de zstd, el default de la librería
+const ZSTD_LEVEL: i32 = 3;
+
+// bits de las banderas del frame (ver Store::append) que dicen el códec
+pub const CODEC_MASK: u8 = 0b111;
+
+impl Compression {
+    pub fn flag(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zstd => 1,
+            Compression::Lz4 => 2,
+            Compression::Snappy => 3,
+        }
+    }
+
+    pub fn from_flags(flags: u8) -> io::Result<Self> {
+        match flags & CODEC_MASK {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Zstd),
+            2 => Ok(Compression::Lz4),
+            3 => Ok(Compression::Snappy),
+            codec => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown compression codec {}", codec),
+            )),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s {
+            "none" => Ok(Compression::None),
+            "zstd" => Ok(Compression::Zstd),
+            "lz4" => Ok(Compression::Lz4),
+            "snappy" => Ok(Compression::Snappy),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown compression {:?} (none, zstd, lz4, snappy)", s),
+            )),
+        }
+    }
+}
+
+// Regresa el códec con el que quedó y los datos. Si comprimir no ahorra nada
+// (registros chiquitos) se quedan como venían, con Compression::None.
+pub fn compress(codec: Compression, data: Vec<u8>) -> io::Result<(Compression, Vec<u8>)> {
+    let compressed = match codec {
+        Compression::None => return Ok((codec, data)),
+        Compression::Zstd => zstd::bulk::compress(&data, ZSTD_LEVEL)?,
+        Compression::Lz4 => lz4_flex::compress_prepend_size(&data),
+        Compression::Snappy => snap::raw::Encoder::new()
+            .compress_vec(&data)
+            .map_err(invalid_data)?,
+    };
+    if compressed.len() >= data.len() {
+        return Ok((Compression::None, data));
+    }
+    Ok((codec, compressed))
+}
+
+pub fn decompress(codec: Compression, data: Vec<u8>) -> io::Result<Vec<u8>> {
+    match codec {
+        Compression::None => Ok(data),
+        Compression::Zstd => zstd::stream::decode_all(data.as_slice()),
+        Compression::Lz4 => lz4_flex::decompress_size_prepended(&data).map_err(invalid_data),
+        Compression::Snappy => snap::raw::Decoder::new()
+            .decompress_vec(&data)
+            .map_err(invalid_data),
+    }
+}
+
+fn invalid_data<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let json = br#"{"cliente": "acme", "total": 100, "estado": "pagado"}"#.repeat(20);
+        for codec in [Compression::Zstd, Compression::Lz4, Compression::Snappy] {
+            let (used, compressed) = compress(codec, json.clone()).unwrap();
+            assert_eq!(used, codec);
+            assert!(compressed.len() < json.len());
+            assert_eq!(Compression::from_flags(used.flag()).unwrap(), codec);
+            assert_eq!(decompress(used, compressed).unwrap(), json);
+        }
+
+        // si no se gana nada se guarda tal cual
+        let (used, data) = compress(Compression::Zstd, b"ab".to_vec()).unwrap();
+        assert_eq!(used, Compression::None);
+        assert_eq!(data, b"ab");
+
+        assert!(Compression::from_flags(7).is_err());
+    }
+}
@@ -1,6 +1,102 @@
-#[derive(Debug, Copy, Clone)]
+use crate::comp::encryption::{self, Keyring};
+use crate::comp::index::ENT_WIDTH;
+use crate::comp::store::LEN_WIDTH;
+use crate::comp::tiered::ObjectStore;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
 pub struct Config {
     pub segment: SegmentConfig,
+    // con qué se comprimen los registros nuevos; los viejos se leen con lo que
+    // diga su frame (ver comp/compression.rs)
+    pub compression: Compression,
+    // con llaves los .store se cifran y el log ya no lo lee el de Go (ver
+    // comp/encryption.rs)
+    pub encryption: Option<Arc<Keyring>>,
+    // con un object store los segmentos sellados se suben y se borran del disco
+    pub tiering: Option<Tiering>,
+}
+
+impl Config {
+    // Log la llama antes de crear cualquier archivo: un index que no es
+    // múltiplo de la entrada deja basura al final y un store donde no cabe un
+    // registro hace un segmento por registro.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |msg: String| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        let segment = &self.segment;
+        if segment.max_index_bytes == 0 || !segment.max_index_bytes.is_multiple_of(ENT_WIDTH) {
+            return invalid(format!(
+                "max_index_bytes must be a positive multiple of {} (one index entry), got {}",
+                ENT_WIDTH, segment.max_index_bytes
+            ));
+        }
+        // los offsets en el index son relativos al segmento y de 32 bits
+        if segment.max_index_bytes / ENT_WIDTH > u32::MAX as u64 + 1 {
+            return invalid(format!(
+                "max_index_bytes allows more than 2^32 entries per segment, got {}",
+                segment.max_index_bytes
+            ));
+        }
+        let (frame, what) = match self.encryption {
+            Some(_) => (LEN_WIDTH + encryption::OVERHEAD, "an encrypted record"),
+            None => (LEN_WIDTH, "a record"),
+        };
+        if segment.max_store_bytes <= frame as u64 {
+            return invalid(format!(
+                "max_store_bytes must be greater than {} (the frame of {}), got {}",
+                frame, what, segment.max_store_bytes
+            ));
+        }
+        Ok(())
+    }
+}
+
+// el de las pruebas: segmentos chicos, sin compresión, cifrado ni object store
+#[cfg(test)]
+pub(crate) fn test_config() -> Config {
+    Config {
+        segment: SegmentConfig {
+            max_store_bytes: 1024,
+            max_index_bytes: 1200,
+            initial_offset: 0,
+        },
+        compression: Compression::None,
+        encryption: None,
+        tiering: None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tiering {
+    pub store: Arc<dyn ObjectStore>,
+    // dónde quedan los objetos de este log (ver tiered::under)
+    pub prefix: String,
+    // cuantos segmentos sellados se quedan en disco además del activo
+    pub local_segments: usize,
+    // cuantos segmentos remotos se guardan en memoria para leerlos
+    pub cache_segments: usize,
+}
+
+impl Tiering {
+    pub fn new(store: Arc<dyn ObjectStore>) -> Self {
+        Tiering {
+            store,
+            prefix: String::new(),
+            local_segments: 2,
+            cache_segments: 4,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Zstd,
+    Lz4,
+    Snappy,
 }
 #[derive(Debug, Copy, Clone)]
 
@@ -9,3 +105,70 @@ pub struct SegmentConfig {
     pub max_index_bytes: u64,
     pub initial_offset: u64,
 }
+
+#[derive(Debug, Clone)]
+pub struct RaftConfig {
+    pub local_id: String,
+    // dirección donde escucha el stream layer de raft
+    pub bind_addr: String,
+    // dirección de gRPC del nodo, la que ven los clientes
+    pub rpc_addr: String,
+    // cada cuanto el lider manda AppendEntries aunque no haya entradas nuevas
+    pub heartbeat_timeout: Duration,
+    // sin noticias del lider en este tiempo (más un poco al azar) empieza una elección
+    pub election_timeout: Duration,
+    pub commit_timeout: Duration,
+    // solo el primer nodo del cluster, se pone a sí mismo como único votante
+    pub bootstrap: bool,
+}
+
+impl Default for RaftConfig {
+    fn default() -> Self {
+        RaftConfig {
+            local_id: String::new(),
+            bind_addr: String::new(),
+            rpc_addr: String::new(),
+            heartbeat_timeout: Duration::from_millis(50),
+            election_timeout: Duration::from_millis(300),
+            commit_timeout: Duration::from_secs(10),
+            bootstrap: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MembershipConfig {
+    pub node_name: String,
+    // dirección UDP del gossip
+    pub bind_addr: String,
+    // lo que el nodo anuncia a los demás, por ejemplo rpc_addr
+    pub tags: HashMap<String, String>,
+    // nodos del cluster a los que le hablamos para entrar
+    pub start_join_addrs: Vec<String>,
+    // cada cuanto le hacemos ping a un miembro
+    pub probe_interval: Duration,
+    // cuanto esperamos el ack antes de pedir pings indirectos
+    pub probe_timeout: Duration,
+    // cuantos nodos le hacen ping por nosotros cuando no contestan
+    pub indirect_checks: usize,
+    // cuanto tiempo un sospechoso tiene para desmentirlo antes de darlo por muerto
+    pub suspicion_timeout: Duration,
+    // cada cuanto intercambiamos el estado completo con alguien
+    pub sync_interval: Duration,
+}
+
+impl Default for MembershipConfig {
+    fn default() -> Self {
+        MembershipConfig {
+            node_name: String::new(),
+            bind_addr: String::new(),
+            tags: HashMap::new(),
+            start_join_addrs: vec![],
+            probe_interval: Duration::from_secs(1),
+            probe_timeout: Duration::from_millis(500),
+            indirect_checks: 3,
+            suspicion_timeout: Duration::from_secs(5),
+            sync_interval: Duration::from_secs(30),
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use crate::comp::config::{Config, RaftConfig};
+use crate::comp::log::Log;
+use crate::comp::membership::Handler;
+use crate::comp::raft::{is_not_leader, Raft, StreamLayer};
+use crate::comp::record::{ProduceRequest, Record, Server};
+use prost::Message;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio::time::{timeout, Duration};
+
+// Log replicado con raft. Las escrituras pasan por el lider y se aplican al
+// Log local de cada nodo cuando la mayoría las tiene; las lecturas son locales.
+pub struct DistributedLog {
+    log: Arc<RwLock<Log>>,
+    raft: Arc<Raft>,
+}
+
+impl DistributedLog {
+    pub async fn new(
+        data_dir: &str,
+        config: Config,
+        raft_config: RaftConfig,
+        layer: StreamLayer,
+    ) -> io::Result<Self> {
+        config.validate()?;
+        let dir = Path::new(data_dir).to_path_buf();
+
+        let log_dir = dir.join("log");
+        std::fs::create_dir_all(&log_dir)?;
+        let log = Arc::new(RwLock::new(
+            Log::new(log_dir.to_str().unwrap(), config.clone()).await?,
+        ));
+
+        let raft = Raft::new(&dir.join("raft"), config, raft_config, Arc::clone(&log)).await?;
+        raft.start(layer);
+
+        Ok(DistributedLog { log, raft })
+    }
+
+    pub async fn append(&self, record: Record) -> io::Result<u64> {
+        let req = ProduceRequest {
+            record: Some(record),
+            ..ProduceRequest::default()
+        };
+        self.raft.apply(req.encode_to_vec()).await
+    }
+
+    // el Log de este nodo, para el servicio Admin
+    pub fn local_log(&self) -> Arc<RwLock<Log>> {
+        Arc::clone(&self.log)
+    }
+
+    pub async fn read(&self, offset: u64) -> io::Result<Record> {
+        self.log.read().await.read(offset).await
+    }
+
+    pub async fn read_committed(&self, offset: u64) -> io::Result<Option<Record>> {
+        self.log.read().await.read_committed(offset).await
+    }
+
+    // addr es la dirección de raft del nodo y rpc_addr la de gRPC
+    pub async fn join(&self, id: &str, addr: &str, rpc_addr: &str) -> io::Result<()> {
+        self.raft.add_voter(id, addr, rpc_addr).await
+    }
+
+    pub async fn leave(&self, id: &str) -> io::Result<()> {
+        self.raft.remove_server(id).await
+    }
+
+    #[cfg(test)]
+    pub fn leader(&self) -> String {
+        self.raft.leader()
+    }
+
+    pub async fn get_servers(&self) -> Vec<Server> {
+        let leader = self.raft.leader();
+        self.raft
+            .servers()
+            .await
+            .into_iter()
+            .map(|s| Server {
+                is_leader: s.id == leader,
+                id: s.id,
+                rpc_addr: s.rpc_addr,
+            })
+            .collect()
+    }
+
+    pub async fn wait_for_leader(&self, wait: Duration) -> io::Result<()> {
+        let mut leader = self.raft.subscribe_leader();
+        timeout(wait, leader.wait_for(|id| !id.is_empty()))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "timed out waiting for leader"))?
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "raft stopped"))?;
+        Ok(())
+    }
+
+    pub async fn close(&self) -> io::Result<()> {
+        self.raft.shutdown().await?;
+        self.log.write().await.close().await
+    }
+}
+
+// Con el Mux raft y gRPC comparten puerto, así que el rpc_addr que anuncia la
+// membresía sirve para los dos. Solo el lider cambia la configuración, en los
+// demás nodos el evento se ignora.
+#[tonic::async_trait]
+impl Handler for DistributedLog {
+    async fn join(&self, name: &str, addr: &str) -> io::Result<()> {
+        match DistributedLog::join(self, name, addr, addr).await {
+            Err(e) if is_not_leader(&e) => Ok(()),
+            res => res,
+        }
+    }
+
+    async fn leave(&self, name: &str) -> io::Result<()> {
+        match DistributedLog::leave(self, name).await {
+            Err(e) if is_not_leader(&e) => Ok(()),
+            res => res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comp::config::test_config;
+
+    async fn node(id: usize, dir: &Path, bootstrap: bool) -> (DistributedLog, String) {
+        let layer = StreamLayer::bind("127.0.0.1:0").await.unwrap();
+        let addr = layer.local_addr().unwrap();
+        let config = test_config();
+        let raft_config = RaftConfig {
+            local_id: id.to_string(),
+            bind_addr: addr.clone(),
+            rpc_addr: format!("rpc-{}", id),
+            heartbeat_timeout: Duration::from_millis(20),
+            election_timeout: Duration::from_millis(100),
+            bootstrap,
+            ..RaftConfig::default()
+        };
+        let log = DistributedLog::new(
+            dir.join(id.to_string()).to_str().unwrap(),
+            config,
+            raft_config,
+            layer,
+        )
+        .await
+        .unwrap();
+        (log, addr)
+    }
+
+    async fn eventually_read(log: &DistributedLog, offset: u64) -> io::Result<Record> {
+        for _ in 0..100 {
+            if let Ok(record) = log.read(offset).await {
+                return Ok(record);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        log.read(offset).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn multiple_nodes() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut logs: Vec<DistributedLog> = vec![];
+        for i in 0..3 {
+            let (log, addr) = node(i, dir.path(), i == 0).await;
+            if i == 0 {
+                log.wait_for_leader(Duration::from_secs(3)).await.unwrap();
+            } else {
+                logs[0]
+                    .join(&i.to_string(), &addr, &format!("rpc-{}", i))
+                    .await
+                    .unwrap();
+            }
+            logs.push(log);
+        }
+        assert_eq!(logs[0].leader(), "0");
+
+        let servers = logs[0].get_servers().await;
+        assert_eq!(servers.len(), 3);
+        for (i, server) in servers.iter().enumerate() {
+            assert_eq!(server.id, i.to_string());
+            assert_eq!(server.rpc_addr, format!("rpc-{}", i));
+            assert_eq!(server.is_leader, i == 0);
+        }
+
+        let values: [&[u8]; 2] = [b"first", b"second"];
+        for value in values {
+            let off = logs[0]
+                .append(Record {
+                    value: value.to_vec(),
+                    ..Record::default()
+                })
+                .await
+                .unwrap();
+            for log in &logs {
+                let got = eventually_read(log, off).await.unwrap();
+                assert_eq!(got.value, value.to_vec());
+                assert_eq!(got.offset, off);
+            }
+        }
+
+        // solo el lider acepta escrituras
+        let err = logs[1].append(Record::default()).await.unwrap_err();
+        assert!(crate::comp::raft::is_not_leader(&err));
+
+        logs[0].leave("1").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let off = logs[0]
+            .append(Record {
+                value: b"third".to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(eventually_read(&logs[2], off).await.unwrap().value, b"third".to_vec());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(logs[1].read(off).await.is_err());
+
+        for log in &logs {
+            log.close().await.unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn new_leader_after_failure() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut logs: Vec<DistributedLog> = vec![];
+        for i in 0..3 {
+            let (log, addr) = node(i, dir.path(), i == 0).await;
+            if i == 0 {
+                log.wait_for_leader(Duration::from_secs(3)).await.unwrap();
+            } else {
+                logs[0]
+                    .join(&i.to_string(), &addr, &format!("rpc-{}", i))
+                    .await
+                    .unwrap();
+            }
+            logs.push(log);
+        }
+        let off = logs[0]
+            .append(Record {
+                value: b"antes".to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+
+        // apagamos al lider y los otros dos eligen a uno nuevo
+        let old = logs.remove(0);
+        old.close().await.unwrap();
+
+        let mut leader = None;
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            if let Some(l) = logs.iter().find(|l| l.leader() == l.raft.id()) {
+                leader = Some(l);
+                break;
+            }
+        }
+        let leader = leader.expect("no new leader was elected");
+
+        let next = leader
+            .append(Record {
+                value: b"despues".to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(next, off + 1);
+        for log in &logs {
+            assert_eq!(eventually_read(log, next).await.unwrap().value, b"despues".to_vec());
+        }
+
+        for log in &logs {
+            log.close().await.unwrap();
+        }
+    }
+
+    // el proceso se cae después de escribir en el Log y antes de guardar
+    // last_applied; al volver esa entrada no se aplica otra vez
+    #[tokio::test(flavor = "multi_thread")]
+    async fn restart_between_append_and_applied() {
+        use std::os::unix::fs::FileExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (log, _) = node(0, dir.path(), true).await;
+        log.wait_for_leader(Duration::from_secs(3)).await.unwrap();
+        for value in [b"uno", b"dos"] {
+            log.append(Record {
+                value: value.to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+        }
+        log.close().await.unwrap();
+
+        // `applied` como quedaba antes del segundo append
+        let applied = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(dir.path().join("0/raft/applied"))
+            .unwrap();
+        let mut buf = [0u8; 16];
+        applied.read_exact_at(&mut buf, 0).unwrap();
+        let last_applied = u64::from_be_bytes(buf[..8].try_into().unwrap());
+        assert_eq!(u64::from_be_bytes(buf[8..].try_into().unwrap()), 2);
+        buf[..8].copy_from_slice(&(last_applied - 1).to_be_bytes());
+        buf[8..].copy_from_slice(&1u64.to_be_bytes());
+        applied.write_all_at(&buf, 0).unwrap();
+
+        let (log, _) = node(0, dir.path(), true).await;
+        log.wait_for_leader(Duration::from_secs(3)).await.unwrap();
+        let off = log
+            .append(Record {
+                value: b"tres".to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(off, 2);
+        assert_eq!(log.read(1).await.unwrap().value, b"dos".to_vec());
+        log.close().await.unwrap();
+    }
+
+    // como corre el binario: raft y gRPC en el mismo puerto y los nodos se
+    // agregan solos cuando la membresía los descubre
+    #[tokio::test(flavor = "multi_thread")]
+    async fn join_through_membership() {
+        use crate::comp::config::MembershipConfig;
+        use crate::comp::membership::{Membership, RPC_ADDR_TAG};
+        use crate::comp::mux::Mux;
+        use std::collections::HashMap;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut logs: Vec<Arc<DistributedLog>> = vec![];
+        let mut members = vec![];
+        for i in 0..3 {
+            let mux = Mux::bind("127.0.0.1:0").await.unwrap();
+            let addr = mux.local_addr().unwrap();
+            let (layer, _grpc) = mux.split().unwrap();
+            let raft_config = RaftConfig {
+                local_id: i.to_string(),
+                bind_addr: addr.clone(),
+                rpc_addr: addr.clone(),
+                heartbeat_timeout: Duration::from_millis(20),
+                election_timeout: Duration::from_millis(100),
+                bootstrap: i == 0,
+                ..RaftConfig::default()
+            };
+            let config = test_config();
+            let log = Arc::new(
+                DistributedLog::new(
+                    dir.path().join(i.to_string()).to_str().unwrap(),
+                    config,
+                    raft_config,
+                    layer,
+                )
+                .await
+                .unwrap(),
+            );
+            if i == 0 {
+                log.wait_for_leader(Duration::from_secs(3)).await.unwrap();
+            }
+
+            let seeds = members
+                .first()
+                .map(|m: &Arc<Membership>| vec![m.addr().to_string()])
+                .unwrap_or_default();
+            let member_config = MembershipConfig {
+                node_name: i.to_string(),
+                bind_addr: "127.0.0.1:0".to_string(),
+                tags: HashMap::from([(RPC_ADDR_TAG.to_string(), addr)]),
+                start_join_addrs: seeds,
+                probe_interval: Duration::from_millis(50),
+                probe_timeout: Duration::from_millis(20),
+                sync_interval: Duration::from_millis(100),
+                ..MembershipConfig::default()
+            };
+            members.push(Membership::new(member_config, log.clone()).await.unwrap());
+            logs.push(log);
+        }
+
+        for _ in 0..100 {
+            if logs[0].get_servers().await.len() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(logs[0].get_servers().await.len(), 3);
+
+        let off = logs[0]
+            .append(Record {
+                value: b"por gossip".to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+        for log in &logs {
+            assert_eq!(eventually_read(log, off).await.unwrap().value, b"por gossip".to_vec());
+        }
+
+        for m in &members {
+            m.close();
+        }
+        for log in &logs {
+            log.close().await.unwrap();
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+// bit de las banderas del frame que dice que los datos van cifrados
+pub const ENCRYPTED: u8 = 0b1000;
+
+const NONCE_LEN: usize = 12;
+// lo que cada frame cifrado crece: el nonce y el tag de GCM
+pub const OVERHEAD: usize = NONCE_LEN + 16;
+
+// Las llaves con las que se cifran los .store. El keyfile tiene una llave por
+// línea, "<id> <32 bytes en base64>", y la última es la activa: los segmentos
+// nuevos se cifran con ella. Las anteriores se quedan para poder leer los
+// segmentos viejos, así se rotan sin reescribir nada.
+// El id de la llave de cada segmento va en su <base>.key. El Log de Go abre el
+// directorio (se salta los .key) pero no sabe descifrar, así que un log cifrado
+// solo lo lee log2.
+pub struct Keyring {
+    keys: HashMap<String, Aes256Gcm>,
+    active: String,
+}
+
+impl Keyring {
+    pub fn load(path: &str) -> io::Result<Self> {
+        Keyring::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(contents: &str) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut keys = HashMap::new();
+        let mut active = None;
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (id, key) = line.split_once(char::is_whitespace).ok_or_else(|| {
+                invalid(format!("keyfile line {}: expected \"<id> <key>\"", n + 1))
+            })?;
+            let key = STANDARD
+                .decode(key.trim())
+                .map_err(|e| invalid(format!("keyfile line {}: {}", n + 1, e)))?;
+            if key.len() != 32 {
+                return Err(invalid(format!(
+                    "keyfile line {}: key {} must be 32 bytes, got {}",
+                    n + 1,
+                    id,
+                    key.len()
+                )));
+            }
+            let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+            keys.insert(id.to_string(), cipher);
+            active = Some(id.to_string());
+        }
+
+        Ok(Keyring {
+            keys,
+            active: active.ok_or_else(|| invalid("keyfile has no keys".to_string()))?,
+        })
+    }
+
+    pub fn active_id(&self) -> &str {
+        &self.active
+    }
+
+    pub fn contains(&self, key_id: &str) -> bool {
+        self.keys.contains_key(key_id)
+    }
+
+    // regresa nonce || texto cifrado; aad va autenticado pero no se guarda
+    pub fn encrypt(&self, key_id: &str, data: &[u8], aad: &[u8]) -> io::Result<Vec<u8>> {
+        let cipher = self.cipher(key_id)?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = cipher
+            .encrypt(&nonce, Payload { msg: data, aad })
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        let mut out = nonce.to_vec();
+        out.extend(sealed);
+        Ok(out)
+    }
+
+    pub fn decrypt(&self, key_id: &str, data: &[u8], aad: &[u8]) -> io::Result<Vec<u8>> {
+        let cipher = self.cipher(key_id)?;
+        if data.len() < NONCE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "encrypted frame is too short",
+            ));
+        }
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad })
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("could not decrypt frame with key {}", key_id),
+                )
+            })
+    }
+
+    fn cipher(&self, key_id: &str) -> io::Result<&Aes256Gcm> {
+        self.keys.get(key_id).ok_or_else(|| missing_key(key_id))
+    }
+}
+
+// para que la config se pueda imprimir sin sacar las llaves
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ids: Vec<&String> = self.keys.keys().collect();
+        ids.sort();
+        f.debug_struct("Keyring")
+            .field("keys", &ids)
+            .field("active", &self.active)
+            .finish()
+    }
+}
+
+pub fn missing_key(key_id: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("encryption key {:?} is not in the keyfile", key_id),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYFILE: &str = "# llaves de prueba
+k1 AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=
+k2 HxwdHh8QERITFBUWFxgZGhsMDQ4PAAECAwQFBgcICQo=
+";
+
+    #[test]
+    fn encrypt_decrypt() {
+        let keyring = Keyring::parse(KEYFILE).unwrap();
+        assert_eq!(keyring.active_id(), "k2");
+        assert!(keyring.contains("k1"));
+
+        let sealed = keyring.encrypt("k1", b"secreto", b"0").unwrap();
+        assert!(!sealed.windows(7).any(|w| w == b"secreto"));
+        assert_eq!(keyring.decrypt("k1", &sealed, b"0").unwrap(), b"secreto");
+        // otra llave u otro aad no abren el frame
+        assert!(keyring.decrypt("k2", &sealed, b"0").is_err());
+        assert!(keyring.decrypt("k1", &sealed, b"1").is_err());
+
+        let err = keyring.decrypt("k9", &sealed, b"0").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(!format!("{:?}", keyring).contains("AAEC"));
+    }
+
+    #[test]
+    fn invalid_keyfiles() {
+        for bad in [
+            "",
+            "# nada\n",
+            "k1\n",
+            "k1 no-es-base64!\n",
+            "k1 AAECAw==\n",
+        ] {
+            let err = Keyring::parse(bad).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use crate::comp::record::Record;
+use serde_json::Value;
+use std::io;
+
+// Los filtros de ConsumeStream. Son condiciones unidas con &&:
+//
+//   header.<nombre> == "texto"    el header existe y vale eso
+//   key ^= "texto" | 0x1f8b       la key empieza con esos bytes
+//   value ^= "texto" | 0x1f8b     el value empieza con esos bytes
+//   json.<campo>.<campo> == 42    el value es JSON y el campo vale eso; el
+//                                 valor es un número, true, false, null o
+//                                 un texto entre comillas. En los arreglos
+//                                 el campo es el índice.
+//
+// Los textos van entre comillas dobles, con \" y \\ para escaparlas.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Header(String, String),
+    KeyPrefix(Vec<u8>),
+    ValuePrefix(Vec<u8>),
+    Json(Vec<String>, Value),
+}
+
+enum Operand {
+    Text(String),
+    Hex(Vec<u8>),
+    // números, true, false y null
+    Bare(String),
+}
+
+impl Filter {
+    pub fn parse(expr: &str) -> io::Result<Self> {
+        let mut terms = Vec::new();
+        let mut rest = expr.trim();
+        loop {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || c == '=' || c == '^')
+                .unwrap_or(rest.len());
+            let (field, r) = rest.split_at(end);
+            let r = r.trim_start();
+            let (op, r) = match r.get(..2) {
+                Some(op @ ("==" | "^=")) => (op, &r[2..]),
+                _ => return Err(invalid(format!("expected == or ^= after {:?}", field))),
+            };
+            let (operand, r) = operand(r.trim_start())?;
+            terms.push(term(field, op, operand)?);
+
+            rest = r.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            rest = rest
+                .strip_prefix("&&")
+                .ok_or_else(|| invalid(format!("expected && before {:?}", rest)))?
+                .trim_start();
+        }
+        Ok(Filter { terms })
+    }
+
+    pub fn matches(&self, record: &Record) -> bool {
+        // el JSON se lee una vez aunque haya varios campos
+        let mut json = None;
+        self.terms.iter().all(|term| match term {
+            Term::Header(name, want) => record.headers.get(name) == Some(want),
+            Term::KeyPrefix(prefix) => record.key.starts_with(prefix),
+            Term::ValuePrefix(prefix) => record.value.starts_with(prefix),
+            Term::Json(path, want) => {
+                let value = json.get_or_insert_with(|| {
+                    serde_json::from_slice::<Value>(&record.value).unwrap_or(Value::Null)
+                });
+                lookup(value, path).is_some_and(|got| json_eq(got, want))
+            }
+        })
+    }
+}
+
+fn term(field: &str, op: &str, operand: Operand) -> io::Result<Term> {
+    let bytes = |operand| match operand {
+        Operand::Text(text) => Ok(text.into_bytes()),
+        Operand::Hex(bytes) => Ok(bytes),
+        Operand::Bare(bare) => Err(invalid(format!("{} needs quotes or 0x", bare))),
+    };
+    match (field.split_once('.'), op) {
+        (None, "^=") if field == "key" => Ok(Term::KeyPrefix(bytes(operand)?)),
+        (None, "^=") if field == "value" => Ok(Term::ValuePrefix(bytes(operand)?)),
+        (Some(("header", name)), "==") if !name.is_empty() => match operand {
+            Operand::Text(text) => Ok(Term::Header(name.to_string(), text)),
+            _ => Err(invalid(format!(
+                "{} must be compared to a quoted text",
+                field
+            ))),
+        },
+        (Some(("json", path)), "==") if !path.is_empty() => {
+            let want = match operand {
+                Operand::Text(text) => Value::String(text),
+                Operand::Bare(bare) => match serde_json::from_str::<Value>(&bare) {
+                    Ok(value) if !value.is_object() && !value.is_array() => value,
+                    _ => return Err(invalid(format!("{} is not a JSON value", bare))),
+                },
+                Operand::Hex(_) => {
+                    return Err(invalid(format!("{} can't be compared to bytes", field)))
+                }
+            };
+            Ok(Term::Json(
+                path.split('.').map(String::from).collect(),
+                want,
+            ))
+        }
+        _ => Err(invalid(format!(
+            "unsupported condition {} {}, expected header.<name> ==, key ^=, value ^= \
+             or json.<path> ==",
+            field, op
+        ))),
+    }
+}
+
+// regresa el operando y lo que sigue
+fn operand(s: &str) -> io::Result<(Operand, &str)> {
+    if let Some(s) = s.strip_prefix('"') {
+        let mut text = String::new();
+        let mut chars = s.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Operand::Text(text), &s[i + 1..])),
+                '\\' => match chars.next() {
+                    Some((_, c @ ('"' | '\\'))) => text.push(c),
+                    _ => return Err(invalid("only \\\" and \\\\ can be escaped")),
+                },
+                c => text.push(c),
+            }
+        }
+        return Err(invalid("unterminated text"));
+    }
+    let end = s
+        .find(|c: char| c.is_whitespace() || c == '&')
+        .unwrap_or(s.len());
+    let (word, rest) = s.split_at(end);
+    if word.is_empty() {
+        return Err(invalid("missing value"));
+    }
+    match word.strip_prefix("0x") {
+        Some(hex) => Ok((Operand::Hex(from_hex(hex)?), rest)),
+        None => Ok((Operand::Bare(word.to_string()), rest)),
+    }
+}
+
+fn from_hex(hex: &str) -> io::Result<Vec<u8>> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return Err(invalid(format!("0x{} has an odd number of digits", hex)));
+    }
+    // antes de cortar el &str: un carácter de varios bytes lo haría tronar, y
+    // from_str_radix acepta un + al inicio
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid(format!("0x{} is not hexadecimal", hex)));
+    }
+    Ok((0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+fn lookup<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, field| match value {
+        Value::Object(map) => map.get(field),
+        Value::Array(items) => items.get(field.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+// 42 y 42.0 son el mismo número
+fn json_eq(got: &Value, want: &Value) -> bool {
+    match (got, want) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        _ => got == want,
+    }
+}
+
+fn invalid(msg: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid filter: {}", msg),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(key: &[u8], value: &[u8], headers: &[(&str, &str)]) -> Record {
+        Record {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..Record::default()
+        }
+    }
+
+    #[test]
+    fn filters() {
+        let matches = |expr: &str, record: &Record| Filter::parse(expr).unwrap().matches(record);
+
+        let pedido = record(
+            b"cliente-7",
+            br#"{"cliente": {"id": 42, "nombre": "Ana \"la\" buena"}, "items": [1, 2]}"#,
+            &[("tipo", "pedido"), ("origen", "web")],
+        );
+        assert!(matches(r#"header.tipo == "pedido""#, &pedido));
+        assert!(!matches(r#"header.tipo == "pago""#, &pedido));
+        assert!(!matches(r#"header.falta == """#, &pedido));
+        assert!(matches(r#"key ^= "cliente-""#, &pedido));
+        assert!(matches("key^=0x636c", &pedido));
+        assert!(matches(r#"value ^= "{""#, &pedido));
+        assert!(matches("json.cliente.id == 42", &pedido));
+        assert!(matches("json.cliente.id == 42.0", &pedido));
+        assert!(matches(
+            r#"json.cliente.nombre == "Ana \"la\" buena""#,
+            &pedido
+        ));
+        assert!(matches("json.items.1 == 2", &pedido));
+        assert!(!matches("json.items.2 == 2", &pedido));
+        assert!(!matches(r#"json.cliente.id == "42""#, &pedido));
+        assert!(matches(
+            r#"header.origen == "web" && key ^= "cliente" && json.cliente.id == 42"#,
+            &pedido
+        ));
+        assert!(!matches(
+            r#"header.origen == "web" && json.cliente.id == 7"#,
+            &pedido
+        ));
+
+        // un value que no es JSON no cumple ninguna condición json
+        let crudo = record(b"", b"\x1f\x8b\x08", &[]);
+        assert!(matches("value ^= 0x1f8b", &crudo));
+        assert!(!matches("json.a == null", &crudo));
+
+        for expr in [
+            "",
+            "key == \"a\"",
+            "value ^= texto",
+            "header.tipo == 1",
+            "json.a == {}",
+            "json.a == 0x00",
+            "value ^= 0x1",
+            "value ^= 0x1g",
+            "value ^= 0xaé1",
+            "value ^= 0xéé",
+            "value ^= 0x+a",
+            "key ^= \"abierto",
+            "key ^= \"a\" header.b == \"c\"",
+            "offset == 3",
+        ] {
+            let err = Filter::parse(expr).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{:?}", expr);
+        }
+    }
+}
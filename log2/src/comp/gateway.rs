@@ -0,0 +1,452 @@
+use crate::comp::metrics;
+use crate::comp::quota::RETRY_AFTER;
+use crate::comp::record::log_server::Log as _;
+use crate::comp::record::{ConsumeRequest, ConsumeResponse, ProduceRequest, Record};
+use crate::comp::server::LogService;
+use crate::comp::trace::TraceLayer;
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Code, Request, Status};
+
+// HTTP/JSON para las herramientas que no hablan gRPC. Por debajo es el mismo
+// LogService, así que topics, deduplicación y read-committed funcionan igual.
+//
+//   POST /records              produce; el cuerpo es el value tal cual, o un
+//                              JSON {"value": base64, "key": base64,
+//                              "headers": {...}} si el Content-Type es
+//                              application/json
+//   GET  /records/{offset}     consume; JSON con value en base64, o el value
+//                              crudo con ?encoding=raw o Accept: application/octet-stream
+//   GET  /records?from=N       server-sent events desde N, un evento por registro;
+//                              con &filter=... solo los que lo cumplen (ver comp/filter.rs)
+//   GET  /metrics              métricas en el formato de texto de Prometheus
+//
+// topic, partition, group y read_committed van en el query string.
+pub fn router(service: LogService) -> Router {
+    Router::new()
+        .route("/records", post(produce).get(consume_stream))
+        .route("/records/:offset", get(consume))
+        .route("/metrics", get(metrics))
+        .layer(TraceLayer)
+        .with_state(service)
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Params {
+    topic: String,
+    partition: u32,
+    group: String,
+    read_committed: bool,
+    // base64 (el default) o raw
+    encoding: String,
+    // key como texto cuando el cuerpo es el value crudo
+    key: String,
+    from: u64,
+    filter: String,
+}
+
+#[derive(Deserialize)]
+struct JsonRecord {
+    value: String,
+    #[serde(default)]
+    key: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+}
+
+async fn produce(
+    State(service): State<LogService>,
+    Query(params): Query<Params>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    let record = if is_json {
+        let decoded = serde_json::from_slice::<JsonRecord>(&body)
+            .map_err(|e| e.to_string())
+            .and_then(|r| {
+                let value = STANDARD.decode(r.value).map_err(|e| e.to_string())?;
+                let key = STANDARD.decode(r.key).map_err(|e| e.to_string())?;
+                Ok(Record {
+                    value,
+                    key,
+                    headers: r.headers,
+                    ..Record::default()
+                })
+            });
+        match decoded {
+            Ok(record) => record,
+            Err(e) => return error(StatusCode::BAD_REQUEST, &e),
+        }
+    } else {
+        Record {
+            value: body.to_vec(),
+            key: params.key.into_bytes(),
+            ..Record::default()
+        }
+    };
+
+    let req = ProduceRequest {
+        record: Some(record),
+        topic: params.topic,
+        partition: params.partition,
+    };
+    match service.produce(Request::new(req)).await {
+        Ok(res) => {
+            let res = res.into_inner();
+            Json(json!({"offset": res.offset, "partition": res.partition})).into_response()
+        }
+        Err(status) => status_response(status),
+    }
+}
+
+async fn consume(
+    State(service): State<LogService>,
+    Path(offset): Path<u64>,
+    Query(params): Query<Params>,
+    headers: HeaderMap,
+) -> Response {
+    let req = ConsumeRequest {
+        offset,
+        topic: params.topic,
+        partition: params.partition,
+        group: params.group,
+        read_committed: params.read_committed,
+        filter: String::new(),
+    };
+    let record = match service.consume(Request::new(req)).await {
+        Ok(res) => res.into_inner().record.unwrap_or_default(),
+        Err(status) => return status_response(status),
+    };
+
+    let wants_raw = params.encoding == "raw"
+        || headers
+            .get(header::ACCEPT)
+            .is_some_and(|v| v == "application/octet-stream");
+    if wants_raw {
+        let mut res = record.value.into_response();
+        res.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/octet-stream"),
+        );
+        res.headers_mut()
+            .insert("x-offset", HeaderValue::from(record.offset));
+        return res;
+    }
+    Json(to_json(&record)).into_response()
+}
+
+// Con Last-Event-ID (lo manda solo el EventSource al reconectarse) sigue
+// después del último que recibió.
+async fn consume_stream(
+    State(service): State<LogService>,
+    Query(params): Query<Params>,
+    headers: HeaderMap,
+) -> Response {
+    let last = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    let req = ConsumeRequest {
+        offset: last.map(|o| o + 1).unwrap_or(params.from),
+        topic: params.topic,
+        partition: params.partition,
+        group: if last.is_some() {
+            String::new()
+        } else {
+            params.group
+        },
+        read_committed: params.read_committed,
+        filter: params.filter,
+    };
+    let stream = match service.consume_stream(Request::new(req)).await {
+        Ok(res) => res.into_inner(),
+        Err(status) => return status_response(status),
+    };
+    Sse::new(events(stream))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+// después de un error ConsumeStream ya no manda nada, ese es el último evento
+fn events<S>(stream: S) -> impl Stream<Item = Result<Event, Infallible>>
+where
+    S: Stream<Item = Result<ConsumeResponse, Status>>,
+{
+    stream.map(|res| {
+        Ok(match res {
+            Ok(ConsumeResponse {
+                record: Some(record),
+                ..
+            }) => Event::default()
+                .id(record.offset.to_string())
+                .data(to_json(&record).to_string()),
+            // Lo que se saltó el filtro: un evento sin datos no llega a la
+            // página, pero el EventSource sí se queda con su id para reconectarse.
+            Ok(res) => Event::default().id(res.next_offset.saturating_sub(1).to_string()),
+            Err(status) => Event::default().event("error").data(status.message()),
+        })
+    })
+}
+
+async fn metrics() -> Response {
+    let mut res = metrics::gather().into_response();
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    res
+}
+
+fn to_json(record: &Record) -> serde_json::Value {
+    let mut json = json!({
+        "offset": record.offset,
+        "value": STANDARD.encode(&record.value),
+        "key": STANDARD.encode(&record.key),
+    });
+    if !record.headers.is_empty() {
+        json["headers"] = json!(record.headers);
+    }
+    json
+}
+
+fn status_response(status: Status) -> Response {
+    let code = match status.code() {
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::InvalidArgument => StatusCode::BAD_REQUEST,
+        Code::AlreadyExists | Code::FailedPrecondition => StatusCode::CONFLICT,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let mut res = error(code, status.message());
+    // Retry-After va en segundos, redondeado hacia arriba
+    let retry_ms = status.metadata().get(RETRY_AFTER);
+    if let Some(ms) = retry_ms.and_then(|v| v.to_str().ok()?.parse::<u64>().ok()) {
+        res.headers_mut()
+            .insert(header::RETRY_AFTER, ms.div_ceil(1000).into());
+    }
+    res
+}
+
+fn error(code: StatusCode, msg: &str) -> Response {
+    (code, Json(json!({ "error": msg }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comp::config::test_config;
+    use crate::comp::log::Log;
+    use crate::comp::quota::{Limit, Quotas};
+    use crate::comp::server::{new_log_service, ServerConfig};
+    use axum::body::Body;
+    use axum::http::Request as HttpRequest;
+    use hyper::body::HttpBody;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+    use tower::ServiceExt;
+
+    async fn setup(dir: &tempfile::TempDir) -> Router {
+        let log = Log::new(dir.path().to_str().unwrap(), test_config())
+            .await
+            .unwrap();
+        let commit_log = Arc::new(RwLock::new(log));
+        router(new_log_service(ServerConfig::new(commit_log)))
+    }
+
+    async fn call(app: &Router, req: HttpRequest<Body>) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let res = app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let headers = res.headers().clone();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, headers, body.to_vec())
+    }
+
+    fn json_body(body: &[u8]) -> serde_json::Value {
+        serde_json::from_slice(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn produce_consume() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = setup(&dir).await;
+
+        // el cuerpo crudo es el value
+        let req = HttpRequest::post("/records")
+            .body(Body::from(&b"crudo \xff"[..]))
+            .unwrap();
+        let (status, _, body) = call(&app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json_body(&body), json!({"offset": 0, "partition": 0}));
+
+        // o un JSON con el value en base64
+        let req = HttpRequest::post("/records")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"value": "aG9sYQ==", "key": "azE="}"#))
+            .unwrap();
+        let (_, _, body) = call(&app, req).await;
+        assert_eq!(json_body(&body)["offset"], 1);
+
+        let req = HttpRequest::get("/records/1").body(Body::empty()).unwrap();
+        let (status, _, body) = call(&app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            json_body(&body),
+            json!({"offset": 1, "value": "aG9sYQ==", "key": "azE="})
+        );
+
+        let req = HttpRequest::get("/records/0?encoding=raw")
+            .body(Body::empty())
+            .unwrap();
+        let (_, headers, body) = call(&app, req).await;
+        assert_eq!(body, b"crudo \xff");
+        assert_eq!(headers["x-offset"], "0");
+
+        let req = HttpRequest::get("/records/0")
+            .header(header::ACCEPT, "application/octet-stream")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(call(&app, req).await.2, b"crudo \xff");
+
+        // los errores de gRPC se vuelven códigos HTTP
+        let req = HttpRequest::get("/records/9").body(Body::empty()).unwrap();
+        let (status, _, body) = call(&app, req).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(json_body(&body)["error"]
+            .as_str()
+            .unwrap()
+            .contains("out of range"));
+
+        let req = HttpRequest::post("/records")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"value": "no es base64!"}"#))
+            .unwrap();
+        assert_eq!(call(&app, req).await.0, StatusCode::BAD_REQUEST);
+
+        let req = HttpRequest::post("/records?topic=pedidos")
+            .body(Body::from("x"))
+            .unwrap();
+        assert_eq!(call(&app, req).await.0, StatusCode::NOT_IMPLEMENTED);
+
+        let req = HttpRequest::get("/metrics").body(Body::empty()).unwrap();
+        let (status, headers, body) = call(&app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(headers[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        assert!(String::from_utf8(body)
+            .unwrap()
+            .contains("log_appends_total"));
+    }
+
+    #[tokio::test]
+    async fn over_quota() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Log::new(dir.path().to_str().unwrap(), test_config())
+            .await
+            .unwrap();
+        let limit = Limit {
+            requests_per_sec: 1,
+            bytes_per_sec: 0,
+        };
+        let app = router(new_log_service(ServerConfig {
+            quotas: Some(Arc::new(Quotas::new(limit, []))),
+            ..ServerConfig::new(Arc::new(RwLock::new(log)))
+        }));
+
+        let post = || HttpRequest::post("/records").body(Body::from("x")).unwrap();
+        assert_eq!(call(&app, post()).await.0, StatusCode::OK);
+        let (status, headers, body) = call(&app, post()).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(headers[header::RETRY_AFTER], "1");
+        assert!(json_body(&body)["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("anonymous is over its quota"));
+    }
+
+    #[tokio::test]
+    async fn server_sent_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = setup(&dir).await;
+        for value in ["uno", "dos", "tres"] {
+            let req = HttpRequest::post("/records")
+                .body(Body::from(value))
+                .unwrap();
+            call(&app, req).await;
+        }
+
+        // el stream no se acaba, leemos hasta tener los eventos que esperamos
+        async fn read_events(app: &Router, req: HttpRequest<Body>, n: usize) -> String {
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.headers()[header::CONTENT_TYPE], "text/event-stream");
+            let mut body = res.into_body();
+            let mut text = String::new();
+            while text.matches("\n\n").count() < n {
+                let chunk = body.data().await.unwrap().unwrap();
+                text.push_str(std::str::from_utf8(&chunk).unwrap());
+            }
+            text
+        }
+
+        let req = HttpRequest::get("/records?from=1")
+            .body(Body::empty())
+            .unwrap();
+        let text = read_events(&app, req, 2).await;
+        assert!(text.starts_with("id:1\ndata:{"), "{}", text);
+        assert!(text.contains("\"value\":\"ZG9z\""), "{}", text);
+        assert!(text.contains("id:1\n"), "{}", text);
+        assert!(text.contains("id:2\n"), "{}", text);
+
+        // al reconectarse sigue después de Last-Event-ID
+        let req = HttpRequest::get("/records?from=0")
+            .header("last-event-id", "1")
+            .body(Body::empty())
+            .unwrap();
+        let text = read_events(&app, req, 1).await;
+        assert!(text.contains("id:2\n"), "{}", text);
+        assert!(!text.contains("id:1\n"), "{}", text);
+
+        let req = HttpRequest::post("/records")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"value": "Y3VhdHJv", "headers": {"n": "4"}}"#,
+            ))
+            .unwrap();
+        assert_eq!(json_body(&call(&app, req).await.2)["offset"], 3);
+
+        // con filtro solo llega el que tiene el header
+        let req = HttpRequest::get("/records?filter=header.n%20%3D%3D%20%224%22")
+            .body(Body::empty())
+            .unwrap();
+        let text = read_events(&app, req, 1).await;
+        assert!(text.starts_with("id:3\ndata:{"), "{}", text);
+        assert!(text.contains(r#""headers":{"n":"4"}"#), "{}", text);
+
+        // si ninguno lo cumple solo llega el id para reconectarse
+        let req = HttpRequest::get("/records?filter=header.n%20%3D%3D%20%225%22")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(read_events(&app, req, 1).await, "id:3\n\n");
+    }
+}
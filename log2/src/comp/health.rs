@@ -0,0 +1,225 @@
+use crate::comp::record::log_server::LogServer;
+use crate::comp::server::LogService;
+use std::convert::Infallible;
+use std::sync::{Arc, OnceLock};
+use tonic::body::BoxBody;
+use tonic::codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError};
+use tonic::server::NamedService;
+use tonic::Status;
+use tonic_health::pb::health_server::{Health, HealthServer};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tonic_reflection::server::{ServerReflection, ServerReflectionServer};
+
+// lo genera build.rs a partir de src/log.proto
+const FILE_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/record_descriptor.bin"));
+
+// el nombre del servicio Log en grpc.health.v1, el mismo del .proto
+pub const LOG_SERVICE: &str = <LogServer<LogService> as NamedService>::NAME;
+
+// grpc.health.v1.Health para los balanceadores. Empieza en NOT_SERVING, tanto
+// el servidor completo ("") como record.Log; main avisa con set_serving cuando
+// los logs ya se recuperaron y vuelve a NOT_SERVING al apagarse.
+pub async fn new_health_server() -> (HealthReporter, HealthServer<impl Health>) {
+    let (mut reporter, server) = tonic_health::server::health_reporter();
+    set_serving(&mut reporter, false).await;
+    (reporter, server)
+}
+
+pub async fn set_serving(reporter: &mut HealthReporter, serving: bool) {
+    let status = if serving {
+        ServingStatus::Serving
+    } else {
+        ServingStatus::NotServing
+    };
+    for name in ["", LOG_SERVICE] {
+        reporter.set_service_status(name, status).await;
+    }
+}
+
+// server reflection para grpcurl y compañía: el Log y el health check
+pub fn new_reflection_server() -> ServerReflectionServer<impl ServerReflection> {
+    tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()
+        // los descriptores vienen compilados, si no cargan es un bug
+        .expect("invalid file descriptor set")
+}
+
+// Un servicio antes de que existan los logs: se registra en el Server desde
+// el principio, así health y reflection contestan mientras setup recupera los
+// segmentos, y las llamadas reciben UNAVAILABLE hasta que main le pasa el
+// servidor con set. Lo usan Log y Admin.
+pub struct Deferred<S> {
+    inner: Arc<OnceLock<S>>,
+}
+
+pub type DeferredLog = Deferred<LogServer<LogService>>;
+
+impl<S> Deferred<S> {
+    pub fn set(&self, server: S) {
+        let _ = self.inner.set(server);
+    }
+}
+
+// a mano porque el derive pediría S: Clone + Default
+impl<S> Clone for Deferred<S> {
+    fn clone(&self) -> Self {
+        Deferred {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<S> Default for Deferred<S> {
+    fn default() -> Self {
+        Deferred {
+            inner: Arc::default(),
+        }
+    }
+}
+
+impl<S: NamedService> NamedService for Deferred<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, B> Service<http::Request<B>> for Deferred<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>, Error = Infallible>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    S::Future: Send + 'static,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // los servidores que genera tonic siempre están listos
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        match self.inner.get() {
+            Some(server) => {
+                let mut server = server.clone();
+                Box::pin(async move { server.call(req).await })
+            }
+            None => {
+                Box::pin(async { Ok(Status::unavailable("the log is still recovering").to_http()) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comp::config::test_config;
+    use crate::comp::log::Log;
+    use crate::comp::record::log_client::LogClient;
+    use crate::comp::record::{ProduceRequest, Record};
+    use crate::comp::server::{new_grpc_server, CommitLog, ServerConfig};
+    use tokio::net::TcpListener;
+    use tokio::sync::RwLock;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::{Endpoint, Server};
+    use tonic_health::pb::health_check_response::ServingStatus as CheckStatus;
+    use tonic_health::pb::health_client::HealthClient;
+    use tonic_health::pb::HealthCheckRequest;
+    use tonic_reflection::pb::server_reflection_client::ServerReflectionClient;
+    use tonic_reflection::pb::server_reflection_request::MessageRequest;
+    use tonic_reflection::pb::server_reflection_response::MessageResponse;
+    use tonic_reflection::pb::ServerReflectionRequest;
+
+    #[tokio::test]
+    async fn health_and_reflection() {
+        let (mut reporter, health) = new_health_server().await;
+        let deferred = DeferredLog::default();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            Server::builder()
+                .add_service(health)
+                .add_service(new_reflection_server())
+                .add_service(deferred.clone())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let channel = Endpoint::from_shared(addr)
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut health = HealthClient::new(channel.clone());
+        let mut log = LogClient::new(channel.clone());
+        let check = |service: &str| HealthCheckRequest {
+            service: service.to_string(),
+        };
+        let produce = ProduceRequest {
+            record: Some(Record {
+                value: b"listo".to_vec(),
+                ..Record::default()
+            }),
+            ..ProduceRequest::default()
+        };
+
+        // mientras se recupera el log
+        for service in ["", LOG_SERVICE] {
+            let res = health.check(check(service)).await.unwrap().into_inner();
+            assert_eq!(res.status(), CheckStatus::NotServing);
+        }
+        let err = log.produce(produce.clone()).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unavailable);
+
+        let dir = tempfile::tempdir().unwrap();
+        let commit_log: Arc<dyn CommitLog> = Arc::new(RwLock::new(
+            Log::new(dir.path().to_str().unwrap(), test_config())
+                .await
+                .unwrap(),
+        ));
+        deferred.set(new_grpc_server(ServerConfig::new(commit_log)));
+        set_serving(&mut reporter, true).await;
+        let res = health.check(check(LOG_SERVICE)).await.unwrap().into_inner();
+        assert_eq!(res.status(), CheckStatus::Serving);
+        assert_eq!(log.produce(produce).await.unwrap().into_inner().offset, 0);
+
+        // al apagarse
+        set_serving(&mut reporter, false).await;
+        let res = health.check(check("")).await.unwrap().into_inner();
+        assert_eq!(res.status(), CheckStatus::NotServing);
+
+        let mut reflection = ServerReflectionClient::new(channel);
+        let req = ServerReflectionRequest {
+            host: String::new(),
+            message_request: Some(MessageRequest::ListServices(String::new())),
+        };
+        let mut res = reflection
+            .server_reflection_info(tokio_stream::iter([req]))
+            .await
+            .unwrap()
+            .into_inner();
+        let Some(MessageResponse::ListServicesResponse(list)) =
+            res.message().await.unwrap().unwrap().message_response
+        else {
+            panic!("expected a list of services");
+        };
+        let mut names: Vec<String> = list.service.into_iter().map(|s| s.name).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "grpc.health.v1.Health",
+                "grpc.reflection.v1alpha.ServerReflection",
+                "record.Admin",
+                "record.Log"
+            ]
+        );
+    }
+}
@@ -1,34 +1,74 @@
 use crate::comp::config::Config;
 
-use memmap2::{MmapMut, MmapOptions};
-use std::fs::{File, OpenOptions};
-use std::io::{self, Seek, SeekFrom, Write};
-use std::path::Path;
+use crate::comp::storage::{Mapping, StorageFile};
+use std::io;
 
+// mismo formato que Log/index.go: offset u32 y posición u64 en big endian
 const OFF_WIDTH: u64 = 4;
 const POS_WIDTH: u64 = 8;
-const ENT_WIDTH: u64 = OFF_WIDTH + POS_WIDTH;
+pub const ENT_WIDTH: u64 = OFF_WIDTH + POS_WIDTH;
 
 #[derive(Debug)]
 pub struct Index {
-    pub mmap: MmapMut,
+    pub file: Box<dyn StorageFile>,
+    // con DirStorage es un mmap del archivo, igual que en Go
+    pub mmap: Box<dyn Mapping>,
     pub size: u64,
     pub path: String,
 }
 
 impl Index {
-    pub fn new(file: &File, config: &Config, path: String) -> io::Result<Index> {
-        let size = file.metadata()?.len();
-
-        file.set_len(config.segment.max_index_bytes)?;
+    pub async fn new(
+        file: Box<dyn StorageFile>,
+        config: &Config,
+        path: String,
+    ) -> io::Result<Index> {
+        let size = file.size().await?;
+
+        file.set_len(config.segment.max_index_bytes).await?;
+
+        let mmap = file.map(config.segment.max_index_bytes as usize).await?;
+
+        // el index de Go nunca se trunca al cerrar, entonces el archivo puede
+        // venir con ceros al final; contamos solo las entradas que son validas
+        let size = Index::used_len(&mmap, size.min(config.segment.max_index_bytes));
+
+        Ok(Index {
+            file,
+            mmap,
+            size,
+            path,
+        })
+    }
 
-        let mmap = unsafe {
-            MmapOptions::new()
-                .len(config.segment.max_index_bytes as usize)
-                .map_mut(file)?
-        };
+    // Una entrada es valida si su offset relativo coincide con su posición en
+    // el index. La primera entrada siempre apunta a la posición 0 del store,
+    // las demás nunca.
+    fn used_len(mmap: &[u8], size: u64) -> u64 {
+        let mut used = 0;
+        while used + ENT_WIDTH <= size {
+            let (off, pos) = Index::entry(mmap, used);
+            let i = used / ENT_WIDTH;
+            if off as u64 != i || (i > 0 && pos == 0) {
+                break;
+            }
+            used += ENT_WIDTH;
+        }
+        used
+    }
 
-        Ok(Index { mmap, size, path })
+    fn entry(mmap: &[u8], pos: u64) -> (u32, u64) {
+        let offset = u32::from_be_bytes(
+            mmap[pos as usize..(pos + OFF_WIDTH) as usize]
+                .try_into()
+                .unwrap(),
+        );
+        let position = u64::from_be_bytes(
+            mmap[(pos + OFF_WIDTH) as usize..(pos + ENT_WIDTH) as usize]
+                .try_into()
+                .unwrap(),
+        );
+        (offset, position)
     }
 
     pub fn read(&self, idx: i64) -> io::Result<(u32, u64)> {
@@ -52,19 +92,7 @@ impl Index {
             ));
         }
 
-        let pos = out as u64 * ENT_WIDTH;
-        let offset = u32::from_le_bytes(
-            self.mmap[pos as usize..(pos + OFF_WIDTH) as usize]
-                .try_into()
-                .unwrap(),
-        );
-        let position = u64::from_le_bytes(
-            self.mmap[(pos + OFF_WIDTH) as usize..(pos + ENT_WIDTH) as usize]
-                .try_into()
-                .unwrap(),
-        );
-
-        Ok((offset, position))
+        Ok(Index::entry(&self.mmap, out as u64 * ENT_WIDTH))
     }
 
     pub fn write(&mut self, off: u32, pos: u64) -> io::Result<()> {
@@ -78,24 +106,23 @@ impl Index {
         }
 
         self.mmap[self.size as usize..(self.size + OFF_WIDTH) as usize]
-            .copy_from_slice(&off.to_le_bytes());
+            .copy_from_slice(&off.to_be_bytes());
         self.mmap[(self.size + OFF_WIDTH) as usize..(self.size + ENT_WIDTH) as usize]
-            .copy_from_slice(&pos.to_le_bytes());
+            .copy_from_slice(&pos.to_be_bytes());
 
         self.size += ENT_WIDTH;
         Ok(())
     }
 
-    pub fn name(&self) -> io::Result<String> {
-        Ok(self.path.clone())
+    pub async fn close(&mut self) -> io::Result<()> {
+        self.mmap.flush()?;
+        self.file.set_len(self.size).await?;
+        self.path = "".to_string();
+        self.file.sync().await
     }
 
-    pub fn close(&mut self, file: &mut File) -> io::Result<()> {
-        self.mmap.flush_async()?;
-        file.set_len(self.size)?;
-        file.sync_all()?;
-        self.path = "".to_string();
-        file.flush();
-        Ok(())
+    // deja solo las primeras `entries` entradas
+    pub fn truncate(&mut self, entries: u64) {
+        self.size = self.size.min(entries * ENT_WIDTH);
     }
 }
@@ -0,0 +1,319 @@
+use crate::comp::record::log_client::LogClient;
+use crate::comp::record::{ConsumeRequest, GetServersRequest, ProduceRequest, Record, Server};
+use crate::comp::trace::TraceContext;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use tonic::transport::{Channel, Endpoint, Error};
+use tonic::{Code, Request, Status};
+
+fn channel(addr: &str) -> Result<Channel, Error> {
+    Ok(Endpoint::from_shared(format!("http://{}", addr))?.connect_lazy())
+}
+
+fn invalid_addr(e: Error) -> Status {
+    Status::invalid_argument(e.to_string())
+}
+
+// Le pregunta a algún nodo del cluster quienes son los servidores. Empieza
+// con la dirección que nos dieron y después se acuerda de todos los que vio,
+// así sigue funcionando aunque ese primer nodo se caiga.
+pub struct Resolver {
+    addrs: Mutex<Vec<String>>,
+}
+
+impl Resolver {
+    pub fn new(addr: &str) -> Self {
+        Resolver {
+            addrs: Mutex::new(vec![addr.to_string()]),
+        }
+    }
+
+    pub async fn resolve(&self) -> Result<Vec<Server>, Status> {
+        let addrs = self.addrs.lock().unwrap().clone();
+        let mut last = Status::unavailable("no servers to resolve from");
+        for addr in addrs {
+            let mut client = LogClient::new(channel(&addr).map_err(invalid_addr)?);
+            match client.get_servers(GetServersRequest {}).await {
+                Ok(res) => {
+                    let servers = res.into_inner().servers;
+                    let mut known: Vec<String> =
+                        servers.iter().map(|s| s.rpc_addr.clone()).collect();
+                    if !known.contains(&addr) {
+                        known.push(addr);
+                    }
+                    *self.addrs.lock().unwrap() = known;
+                    return Ok(servers);
+                }
+                Err(status) => last = status,
+            }
+        }
+        Err(last)
+    }
+}
+
+// Escoge a quien mandarle cada llamada: Produce al lider y Consume por
+// turnos entre los followers, para no cargarle todas las lecturas al lider.
+pub struct Picker {
+    servers: Vec<Server>,
+    leader: Option<LogClient<Channel>>,
+    // (id, cliente) de cada follower
+    followers: Vec<(String, LogClient<Channel>)>,
+    next: AtomicUsize,
+}
+
+impl Picker {
+    pub fn new(servers: &[Server]) -> Result<Self, Error> {
+        let mut leader = None;
+        let mut followers = vec![];
+        for server in servers {
+            let client = LogClient::new(channel(&server.rpc_addr)?);
+            if server.is_leader {
+                leader = Some(client);
+            } else {
+                followers.push((server.id.clone(), client));
+            }
+        }
+        Ok(Picker {
+            servers: servers.to_vec(),
+            leader,
+            followers,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn pick_produce(&self) -> Option<LogClient<Channel>> {
+        self.leader.clone()
+    }
+
+    // sin followers le leemos al lider
+    pub fn pick_consume(&self) -> Option<LogClient<Channel>> {
+        match self.next_follower() {
+            Some(i) => Some(self.followers[i].1.clone()),
+            None => self.pick_produce(),
+        }
+    }
+
+    fn next_follower(&self) -> Option<usize> {
+        if self.followers.is_empty() {
+            return None;
+        }
+        Some(self.next.fetch_add(1, Ordering::Relaxed) % self.followers.len())
+    }
+}
+
+pub struct Client {
+    resolver: Resolver,
+    picker: RwLock<Picker>,
+    // cada cliente es un productor idempotente, así el reintento de produce
+    // no duplica el registro si la primera vez sí se escribió
+    producer_id: u64,
+    sequence: AtomicU64,
+}
+
+impl Client {
+    pub async fn connect(addr: &str) -> Result<Self, Status> {
+        let resolver = Resolver::new(addr);
+        let picker = Picker::new(&resolver.resolve().await?).map_err(invalid_addr)?;
+        Ok(Client {
+            resolver,
+            picker: RwLock::new(picker),
+            // el 0 es "sin producer id"
+            producer_id: rand::random::<u64>().max(1),
+            sequence: AtomicU64::new(0),
+        })
+    }
+
+    // vuelve a preguntar por los servidores, por ejemplo cuando cambia el lider
+    pub async fn refresh(&self) -> Result<(), Status> {
+        let picker = Picker::new(&self.resolver.resolve().await?).map_err(invalid_addr)?;
+        *self.picker.write().unwrap() = picker;
+        Ok(())
+    }
+
+    // los servidores según la última vez que se resolvió
+    pub fn servers(&self) -> Vec<Server> {
+        self.picker.read().unwrap().servers.clone()
+    }
+
+    pub async fn produce(&self, mut record: Record) -> Result<u64, Status> {
+        if record.producer_id == 0 {
+            record.producer_id = self.producer_id;
+            record.sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        }
+        let req = ProduceRequest {
+            record: Some(record),
+            ..ProduceRequest::default()
+        };
+        // el reintento va en la misma traza
+        let trace = TraceContext::new();
+        tracing::debug!(trace_id = %trace.trace_id, "produce");
+        let mut client = self.picker.read().unwrap().pick_produce();
+        for retry in [true, false] {
+            let mut request = Request::new(req.clone());
+            trace.inject(&mut request);
+            let res = match client {
+                Some(mut c) => c.produce(request).await.map(|r| r.into_inner().offset),
+                None => Err(no_server()),
+            };
+            match res {
+                // le pegamos a un nodo que ya no es el lider, preguntamos de nuevo una vez
+                Err(status) if retry && stale_leader(&status) => {
+                    self.refresh().await?;
+                    client = self.picker.read().unwrap().pick_produce();
+                }
+                res => return res,
+            }
+        }
+        unreachable!()
+    }
+
+    pub async fn consume(&self, offset: u64) -> Result<Record, Status> {
+        let mut client = self
+            .picker
+            .read()
+            .unwrap()
+            .pick_consume()
+            .ok_or_else(no_server)?;
+        let res = client
+            .consume(ConsumeRequest {
+                offset,
+                ..ConsumeRequest::default()
+            })
+            .await?;
+        res.into_inner()
+            .record
+            .ok_or_else(|| Status::internal("empty consume response"))
+    }
+}
+
+fn no_server() -> Status {
+    Status::unavailable("no server available")
+}
+
+fn stale_leader(status: &Status) -> bool {
+    matches!(status.code(), Code::FailedPrecondition | Code::Unavailable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comp::config::{test_config, RaftConfig};
+    use crate::comp::distributed::DistributedLog;
+    use crate::comp::raft::StreamLayer;
+    use crate::comp::server::{new_grpc_server, ServerConfig};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio::time::{sleep, Duration};
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    #[tokio::test]
+    async fn picker_routes_calls() {
+        let servers: Vec<Server> = (0..3)
+            .map(|i| Server {
+                id: i.to_string(),
+                rpc_addr: format!("127.0.0.1:{}", 7000 + i),
+                is_leader: i == 1,
+            })
+            .collect();
+        let picker = Picker::new(&servers).unwrap();
+        assert!(picker.pick_produce().is_some());
+        let picked: Vec<&str> = (0..4)
+            .map(|_| picker.followers[picker.next_follower().unwrap()].0.as_str())
+            .collect();
+        assert_eq!(picked, ["0", "2", "0", "2"]);
+
+        // sin lider no hay a quien producirle, pero sí se puede leer
+        let picker = Picker::new(&servers[..1]).unwrap();
+        assert!(picker.pick_produce().is_none());
+        assert!(picker.pick_consume().is_some());
+    }
+
+    async fn node(
+        id: usize,
+        dir: &std::path::Path,
+        bootstrap: bool,
+    ) -> (DistributedLog, String, TcpListener) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rpc_addr = listener.local_addr().unwrap().to_string();
+        let layer = StreamLayer::bind("127.0.0.1:0").await.unwrap();
+        let raft_addr = layer.local_addr().unwrap();
+        let config = test_config();
+        let raft_config = RaftConfig {
+            local_id: id.to_string(),
+            bind_addr: raft_addr.clone(),
+            rpc_addr: rpc_addr.clone(),
+            heartbeat_timeout: Duration::from_millis(20),
+            election_timeout: Duration::from_millis(100),
+            bootstrap,
+            ..RaftConfig::default()
+        };
+        let log = DistributedLog::new(
+            dir.join(id.to_string()).to_str().unwrap(),
+            config,
+            raft_config,
+            layer,
+        )
+        .await
+        .unwrap();
+        (log, raft_addr, listener)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn produce_to_leader_consume_from_followers() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut logs: Vec<Arc<DistributedLog>> = vec![];
+        let mut rpc_addrs = vec![];
+        let mut listeners = vec![];
+        for i in 0..3 {
+            let (log, raft_addr, listener) = node(i, dir.path(), i == 0).await;
+            let rpc_addr = listener.local_addr().unwrap().to_string();
+            if i == 0 {
+                log.wait_for_leader(Duration::from_secs(3)).await.unwrap();
+            } else {
+                logs[0].join(&i.to_string(), &raft_addr, &rpc_addr).await.unwrap();
+            }
+            let log = Arc::new(log);
+            listeners.push(tokio::spawn(
+                tonic::transport::Server::builder()
+                    .add_service(new_grpc_server(ServerConfig::new(log.clone())))
+                    .serve_with_incoming(TcpListenerStream::new(listener)),
+            ));
+            logs.push(log);
+            rpc_addrs.push(rpc_addr);
+        }
+
+        // nos conectamos a un follower y aun así las escrituras llegan al lider
+        let client = Client::connect(&rpc_addrs[2]).await.unwrap();
+        let servers = client.resolver.resolve().await.unwrap();
+        assert_eq!(servers.iter().filter(|s| s.is_leader).count(), 1);
+        assert_eq!(servers.len(), 3);
+
+        let off = client
+            .produce(Record {
+                value: b"hola".to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+
+        // cada consume va a un follower distinto, los dos tienen que tener el registro
+        for _ in 0..2 {
+            let mut got = client.consume(off).await;
+            for _ in 0..100 {
+                if got.is_ok() {
+                    break;
+                }
+                sleep(Duration::from_millis(20)).await;
+                got = client.consume(off).await;
+            }
+            assert_eq!(got.unwrap().value, b"hola".to_vec());
+        }
+
+        for listener in listeners {
+            listener.abort();
+        }
+        for log in &logs {
+            log.close().await.unwrap();
+        }
+    }
+}
@@ -1,40 +1,98 @@
-use std::future::Future;
 use crate::comp::config::Config;
-use crate::comp::record::Record;
+use crate::comp::metrics;
+use crate::comp::record::{
+    LogState, ProducerSequence, Record, SegmentInfo, SnapshotManifest, TransactionState,
+};
 use crate::comp::segments::Segment;
-use crate::comp::store::Store;
-use std::collections::BTreeMap;
-use std::fs::{remove_dir_all, File, OpenOptions};
-use std::io::{self, BufReader, BufWriter, Read, Write};
-use std::path::{Path, PathBuf};
-use std::pin::Pin;
-use std::sync::{Arc, RwLock};
-use std::task::{Context, Poll};
-use tokio::fs;
-use tokio::io::{AsyncRead, ReadBuf};
+use crate::comp::snapshot::{self, Part, Snapshot, Source};
+use crate::comp::storage::{DirStorage, Storage};
+use crate::comp::store::LEN_WIDTH;
+use crate::comp::tiered::{self, RemoteCache};
+use prost::Message;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+// cuantas sequences por productor recordamos para detectar los repetidos
+pub const DEDUP_WINDOW: usize = 64;
+
+// ni empieza con un número ni termina en .store, así ni este setup ni el del
+// Log de Go lo toman por un segmento
+const STATE: &str = "state";
+
+// Record.control: marcadores de transacción. Una transacción empieza con un
+// BEGIN, sus registros llevan el mismo transaction_id y termina con un COMMIT o
+// un ABORT. Los consumidores read-committed solo ven lo de las que hicieron commit.
+pub const CONTROL_NONE: u32 = 0;
+pub const CONTROL_BEGIN: u32 = 1;
+pub const CONTROL_COMMIT: u32 = 2;
+pub const CONTROL_ABORT: u32 = 3;
+
+// una transacción es (origin, transaction_id): lo replicado de otro nodo no
+// choca con lo que se produce aquí
+type TxnKey = (String, u64);
+
+// lo que el log se guarda de cada registro escrito
+struct Meta {
+    producer: Option<(u64, u64)>,
+    transaction: Option<(TxnKey, u32)>,
+}
+
+impl Meta {
+    fn of(record: &Record) -> Self {
+        Meta {
+            producer: (record.producer_id != 0).then_some((record.producer_id, record.sequence)),
+            transaction: (record.transaction_id != 0).then(|| {
+                (
+                    (record.origin.clone(), record.transaction_id),
+                    record.control,
+                )
+            }),
+        }
+    }
+}
 
 pub struct Log {
-    dir: PathBuf,
+    storage: Arc<dyn Storage>,
     config: Config,
     active_segment: Option<Arc<RwLock<Segment>>>,
     segments: Vec<Arc<RwLock<Segment>>>,
+    // base offsets de los segmentos que ya solo están en el object store; todos
+    // son anteriores al primero de segments
+    remote: Vec<u64>,
+    cache: RemoteCache,
+    // por producer id, los últimos (sequence, offset) que se escribieron
+    producers: HashMap<u64, VecDeque<(u64, u64)>>,
+    // transacciones abiertas y el offset de su BEGIN
+    open_transactions: HashMap<TxnKey, u64>,
+    // (BEGIN, ABORT) de cada transacción abortada
+    aborted_transactions: HashMap<TxnKey, Vec<(u64, u64)>>,
+    // después de close el index ya está cortado a su tamaño real y su mmap no
+    // se puede tocar, así que no se lee ni se escribe nada más
+    closed: bool,
 }
 
 impl Log {
     pub async fn new(dir: &str, config: Config) -> io::Result<Self> {
-        let mut config = config;
-        if config.segment.max_store_bytes == 0 {
-            config.segment.max_store_bytes = 1024;
-        }
-        if config.segment.max_index_bytes == 0 {
-            config.segment.max_index_bytes = 1024;
-        }
+        Log::with_storage(Arc::new(DirStorage::new(dir)), config).await
+    }
+
+    // por ejemplo con un MemoryStorage para que el log no toque el disco
+    pub async fn with_storage(storage: Arc<dyn Storage>, config: Config) -> io::Result<Self> {
+        config.validate()?;
 
         let mut log = Log {
-            dir: Path::new(dir).to_path_buf(),
+            storage,
             config,
             active_segment: None,
             segments: Vec::new(),
+            remote: Vec::new(),
+            cache: RemoteCache::default(),
+            producers: HashMap::new(),
+            open_transactions: HashMap::new(),
+            aborted_transactions: HashMap::new(),
+            closed: false,
         };
 
         log.setup().await?;
@@ -42,126 +100,682 @@ impl Log {
     }
 
     pub async fn setup(&mut self) -> io::Result<()> {
+        // reset vuelve a abrir el log después de remove
+        self.closed = false;
         let mut base_offsets = Vec::new();
-        let entries = std::fs::read_dir(&self.dir)?;
+        let entries = self.storage.list().await?;
 
-        for entry in entries {
-            let entry = entry?;
-            let file_name = entry.file_name();
-            let file_name_str = file_name.to_string_lossy();
-            let base_offset_str = file_name_str.split('.').next().unwrap_or("");
+        for file_name in entries {
+            let base_offset_str = file_name.split('.').next().unwrap_or("");
             if let Ok(base_offset) = base_offset_str.parse::<u64>() {
                 base_offsets.push(base_offset);
             }
         }
 
+        // cada base offset aparece dos veces, una por el .store y otra por el .index
         base_offsets.sort();
-        for i in 0..base_offsets.len() {
-            if let Err(e) = self.new_segment(base_offsets[i]).await {
-                return Err(e);
-            }
-            if i + 1 < base_offsets.len() {
-                // i += 1;
-            }
+        base_offsets.dedup();
+        for base_offset in base_offsets {
+            self.new_segment(base_offset).await?;
         }
 
         if self.segments.is_empty() {
             self.new_segment(self.config.segment.initial_offset).await?;
         }
 
+        if let Some(tiering) = &self.config.tiering {
+            // si se cayó entre subir un segmento y borrarlo, manda el de disco
+            let local = self.local_lowest().await;
+            self.remote = tiered::remote_segments(tiering).await?;
+            self.remote.retain(|base| *base < local);
+        }
+
+        self.load_state().await
+    }
+
+    // Vuelve a llenar la ventana de cada productor y el estado de las
+    // transacciones. `state` guarda cómo estaban al empezar el segmento activo,
+    // así solo se lee ese segmento. Sin él (un log de antes, o un truncate_from
+    // que volvió a un segmento anterior) se lee todo lo que hay en disco y se
+    // guarda de nuevo. Los segmentos remotos no se bajan para esto, lo que
+    // quedó allá ya no se deduplica.
+    async fn load_state(&mut self) -> io::Result<()> {
+        self.producers.clear();
+        self.open_transactions.clear();
+        self.aborted_transactions.clear();
+        let base = match &self.active_segment {
+            Some(active) => active.read().await.base_offset,
+            None => return Ok(()),
+        };
+        if !self.read_state(base).await? {
+            let lowest = self.local_lowest().await;
+            self.replay(lowest, base).await?;
+            self.save_state(base).await?;
+        }
+        self.replay(base, u64::MAX).await
+    }
+
+    async fn replay(&mut self, from: u64, to: u64) -> io::Result<()> {
+        for offset in from..to {
+            let record = match self.find(offset).await {
+                Ok(record) => record,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            self.track(Meta::of(&record), offset);
+        }
         Ok(())
     }
 
-    pub async fn append(&mut self, record: Record) -> io::Result<u64> {
-        let mut offset = self
-            .active_segment
-            .as_mut()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No active segment"))?
-            .write().unwrap()
-            .append(record)
-            .await?;
-        offset += 1;
+    // false si no hay `state` de base o no se pudo leer completo
+    async fn read_state(&mut self, base: u64) -> io::Result<bool> {
+        if !self.storage.exists(STATE).await? {
+            return Ok(false);
+        }
+        let data = self.storage.read_file(STATE).await?;
+        // el largo va antes, un write a medias se nota
+        let state = match data.split_first_chunk::<8>() {
+            Some((len, rest)) if u64::from_be_bytes(*len) == rest.len() as u64 => {
+                LogState::decode(rest)
+            }
+            _ => return Ok(false),
+        };
+        let state = match state {
+            Ok(state) if state.base_offset == base => state,
+            Ok(_) => return Ok(false),
+            Err(e) => {
+                tracing::warn!(error = %e, "no se pudo leer el estado, se lee todo el log");
+                return Ok(false);
+            }
+        };
+        for p in state.producers {
+            self.track_sequence(p.producer_id, p.sequence, p.offset);
+        }
+        for t in state.transactions {
+            let key = (t.origin, t.transaction_id);
+            match t.end {
+                0 => {
+                    self.open_transactions.insert(key, t.begin);
+                }
+                end => self
+                    .aborted_transactions
+                    .entry(key)
+                    .or_default()
+                    .push((t.begin, end)),
+            }
+        }
+        Ok(true)
+    }
 
-        if let Some(ref a) = self.active_segment {
-            if a.read().unwrap().is_maxed().await {
-                self.new_segment(offset).await?;
+    // se llama cuando base es el primer offset del segmento activo
+    async fn save_state(&self, base: u64) -> io::Result<()> {
+        let mut state = LogState {
+            base_offset: base,
+            ..LogState::default()
+        };
+        for (id, recent) in &self.producers {
+            state
+                .producers
+                .extend(recent.iter().map(|(sequence, offset)| ProducerSequence {
+                    producer_id: *id,
+                    sequence: *sequence,
+                    offset: *offset,
+                }));
+        }
+        let transaction = |(origin, id): &TxnKey, begin: u64, end: u64| TransactionState {
+            origin: origin.clone(),
+            transaction_id: *id,
+            begin,
+            end,
+        };
+        for (key, begin) in &self.open_transactions {
+            state.transactions.push(transaction(key, *begin, 0));
+        }
+        for (key, ranges) in &self.aborted_transactions {
+            for (begin, end) in ranges {
+                state.transactions.push(transaction(key, *begin, *end));
             }
         }
+        let data = state.encode_to_vec();
+        let mut buf = (data.len() as u64).to_be_bytes().to_vec();
+        buf.extend_from_slice(&data);
+        self.storage.write_file(STATE, &buf).await
+    }
 
+    // Some(offset) si el registro ya se escribió; error si no se puede escribir
+    fn check(&self, record: &Record) -> io::Result<Option<u64>> {
+        if record.producer_id != 0 {
+            if let Some(offset) = self.check_sequence(record.producer_id, record.sequence)? {
+                return Ok(Some(offset));
+            }
+        }
+
+        let invalid = |msg: String| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        let id = record.transaction_id;
+        if id == 0 {
+            if record.control != CONTROL_NONE {
+                return invalid("transaction markers need a transaction id".to_string());
+            }
+            return Ok(None);
+        }
+        let open = self
+            .open_transactions
+            .contains_key(&(record.origin.clone(), id));
+        match record.control {
+            CONTROL_BEGIN if open => invalid(format!("transaction {} is already open", id)),
+            CONTROL_BEGIN => Ok(None),
+            CONTROL_NONE | CONTROL_COMMIT | CONTROL_ABORT if !open => {
+                invalid(format!("transaction {} is not open", id))
+            }
+            CONTROL_NONE | CONTROL_COMMIT | CONTROL_ABORT => Ok(None),
+            control => invalid(format!("unknown control type {}", control)),
+        }
+    }
 
+    fn track(&mut self, meta: Meta, offset: u64) {
+        if let Some((id, sequence)) = meta.producer {
+            self.track_sequence(id, sequence, offset);
+        }
+        let Some((key, control)) = meta.transaction else {
+            return;
+        };
+        match control {
+            CONTROL_BEGIN => {
+                self.open_transactions.insert(key, offset);
+            }
+            CONTROL_COMMIT => {
+                self.open_transactions.remove(&key);
+            }
+            CONTROL_ABORT => {
+                if let Some(begin) = self.open_transactions.remove(&key) {
+                    self.aborted_transactions
+                        .entry(key)
+                        .or_default()
+                        .push((begin, offset));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Some(offset) si la sequence ya se escribió; error si es más vieja que la ventana
+    fn check_sequence(&self, producer_id: u64, sequence: u64) -> io::Result<Option<u64>> {
+        let Some(recent) = self.producers.get(&producer_id) else {
+            return Ok(None);
+        };
+        if let Some((_, offset)) = recent.iter().find(|(s, _)| *s == sequence) {
+            return Ok(Some(*offset));
+        }
+        // con la ventana llena ya no sabemos si algo más viejo se escribió o no
+        let oldest = recent.iter().map(|(s, _)| *s).min().unwrap_or(0);
+        if recent.len() == DEDUP_WINDOW && sequence < oldest {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "sequence {} of producer {} is older than the deduplication window",
+                    sequence, producer_id
+                ),
+            ));
+        }
+        Ok(None)
+    }
+
+    fn track_sequence(&mut self, producer_id: u64, sequence: u64, offset: u64) {
+        let recent = self.producers.entry(producer_id).or_default();
+        recent.push_back((sequence, offset));
+        if recent.len() > DEDUP_WINDOW {
+            recent.pop_front();
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(offset))]
+    pub async fn append(&mut self, record: Record) -> io::Result<u64> {
+        self.check_open()?;
+        if let Some(offset) = self.check(&record)? {
+            tracing::debug!(offset, "registro repetido, ya estaba escrito");
+            return Ok(offset);
+        }
+        let meta = Meta::of(&record);
+        let timer = metrics::APPEND_SECONDS.start_timer();
+
+        let active = self
+            .active_segment
+            .clone()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No active segment"))?;
+        let mut segment = active.write().await;
+        let size = segment.store.size;
+        let offset = segment.append(record).await?;
+        tracing::Span::current().record("offset", offset);
+        metrics::STORE_BYTES.add((segment.store.size - size) as i64);
+        let maxed = segment.is_maxed().await;
+        drop(segment);
+        // antes del roll, el estado que se guarda ya lo incluye
+        self.track(meta, offset);
+
+        if maxed {
+            tracing::debug!(base = offset + 1, "segmento lleno, se abre otro");
+            metrics::ROLLS.inc();
+            self.new_segment(offset + 1).await?;
+            self.save_state(offset + 1).await?;
+            self.offload().await?;
+        }
 
+        timer.observe_duration();
+        metrics::APPENDS.inc();
         Ok(offset)
     }
 
+    #[tracing::instrument(level = "debug", skip(self))]
     pub async fn read(&self, offset: u64) -> io::Result<Record> {
-        let segment = self.segments
-            .iter()
-            .find(|seg| {
-                let guard = seg.read().unwrap();
-                guard.base_offset <= offset && offset < guard.next_offset
-            })
-            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Offset out of range"))?;
+        // los offsets que todavía no existen no cuentan, los streams preguntan
+        // por ellos todo el rato
+        let timer = metrics::READ_SECONDS.start_timer();
+        match self.find(offset).await {
+            Ok(record) => {
+                timer.observe_duration();
+                metrics::READS.inc();
+                Ok(record)
+            }
+            Err(e) => {
+                timer.stop_and_discard();
+                Err(e)
+            }
+        }
+    }
+
+    async fn find(&self, offset: u64) -> io::Result<Record> {
+        self.check_open()?;
+        for segment in &self.segments {
+            let guard = segment.read().await;
+            if guard.base_offset <= offset && offset < guard.next_offset {
+                return guard.read(offset).await;
+            }
+        }
 
-        segment.read().unwrap().read(offset).await
+        // lo que ya no está en disco se baja del object store
+        if offset < self.local_lowest().await {
+            if let Some(base) = self.remote.iter().rev().find(|b| **b <= offset) {
+                let segment = self.cache.get(*base, &self.config).await?;
+                return segment.read(offset).await;
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::NotFound, "Offset out of range"))
+    }
+
+    // Lectura read-committed: None si el offset es un marcador o es de una
+    // transacción abortada, WouldBlock si su transacción sigue abierta.
+    pub async fn read_committed(&self, offset: u64) -> io::Result<Option<Record>> {
+        let record = self.read(offset).await?;
+        if record.control != CONTROL_NONE {
+            return Ok(None);
+        }
+        if record.transaction_id == 0 {
+            return Ok(Some(record));
+        }
+        let key = (record.origin.clone(), record.transaction_id);
+        if self
+            .open_transactions
+            .get(&key)
+            .is_some_and(|begin| *begin < offset)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("transaction {} is still open", record.transaction_id),
+            ));
+        }
+        let aborted = self.aborted_transactions.get(&key).is_some_and(|ranges| {
+            ranges
+                .iter()
+                .any(|(begin, end)| *begin < offset && offset < *end)
+        });
+        Ok((!aborted).then_some(record))
     }
 
     async fn new_segment(&mut self, offset: u64) -> io::Result<()> {
-        let segment = Arc::new(RwLock::new(Segment::new(&self.dir.as_os_str().to_str().unwrap(), offset, self.config.clone()).await?));
+        let segment = Segment::new(Arc::clone(&self.storage), offset, self.config.clone()).await?;
+        metrics::segment_opened(segment.store.size);
+        let segment = Arc::new(RwLock::new(segment));
         self.segments.push(Arc::clone(&segment));
         self.active_segment = Some(segment);
         Ok(())
     }
 
+    // sube al object store los segmentos sellados que sobran en disco
+    async fn offload(&mut self) -> io::Result<()> {
+        let Some(tiering) = self.config.tiering.clone() else {
+            return Ok(());
+        };
+        while self.segments.len() > tiering.local_segments + 1 {
+            let segment = self.segments[0].clone();
+            let mut guard = segment.write().await;
+            guard.close().await?;
+            tiered::upload(&tiering, &guard).await?;
+            tracing::info!(
+                base = guard.base_offset,
+                prefix = %tiering.prefix,
+                "segmento subido al object store"
+            );
+            metrics::segment_closed(guard.store.size);
+            guard.remove().await?;
+            self.remote.push(guard.base_offset);
+            drop(guard);
+            self.segments.remove(0);
+        }
+        Ok(())
+    }
+
+    async fn local_lowest(&self) -> u64 {
+        match self.segments.first() {
+            Some(seg) => seg.read().await.base_offset,
+            None => 0,
+        }
+    }
+
+    // se puede llamar más de una vez, por ejemplo raft y DistributedLog
     pub async fn close(&mut self) -> io::Result<()> {
+        if self.closed {
+            return Ok(());
+        }
         for segment in &mut self.segments {
-            segment.write().unwrap().close().await?;
+            segment.write().await.close().await?;
+        }
+        self.closed = true;
+        Ok(())
+    }
+
+    fn check_open(&self) -> io::Result<()> {
+        if self.closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "log is closed"));
         }
         Ok(())
     }
 
     pub async fn remove(&mut self) -> io::Result<()> {
         self.close().await?;
-        remove_dir_all(&self.dir)
+        for name in self.storage.list().await? {
+            self.storage.remove(&name).await?;
+        }
+        if let Some(tiering) = &self.config.tiering {
+            for base in self.remote.drain(..) {
+                self.cache.evict(base).await;
+                tiered::delete(tiering, base).await?;
+            }
+        }
+        for segment in self.segments.drain(..) {
+            metrics::segment_closed(segment.read().await.store.size);
+        }
+        self.active_segment = None;
+        Ok(())
+    }
+
+    pub async fn lowest_offset(&self) -> io::Result<u64> {
+        match self.remote.first() {
+            Some(base) => Ok(*base),
+            None => Ok(self.local_lowest().await),
+        }
     }
 
-    pub async fn reset(&mut self) -> io::Result<()> {
-        self.remove().await?;
-        self.setup().await
+    pub async fn highest_offset(&self) -> io::Result<u64> {
+        match self.segments.last() {
+            Some(seg) => Ok(seg.read().await.next_offset.saturating_sub(1)),
+            None => Ok(0),
+        }
+    }
+
+    // el offset que va a recibir el próximo append
+    pub async fn next_offset(&self) -> u64 {
+        match self.segments.last() {
+            Some(seg) => seg.read().await.next_offset,
+            None => self.config.segment.initial_offset,
+        }
     }
 
-    pub fn lowest_offset(&self) -> io::Result<u64> {
-        Ok(self.segments.first().map_or(0, |seg| seg.read().unwrap().base_offset))
+    // los segmentos remotos y luego los de disco, el último es el activo
+    pub async fn describe(&self) -> Vec<SegmentInfo> {
+        let local = self.local_lowest().await;
+        let mut segments: Vec<SegmentInfo> = self
+            .remote
+            .iter()
+            .enumerate()
+            .map(|(i, base)| SegmentInfo {
+                base_offset: *base,
+                next_offset: self.remote.get(i + 1).copied().unwrap_or(local),
+                remote: true,
+                ..SegmentInfo::default()
+            })
+            .collect();
+        for (i, segment) in self.segments.iter().enumerate() {
+            let guard = segment.read().await;
+            segments.push(SegmentInfo {
+                base_offset: guard.base_offset,
+                next_offset: guard.next_offset,
+                store_bytes: guard.store.size,
+                index_bytes: guard.index.size,
+                remote: false,
+                active: i == self.segments.len() - 1,
+            });
+        }
+        segments
     }
 
-    pub fn highest_offset(&self) -> io::Result<u64> {
-        Ok(self
-            .segments
-            .last()
-            .map_or(0, |seg| seg.read().unwrap().next_offset.saturating_sub(1)))
+    // Cierra el segmento activo aunque no esté lleno y regresa el base offset
+    // del nuevo. Si el activo está vacío se queda igual.
+    pub async fn roll(&mut self) -> io::Result<u64> {
+        self.check_open()?;
+        let active = self
+            .active_segment
+            .clone()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No active segment"))?;
+        let (base, next) = {
+            let guard = active.read().await;
+            (guard.base_offset, guard.next_offset)
+        };
+        if base == next {
+            return Ok(base);
+        }
+        tracing::info!(base = next, "segmento cerrado a mano, se abre otro");
+        metrics::ROLLS.inc();
+        self.new_segment(next).await?;
+        self.save_state(next).await?;
+        self.offload().await?;
+        Ok(next)
     }
 
+    // Lee cada registro del segmento que empieza en base. Regresa cuantos
+    // tiene y lo que está mal: frames que no se leen, offsets que no cuadran
+    // o bytes en el store después del último frame (un append a medias).
+    pub async fn verify_segment(&self, base: u64) -> io::Result<(u64, Vec<String>)> {
+        self.check_open()?;
+        let info = self
+            .describe()
+            .await
+            .into_iter()
+            .find(|s| s.base_offset == base)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no segment starts at offset {}", base),
+                )
+            })?;
+        let mut problems = vec![];
+        for offset in info.base_offset..info.next_offset {
+            match self.find(offset).await {
+                Ok(record) if record.offset != offset => {
+                    problems.push(format!(
+                        "offset {} has the record of {}",
+                        offset, record.offset
+                    ));
+                }
+                Ok(_) => {}
+                Err(e) => problems.push(format!("offset {}: {}", offset, e)),
+            }
+        }
+
+        if !info.remote && info.next_offset > info.base_offset {
+            for segment in &self.segments {
+                let guard = segment.read().await;
+                if guard.base_offset != base {
+                    continue;
+                }
+                let end = match guard.index.read(-1) {
+                    Ok((_, pos)) => match guard.store.read(pos).await {
+                        Ok((_, data)) => pos + (LEN_WIDTH + data.len()) as u64,
+                        Err(_) => break,
+                    },
+                    Err(e) => {
+                        problems.push(format!("index: {}", e));
+                        break;
+                    }
+                };
+                if end != guard.store.size {
+                    problems.push(format!(
+                        "the store has {} bytes after the last record",
+                        guard.store.size.saturating_sub(end)
+                    ));
+                }
+            }
+        }
+        Ok((info.next_offset - info.base_offset, problems))
+    }
+
+    // Deja por lo menos los últimos max_records registros (0 es sin límite).
+    // Como truncate borra segmentos completos, pueden quedar algunos más.
+    pub async fn retain(&mut self, max_records: u64) -> io::Result<()> {
+        let next = match self.segments.last() {
+            Some(seg) => seg.read().await.next_offset,
+            None => return Ok(()),
+        };
+        if max_records == 0 || next <= max_records {
+            return Ok(());
+        }
+        self.truncate(next - max_records - 1).await
+    }
+
+    #[tracing::instrument(level = "debug", skip(self))]
     pub async fn truncate(&mut self, lowest: u64) -> io::Result<()> {
-        let mut remove = vec![];
-        for (i, seg) in self.segments.iter().enumerate() {
-            let mut seg = seg.write().unwrap();
-            if seg.next_offset <= lowest + 1 {
-                seg.remove().await?;
-                remove.push(i);
+        // un segmento remoto acaba donde empieza el siguiente
+        let local = self.local_lowest().await;
+        while let Some(base) = self.remote.first().copied() {
+            let next = self.remote.get(1).copied().unwrap_or(local);
+            if next > lowest + 1 {
+                break;
+            }
+            self.cache.evict(base).await;
+            if let Some(tiering) = &self.config.tiering {
+                tiered::delete(tiering, base).await?;
             }
+            self.remote.remove(0);
         }
 
-        let mut index = 0;
-        self.segments.retain(|_| {
-            let r = remove.contains(&index);
-            index += 1;
-            r
-        });
+        let mut segments = vec![];
+        for seg in self.segments.drain(..) {
+            {
+                let mut guard = seg.write().await;
+                if guard.next_offset <= lowest + 1 {
+                    metrics::segment_closed(guard.store.size);
+                    guard.remove().await?;
+                    continue;
+                }
+            }
+            segments.push(seg);
+        }
+        self.segments = segments;
 
         Ok(())
     }
 
- 
+    // Lo contrario a truncate: borra offset y todo lo que viene después.
+    // Raft lo necesita cuando un follower tiene entradas que el lider no tiene.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn truncate_from(&mut self, offset: u64) -> io::Result<()> {
+        // los segmentos remotos ya están sellados, no se tocan
+        if offset < self.local_lowest().await {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Offset below the lowest segment",
+            ));
+        }
+
+        while self.segments.len() > 1 {
+            let last = self.segments.last().unwrap().clone();
+            let mut guard = last.write().await;
+            if guard.base_offset < offset {
+                break;
+            }
+            metrics::segment_closed(guard.store.size);
+            guard.remove().await?;
+            drop(guard);
+            self.segments.pop();
+        }
 
+        let last = self.segments.last().unwrap().clone();
+        let full = {
+            let mut guard = last.write().await;
+            if offset < guard.next_offset {
+                let size = guard.store.size;
+                guard.truncate(offset).await?;
+                metrics::STORE_BYTES.sub((size - guard.store.size) as i64);
+            }
+            guard.is_maxed().await.then_some(guard.next_offset)
+        };
+        self.active_segment = Some(last);
+        // append solo rueda después de escribir, un segmento lleno no puede
+        // quedar como el activo
+        if let Some(next) = full {
+            self.new_segment(next).await?;
+        }
+
+        // lo borrado ya no cuenta como escrito
+        self.load_state().await
+    }
+
+    // Anota hasta dónde llega cada segmento y abre sus store, sin copiarlos.
+    // Con el log en un RwLock se suelta el lock y se escribe el Snapshot
+    // aparte, así los appends no se detienen mientras se escribe el tar.
+    pub async fn checkpoint(&self) -> io::Result<Snapshot> {
+        let mut parts = vec![];
+        let local = self.local_lowest().await;
+        if let Some(tiering) = &self.config.tiering {
+            for (i, base) in self.remote.iter().enumerate() {
+                parts.push(Part {
+                    base_offset: *base,
+                    next_offset: self.remote.get(i + 1).copied().unwrap_or(local),
+                    key_id: None,
+                    source: Source::Remote(tiering.clone()),
+                });
+            }
+        }
+        for segment in &self.segments {
+            let guard = segment.read().await;
+            parts.push(Part {
+                base_offset: guard.base_offset,
+                next_offset: guard.next_offset,
+                key_id: guard.key_id.clone(),
+                source: Source::Local {
+                    index: guard.index.mmap[..guard.index.size as usize].to_vec(),
+                    // el segmento existe mientras tengamos el log, open no crea nada
+                    store: guard.storage.open(&guard.path_store).await?,
+                    store_bytes: guard.store.size,
+                },
+            });
+        }
+        Ok(Snapshot { parts })
+    }
+
+    // un tar con todos los segmentos, también los que están en el object
+    // store; el lock solo se toma para el checkpoint
+    pub async fn snapshot(log: &RwLock<Log>, dest: &str) -> io::Result<SnapshotManifest> {
+        let snapshot = log.read().await.checkpoint().await?;
+        snapshot.write(dest).await
+    }
+
+    // arma en dir (vacío) el log de un snapshot y lo abre
+    pub async fn restore(archive: &str, dir: &str, config: Config) -> io::Result<Log> {
+        let (archive, unpacked) = (archive.to_string(), dir.to_string());
+        snapshot::blocking(move || snapshot::unpack(&archive, &unpacked)).await?;
+        Log::new(dir, config).await
+    }
 
     /* 
     
@@ -182,46 +796,700 @@ impl Log {
      */
 }
 
+// los segmentos de un log que se suelta ya no cuentan en las métricas
+impl Drop for Log {
+    fn drop(&mut self) {
+        for segment in &self.segments {
+            if let Ok(guard) = segment.try_read() {
+                metrics::segment_closed(guard.store.size);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comp::config::{test_config, Compression, SegmentConfig};
+    use std::path::Path;
 
+    const VALUES: [&[u8]; 4] = [b"hello world", b"adios mundo", b"distributed", b"commit log!"];
 
-pub struct OriginReader {
-    store: Arc<Store>, 
-    off: u64,
-}
+    fn config() -> Config {
+        Config {
+            segment: SegmentConfig {
+                max_store_bytes: 32,
+                ..test_config().segment
+            },
+            ..test_config()
+        }
+    }
 
-impl OriginReader {
-    pub async fn new(store: Arc<Store>) -> Self {
-        OriginReader { store, off: 0 }
+    // abrir el log modifica los archivos, así que trabajamos sobre una copia
+    fn copy_fixture(name: &str) -> tempfile::TempDir {
+        let src = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../Log/testdata/interop")
+            .join(name);
+        let dir = tempfile::tempdir().unwrap();
+        for entry in std::fs::read_dir(src).unwrap() {
+            let entry = entry.unwrap();
+            std::fs::copy(entry.path(), dir.path().join(entry.file_name())).unwrap();
+        }
+        dir
     }
-}
 
+    async fn check_fixture(name: &str) {
+        let dir = copy_fixture(name);
+        let path = dir.path().to_str().unwrap();
 
-impl AsyncRead for OriginReader {
-    fn poll_read(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &mut ReadBuf<'_>
-    ) -> Poll<io::Result<()>> {
-        let store = self.store.clone();  
-        let offset = self.off;
-        
-        
-        let fut = store.reat_at(buf.initialize_unfilled(), offset);
+        let mut log = Log::new(path, config()).await.unwrap();
+        assert_eq!(log.lowest_offset().await.unwrap(), 0);
+        assert_eq!(log.highest_offset().await.unwrap(), 3);
+        for (off, value) in VALUES.iter().enumerate() {
+            let record = log.read(off as u64).await.unwrap();
+            assert_eq!(record.value, value.to_vec());
+            assert_eq!(record.offset, off as u64);
+        }
+        assert!(log.read(VALUES.len() as u64).await.is_err());
 
-        tokio::pin!(fut);  
+        let off = log
+            .append(Record {
+                value: b"desde rust".to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(off, 4);
+        log.close().await.unwrap();
+
+        let log = Log::new(path, config()).await.unwrap();
+        assert_eq!(log.read(4).await.unwrap().value, b"desde rust".to_vec());
+    }
+
+    #[tokio::test]
+    async fn open_go_log() {
+        check_fixture("go").await;
+    }
 
-        match fut.poll(cx) {
-            Poll::Ready(Ok(n)) => {
-                self.off += n as u64; 
-                  
-                Poll::Ready(Ok(()))    
+    #[tokio::test]
+    async fn open_rust_log() {
+        check_fixture("rust").await;
+    }
+
+    #[tokio::test]
+    async fn store_matches_go() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = Log::new(dir.path().to_str().unwrap(), config()).await.unwrap();
+        for value in VALUES {
+            log.append(Record {
+                value: value.to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+        }
+        log.close().await.unwrap();
+
+        let go = Path::new(env!("CARGO_MANIFEST_DIR")).join("../Log/testdata/interop/go");
+        for base in [0, 2, 4] {
+            let name = format!("{}.store", base);
+            assert_eq!(
+                std::fs::read(dir.path().join(&name)).unwrap(),
+                std::fs::read(go.join(&name)).unwrap(),
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn close_truncates_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = Log::new(dir.path().to_str().unwrap(), config()).await.unwrap();
+        let record = Record {
+            value: VALUES[0].to_vec(),
+            ..Record::default()
+        };
+        log.append(record.clone()).await.unwrap();
+        let index = dir.path().join("0.index");
+        assert_eq!(std::fs::metadata(&index).unwrap().len(), 1200);
+
+        log.close().await.unwrap();
+        // una sola entrada de 12 bytes, ya no el tamaño preasignado
+        assert_eq!(std::fs::metadata(&index).unwrap().len(), 12);
+        log.close().await.unwrap();
+        let err = log.append(record).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(log.read(0).await.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[tokio::test]
+    async fn torn_write_is_cut_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let config = Config {
+            segment: SegmentConfig {
+                max_store_bytes: 1024,
+                ..config().segment
+            },
+            ..config()
+        };
+        let mut log = Log::new(path, config.clone()).await.unwrap();
+        let store = dir.path().join("0.store");
+        let mut first = 0;
+        for value in &VALUES[..2] {
+            log.append(Record {
+                value: value.to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+            if first == 0 {
+                first = std::fs::metadata(&store).unwrap().len();
             }
-            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
-            Poll::Pending => Poll::Pending, 
         }
+        log.close().await.unwrap();
+
+        // el segundo frame quedó a medias: el index lo tiene pero el store no
+        let len = std::fs::metadata(&store).unwrap().len();
+        let file = std::fs::OpenOptions::new().write(true).open(&store).unwrap();
+        file.set_len(len - 4).unwrap();
+
+        let mut log = Log::new(path, config).await.unwrap();
+        assert_eq!(log.highest_offset().await.unwrap(), 0);
+        assert_eq!(log.read(0).await.unwrap().value, VALUES[0]);
+        assert!(log.read(1).await.is_err());
+        // el store se corta al final del primer registro
+        assert_eq!(std::fs::metadata(&store).unwrap().len(), first);
+        let off = log
+            .append(Record {
+                value: VALUES[2].to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(off, 1);
+        assert_eq!(log.read(1).await.unwrap().value, VALUES[2]);
     }
-}
 
+    #[tokio::test]
+    async fn invalid_segment_config() {
+        use crate::comp::encryption::Keyring;
 
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let keyring = Arc::new(
+            Keyring::parse("k1 AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=\n").unwrap(),
+        );
+        for (store, index, encrypted, msg) in [
+            (1024, 0, false, "max_index_bytes must be a positive multiple of 12"),
+            (1024, 1000, false, "got 1000"),
+            (1024, 12 << 33, false, "more than 2^32 entries"),
+            (0, 1200, false, "max_store_bytes must be greater than 8"),
+            (8, 1200, false, "got 8"),
+            (32, 1200, true, "greater than 36 (the frame of an encrypted record)"),
+        ] {
+            let mut config = config();
+            config.segment.max_store_bytes = store;
+            config.segment.max_index_bytes = index;
+            if encrypted {
+                config.encryption = Some(keyring.clone());
+            }
+            let Err(err) = Log::new(path, config).await else {
+                panic!("{} {} should be invalid", store, index);
+            };
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(err.to_string().contains(msg), "{}", err);
+        }
+        // ni un archivo
+        assert_eq!(std::fs::read_dir(path).unwrap().count(), 0);
 
+        let mut config = config();
+        config.segment.max_store_bytes = 9;
+        config.segment.max_index_bytes = 12;
+        Log::new(path, config).await.unwrap();
+    }
 
+    #[tokio::test]
+    async fn truncate_from_a_segment_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let mut log = Log::new(path, config()).await.unwrap();
+        async fn append(log: &mut Log, value: &[u8]) -> io::Result<u64> {
+            log.append(Record {
+                value: value.to_vec(),
+                ..Record::default()
+            })
+            .await
+        }
+        let bases =
+            |segments: Vec<SegmentInfo>| segments.iter().map(|s| s.base_offset).collect::<Vec<_>>();
+        // con 32 bytes cada segmento se llena con dos registros
+        for value in &VALUES[..3] {
+            append(&mut log, value).await.unwrap();
+        }
+        assert_eq!(bases(log.describe().await), [0, 2]);
+
+        log.truncate_from(2).await.unwrap();
+        assert!(log.read(2).await.is_err());
+        assert_eq!(append(&mut log, b"otra vez").await.unwrap(), 2);
+        assert_eq!(append(&mut log, VALUES[3]).await.unwrap(), 3);
+        assert_eq!(bases(log.describe().await), [0, 2, 4]);
+        log.close().await.unwrap();
+
+        let log = Log::new(path, config()).await.unwrap();
+        assert_eq!(log.read(1).await.unwrap().value, VALUES[1].to_vec());
+        assert_eq!(log.read(2).await.unwrap().value, b"otra vez".to_vec());
+    }
+
+    #[tokio::test]
+    async fn retain_keeps_the_last_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = Log::new(dir.path().to_str().unwrap(), config()).await.unwrap();
+        for _ in 0..8 {
+            log.append(Record {
+                value: VALUES[0].to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+        }
+        log.retain(0).await.unwrap();
+        log.retain(8).await.unwrap();
+        assert_eq!(log.lowest_offset().await.unwrap(), 0);
+
+        log.retain(3).await.unwrap();
+        let lowest = log.lowest_offset().await.unwrap();
+        assert!(lowest > 0 && lowest <= 5, "{}", lowest);
+        assert_eq!(log.highest_offset().await.unwrap(), 7);
+        assert!(log.read(lowest - 1).await.is_err());
+        log.read(5).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn idempotent_producer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let mut log = Log::new(path, config()).await.unwrap();
+        let record = |producer_id: u64, sequence: u64| Record {
+            value: b"pago".to_vec(),
+            producer_id,
+            sequence,
+            ..Record::default()
+        };
+
+        assert_eq!(log.append(record(7, 0)).await.unwrap(), 0);
+        assert_eq!(log.append(record(7, 1)).await.unwrap(), 1);
+        // un reintento regresa el offset original sin escribir nada
+        assert_eq!(log.append(record(7, 0)).await.unwrap(), 0);
+        assert_eq!(log.append(record(7, 1)).await.unwrap(), 1);
+        // otro productor con la misma sequence sí se escribe
+        assert_eq!(log.append(record(8, 0)).await.unwrap(), 2);
+        // sin producer id no hay deduplicación
+        assert_eq!(log.append(record(0, 0)).await.unwrap(), 3);
+        assert_eq!(log.append(record(0, 0)).await.unwrap(), 4);
+        assert!(log.read(5).await.is_err());
+        log.close().await.unwrap();
+
+        // la ventana se reconstruye al abrir
+        let mut log = Log::new(path, config()).await.unwrap();
+        assert_eq!(log.append(record(7, 1)).await.unwrap(), 1);
+        assert_eq!(log.append(record(7, 3)).await.unwrap(), 5);
+        // las sequences pueden llegar en desorden mientras estén en la ventana
+        assert_eq!(log.append(record(7, 2)).await.unwrap(), 6);
+        assert_eq!(log.append(record(7, 3)).await.unwrap(), 5);
+
+        // lo que ya salió de la ventana no se puede deduplicar
+        for sequence in 4..4 + DEDUP_WINDOW as u64 {
+            log.append(record(7, sequence)).await.unwrap();
+        }
+        let err = log.append(record(7, 3)).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let mut log = Log::new(path, config()).await.unwrap();
+        let record = |transaction_id: u64, control: u32, value: &[u8]| Record {
+            value: value.to_vec(),
+            transaction_id,
+            control,
+            ..Record::default()
+        };
+
+        log.append(record(1, CONTROL_BEGIN, b"")).await.unwrap(); // 0
+        log.append(record(1, CONTROL_NONE, b"uno")).await.unwrap(); // 1
+        log.append(record(0, CONTROL_NONE, b"suelto")).await.unwrap(); // 2
+        log.append(record(2, CONTROL_BEGIN, b"")).await.unwrap(); // 3
+        log.append(record(2, CONTROL_NONE, b"dos")).await.unwrap(); // 4
+
+        // mientras la transacción está abierta no se puede leer lo suyo
+        let err = log.read_committed(1).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(log.read_committed(2).await.unwrap().unwrap().value, b"suelto");
+        assert!(log.read_committed(0).await.unwrap().is_none());
+
+        log.append(record(2, CONTROL_ABORT, b"")).await.unwrap(); // 5
+        log.append(record(1, CONTROL_COMMIT, b"")).await.unwrap(); // 6
+
+        async fn check(log: &Log) {
+            assert_eq!(log.read_committed(1).await.unwrap().unwrap().value, b"uno");
+            assert!(log.read_committed(4).await.unwrap().is_none());
+            assert!(log.read_committed(5).await.unwrap().is_none());
+            assert!(log.read_committed(6).await.unwrap().is_none());
+            // leer normal regresa todo, marcadores incluidos
+            assert_eq!(log.read(4).await.unwrap().value, b"dos");
+            assert_eq!(log.read(5).await.unwrap().control, CONTROL_ABORT);
+        }
+        check(&log).await;
+        log.close().await.unwrap();
+
+        // el estado de las transacciones se reconstruye al abrir
+        let mut log = Log::new(path, config()).await.unwrap();
+        check(&log).await;
+
+        // el mismo id se puede volver a usar cuando la anterior ya terminó
+        log.append(record(2, CONTROL_BEGIN, b"")).await.unwrap(); // 7
+        log.append(record(2, CONTROL_NONE, b"otra vez")).await.unwrap(); // 8
+        log.append(record(2, CONTROL_COMMIT, b"")).await.unwrap(); // 9
+        assert_eq!(log.read_committed(8).await.unwrap().unwrap().value, b"otra vez");
+        assert!(log.read_committed(4).await.unwrap().is_none());
+
+        for bad in [
+            record(3, CONTROL_NONE, b"sin begin"),
+            record(3, CONTROL_COMMIT, b""),
+            record(0, CONTROL_BEGIN, b""),
+            record(4, 9, b""),
+        ] {
+            let err = log.append(bad).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        log.append(record(5, CONTROL_BEGIN, b"")).await.unwrap();
+        let err = log.append(record(5, CONTROL_BEGIN, b"")).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn compressed_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let json = br#"{"cliente": "acme", "estado": "pagado"}"#.repeat(10);
+        let mut config = config();
+        config.segment.max_store_bytes = 1024;
+
+        // cada registro se lee con el códec de su frame, aunque cambie la config
+        let mut offsets = vec![];
+        for codec in [
+            Compression::Zstd,
+            Compression::Lz4,
+            Compression::Snappy,
+            Compression::None,
+        ] {
+            config.compression = codec;
+            let mut log = Log::new(path, config.clone()).await.unwrap();
+            let off = log
+                .append(Record {
+                    value: json.clone(),
+                    ..Record::default()
+                })
+                .await
+                .unwrap();
+            offsets.push(off);
+            log.close().await.unwrap();
+        }
+
+        let log = Log::new(path, config).await.unwrap();
+        for off in offsets {
+            assert_eq!(log.read(off).await.unwrap().value, json);
+        }
+        // los tres comprimidos más el que no, mucho menos que cuatro sin comprimir
+        let size: u64 = std::fs::read_dir(path)
+            .unwrap()
+            .map(|e| e.unwrap())
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".store"))
+            .map(|e| e.metadata().unwrap().len())
+            .sum();
+        assert!(size < 2 * json.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn encrypted_segments() {
+        use crate::comp::encryption::Keyring;
+
+        let k1 = "k1 AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=\n";
+        let k2 = "k2 HxwdHh8QERITFBUWFxgZGhsMDQ4PAAECAwQFBgcICQo=\n";
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let with_keys = |keys: &str| Config {
+            // lo mínimo con cifrado: un registro por segmento
+            segment: SegmentConfig {
+                max_store_bytes: 37,
+                ..config().segment
+            },
+            encryption: Some(Arc::new(Keyring::parse(keys).unwrap())),
+            compression: Compression::Zstd,
+            ..config()
+        };
+
+        let mut log = Log::new(path, with_keys(k1)).await.unwrap();
+        for value in VALUES {
+            log.append(Record {
+                value: value.to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+        }
+        log.close().await.unwrap();
+        let store = std::fs::read(dir.path().join("0.store")).unwrap();
+        assert!(!store.windows(5).any(|w| w == b"hello"));
+        assert_eq!(std::fs::read_to_string(dir.path().join("0.key")).unwrap(), "k1");
+
+        // rotamos: k2 es la activa, k1 se queda para leer lo viejo
+        let mut log = Log::new(path, with_keys(&format!("{}{}", k1, k2))).await.unwrap();
+        let off = log
+            .append(Record {
+                value: b"con k2".to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+        for (i, value) in VALUES.iter().enumerate() {
+            assert_eq!(log.read(i as u64).await.unwrap().value, value.to_vec());
+        }
+        assert_eq!(log.read(off).await.unwrap().value, b"con k2".to_vec());
+        let key = format!("{}.key", off);
+        assert_eq!(std::fs::read_to_string(dir.path().join(key)).unwrap(), "k2");
+        log.close().await.unwrap();
+
+        // sin k1 no se puede abrir, y el error dice qué llave falta
+        let err = Log::new(path, with_keys(k2)).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("\"k1\""), "{}", err);
+        let err = Log::new(path, config()).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn memory_storage() {
+        use crate::comp::storage::MemoryStorage;
+
+        let storage = MemoryStorage::new();
+        let mut log = Log::with_storage(Arc::new(storage.clone()), config())
+            .await
+            .unwrap();
+        for value in VALUES {
+            log.append(Record {
+                value: value.to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+        }
+        // con 32 bytes por store caben dos registros por segmento
+        assert_eq!(log.segments.len(), 3);
+        log.truncate_from(3).await.unwrap();
+        log.close().await.unwrap();
+
+        // otro Log sobre el mismo storage ve lo mismo
+        let mut log = Log::with_storage(Arc::new(storage.clone()), config())
+            .await
+            .unwrap();
+        for (i, value) in VALUES[..3].iter().enumerate() {
+            assert_eq!(log.read(i as u64).await.unwrap().value, value.to_vec());
+        }
+        assert_eq!(log.highest_offset().await.unwrap(), 2);
+
+        log.remove().await.unwrap();
+        assert!(storage.list().await.unwrap().is_empty());
+    }
+
+    // al abrir solo se lee el segmento activo, lo de antes sale de `state`
+    #[tokio::test]
+    async fn state_covers_sealed_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let mut log = Log::new(path, config()).await.unwrap();
+        let record = |producer_id: u64, transaction_id: u64, control: u32| Record {
+            value: b"x".to_vec(),
+            producer_id,
+            transaction_id,
+            control,
+            ..Record::default()
+        };
+        log.append(record(7, 0, CONTROL_NONE)).await.unwrap(); // 0
+        log.append(record(0, 1, CONTROL_BEGIN)).await.unwrap(); // 1
+        log.append(record(0, 1, CONTROL_NONE)).await.unwrap(); // 2
+        log.append(record(0, 1, CONTROL_ABORT)).await.unwrap(); // 3
+        log.append(record(0, 2, CONTROL_BEGIN)).await.unwrap(); // 4
+        assert_eq!(log.roll().await.unwrap(), 5);
+        log.close().await.unwrap();
+
+        let mut log = Log::new(path, config()).await.unwrap();
+        assert!(log.read_committed(2).await.unwrap().is_none());
+        log.close().await.unwrap();
+
+        // se rompen los datos de los segmentos sellados, sin tocar los largos
+        for entry in std::fs::read_dir(path).unwrap() {
+            let file = entry.unwrap().path();
+            let mut bytes = std::fs::read(&file).unwrap();
+            if file.extension().is_some_and(|e| e == "store") && !bytes.is_empty() {
+                bytes[LEN_WIDTH..].fill(0xff);
+                std::fs::write(&file, bytes).unwrap();
+            }
+        }
+        let mut log = Log::new(path, config()).await.unwrap();
+        assert_eq!(log.append(record(7, 0, CONTROL_NONE)).await.unwrap(), 0);
+        let err = log.append(record(0, 2, CONTROL_BEGIN)).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        log.close().await.unwrap();
+
+        // sin un `state` completo se lee todo el log
+        let state = Path::new(path).join(STATE);
+        let bytes = std::fs::read(&state).unwrap();
+        std::fs::write(&state, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(Log::new(path, config()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn tiered_segments() {
+        use crate::comp::config::Tiering;
+        use crate::comp::tiered::LocalObjectStore;
+
+        let dir = tempfile::tempdir().unwrap();
+        let remote = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let config = Config {
+            tiering: Some(Tiering {
+                prefix: "log".to_string(),
+                local_segments: 1,
+                cache_segments: 1,
+                ..Tiering::new(Arc::new(LocalObjectStore::new(
+                    remote.path().to_str().unwrap(),
+                )))
+            }),
+            ..config()
+        };
+        let count = |dir: &Path, ext: &str| {
+            std::fs::read_dir(dir)
+                .map(|entries| {
+                    entries
+                        .filter(|e| {
+                            e.as_ref()
+                                .unwrap()
+                                .path()
+                                .extension()
+                                .is_some_and(|x| x == ext)
+                        })
+                        .count()
+                })
+                .unwrap_or(0)
+        };
+
+        // dos registros por segmento: 0-1, 2-3, ... 8-9 y el activo en 10
+        let mut log = Log::new(path, config.clone()).await.unwrap();
+        for i in 0..10u64 {
+            log.append(Record {
+                value: format!("registro {}", i).into_bytes(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+        }
+        // en disco el activo y un sellado, los otros cuatro en el object store
+        assert_eq!(count(dir.path(), "store"), 2);
+        assert_eq!(count(&remote.path().join("log"), "store"), 4);
+        assert_eq!(log.lowest_offset().await.unwrap(), 0);
+
+        async fn check(log: &Log) {
+            for i in 0..10u64 {
+                let value = log.read(i).await.unwrap().value;
+                assert_eq!(value, format!("registro {}", i).into_bytes());
+            }
+        }
+        check(&log).await;
+        log.close().await.unwrap();
+
+        // al reabrir se acuerda de lo que está allá
+        let mut log = Log::new(path, config.clone()).await.unwrap();
+        check(&log).await;
+        assert!(log.read(10).await.is_err());
+
+        // la retención también borra los remotos
+        log.truncate(3).await.unwrap();
+        assert_eq!(log.lowest_offset().await.unwrap(), 4);
+        assert_eq!(count(&remote.path().join("log"), "store"), 2);
+        assert!(log.read(1).await.is_err());
+        assert_eq!(log.read(4).await.unwrap().value, b"registro 4".to_vec());
+
+        log.remove().await.unwrap();
+        assert_eq!(count(&remote.path().join("log"), "store"), 0);
+    }
+
+    #[tokio::test]
+    async fn snapshot_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        std::fs::create_dir(&path).unwrap();
+        let path = path.to_str().unwrap();
+        let archive = dir.path().join("log.tar");
+        let archive = archive.to_str().unwrap();
+
+        let mut log = Log::new(path, config()).await.unwrap();
+        for value in VALUES {
+            log.append(Record {
+                value: value.to_vec(),
+                ..Record::default()
+            })
+            .await
+            .unwrap();
+        }
+        // lo que se escribe después del checkpoint no entra, y lo que se
+        // borra después sí
+        let snapshot = log.checkpoint().await.unwrap();
+        log.append(Record {
+            value: b"despues".to_vec(),
+            ..Record::default()
+        })
+        .await
+        .unwrap();
+        log.truncate(1).await.unwrap();
+        assert!(log.read(0).await.is_err());
+        let manifest = snapshot.write(archive).await.unwrap();
+        assert_eq!(manifest.segments[0].base_offset, 0);
+        assert!(!Path::new(path).join("0.store").exists());
+
+        let restored = dir.path().join("restored");
+        let restored = restored.to_str().unwrap();
+        let mut copy = Log::restore(archive, restored, config()).await.unwrap();
+        for (i, value) in VALUES.iter().enumerate() {
+            assert_eq!(copy.read(i as u64).await.unwrap().value, value.to_vec());
+        }
+        assert!(copy.read(VALUES.len() as u64).await.is_err());
+        let off = copy.append(Record::default()).await.unwrap();
+        assert_eq!(off, VALUES.len() as u64);
+        copy.close().await.unwrap();
+
+        // no se restaura encima de un log
+        let err = Log::restore(archive, restored, config()).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        // un tar cortado no deja nada a medias
+        let data = std::fs::read(archive).unwrap();
+        std::fs::write(archive, &data[..data.len() / 2]).unwrap();
+        let other = dir.path().join("other");
+        let other = other.to_str().unwrap();
+        assert!(Log::restore(archive, other, config()).await.is_err());
+        assert!(!Path::new(other).exists());
+        assert!(!dir.path().join("other.restore").exists());
+
+        // un snapshot de ahora sí trae lo último
+        let log = RwLock::new(log);
+        Log::snapshot(&log, archive).await.unwrap();
+        let copy = Log::restore(archive, other, config()).await.unwrap();
+        let last = copy.read(VALUES.len() as u64).await.unwrap();
+        assert_eq!(last.value, b"despues".to_vec());
+        assert!(copy.read(1).await.is_err());
+        log.write().await.close().await.unwrap();
+    }
+}
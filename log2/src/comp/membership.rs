@@ -0,0 +1,606 @@
+use crate::comp::config::MembershipConfig;
+use crate::comp::record::{
+    gossip_message, GossipAck, GossipMember, GossipMessage, GossipPing, GossipPingReq, GossipSync,
+};
+use prost::Message;
+use rand::seq::SliceRandom;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep, timeout, Instant};
+
+// valores de GossipMember.status
+pub const MEMBER_ALIVE: u32 = 0;
+pub const MEMBER_SUSPECT: u32 = 1;
+pub const MEMBER_DEAD: u32 = 2;
+pub const MEMBER_LEFT: u32 = 3;
+
+// tag donde cada nodo anuncia su dirección de gRPC
+pub const RPC_ADDR_TAG: &str = "rpc_addr";
+
+// cuantos cambios van de aventón en cada mensaje
+const MAX_PIGGYBACK: usize = 8;
+// cada cambio se manda RETRANSMIT_MULT * log2(n + 1) veces
+const RETRANSMIT_MULT: usize = 3;
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+// A quien le avisamos cuando un nodo entra o sale del cluster, por ejemplo
+// el Replicator para empezar o dejar de replicar.
+#[tonic::async_trait]
+pub trait Handler: Send + Sync + 'static {
+    async fn join(&self, name: &str, addr: &str) -> io::Result<()>;
+    async fn leave(&self, name: &str) -> io::Result<()>;
+}
+
+enum Event {
+    Join(String, String),
+    Leave(String),
+}
+
+struct Member {
+    state: GossipMember,
+    suspected_at: Option<Instant>,
+}
+
+struct Broadcast {
+    member: GossipMember,
+    transmits: usize,
+}
+
+struct State {
+    incarnation: u64,
+    members: HashMap<String, Member>,
+    broadcasts: Vec<Broadcast>,
+    acks: HashMap<u64, oneshot::Sender<()>>,
+    seq: u64,
+    left: bool,
+}
+
+fn is_alive(status: u32) -> bool {
+    status == MEMBER_ALIVE || status == MEMBER_SUSPECT
+}
+
+// Membresía estilo SWIM: cada probe_interval le hacemos ping a un miembro, si
+// no contesta le pedimos a otros que lo intenten y si tampoco lo marcamos como
+// sospechoso. Los cambios viajan pegados a los mensajes y con un sync completo
+// de vez en cuando.
+pub struct Membership {
+    config: MembershipConfig,
+    addr: String,
+    socket: UdpSocket,
+    state: Mutex<State>,
+    events: mpsc::UnboundedSender<Event>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Membership {
+    pub async fn new(
+        config: MembershipConfig,
+        handler: Arc<dyn Handler>,
+    ) -> io::Result<Arc<Membership>> {
+        let socket = UdpSocket::bind(&config.bind_addr).await?;
+        let addr = socket.local_addr()?.to_string();
+
+        let mut members = HashMap::new();
+        members.insert(
+            config.node_name.clone(),
+            Member {
+                state: GossipMember {
+                    name: config.node_name.clone(),
+                    addr: addr.clone(),
+                    tags: config.tags.clone(),
+                    incarnation: 0,
+                    status: MEMBER_ALIVE,
+                },
+                suspected_at: None,
+            },
+        );
+
+        let (events, rx) = mpsc::unbounded_channel();
+        let membership = Arc::new(Membership {
+            config,
+            addr,
+            socket,
+            state: Mutex::new(State {
+                incarnation: 0,
+                members,
+                broadcasts: vec![],
+                acks: HashMap::new(),
+                seq: 0,
+                left: false,
+            }),
+            events,
+            tasks: Mutex::new(vec![]),
+        });
+
+        let tasks = vec![
+            tokio::spawn(run_events(rx, handler)),
+            tokio::spawn(Arc::clone(&membership).run_receiver()),
+            tokio::spawn(Arc::clone(&membership).run_prober()),
+            tokio::spawn(Arc::clone(&membership).run_syncer()),
+        ];
+        *membership.tasks.lock().unwrap() = tasks;
+
+        membership.join_seeds().await;
+        Ok(membership)
+    }
+
+    // dirección real del gossip, sirve cuando bind_addr usa el puerto 0
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    // los miembros vivos, incluyendo a este nodo
+    #[cfg(test)]
+    pub fn members(&self) -> Vec<GossipMember> {
+        let st = self.state.lock().unwrap();
+        let mut members: Vec<GossipMember> = st
+            .members
+            .values()
+            .filter(|m| is_alive(m.state.status))
+            .map(|m| m.state.clone())
+            .collect();
+        members.sort_by(|a, b| a.name.cmp(&b.name));
+        members
+    }
+
+    // Avisa a los demás que nos vamos y deja de participar.
+    pub async fn leave(&self) -> io::Result<()> {
+        let (msg, peers) = {
+            let mut st = self.state.lock().unwrap();
+            st.left = true;
+            st.incarnation += 1;
+            let me = st.members.get_mut(&self.config.node_name).unwrap();
+            me.state.incarnation += 1;
+            me.state.status = MEMBER_LEFT;
+            let sync = GossipSync {
+                members: vec![me.state.clone()],
+                reply: false,
+            };
+            (
+                GossipMessage {
+                    kind: Some(gossip_message::Kind::Sync(sync)),
+                    updates: vec![],
+                },
+                alive_peers(&st, &self.config.node_name),
+            )
+        };
+        for peer in peers {
+            let _ = self.send_raw(&peer.addr, &msg).await;
+        }
+        self.close();
+        Ok(())
+    }
+
+    // Deja de escuchar sin avisar, para los demás es como si se cayera.
+    pub fn close(&self) {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+    }
+
+    async fn join_seeds(&self) {
+        let msg = self.sync_message(true);
+        for seed in &self.config.start_join_addrs {
+            if let Err(e) = self.send_raw(seed, &msg).await {
+                tracing::warn!(seed, error = %e, "no se pudo contactar al seed");
+            }
+        }
+    }
+
+    fn sync_message(&self, reply: bool) -> GossipMessage {
+        let st = self.state.lock().unwrap();
+        GossipMessage {
+            kind: Some(gossip_message::Kind::Sync(GossipSync {
+                members: st.members.values().map(|m| m.state.clone()).collect(),
+                reply,
+            })),
+            updates: vec![],
+        }
+    }
+
+    async fn send_raw(&self, addr: &str, msg: &GossipMessage) -> io::Result<()> {
+        self.socket.send_to(&msg.encode_to_vec(), addr).await?;
+        Ok(())
+    }
+
+    // manda kind con los cambios pendientes de aventón
+    async fn send(&self, addr: &str, kind: gossip_message::Kind) -> io::Result<()> {
+        let updates = {
+            let mut st = self.state.lock().unwrap();
+            let n = st.members.values().filter(|m| is_alive(m.state.status)).count();
+            let limit = RETRANSMIT_MULT * (usize::BITS - n.leading_zeros()) as usize;
+            let mut updates = vec![];
+            for b in st.broadcasts.iter_mut().take(MAX_PIGGYBACK) {
+                updates.push(b.member.clone());
+                b.transmits += 1;
+            }
+            st.broadcasts.retain(|b| b.transmits < limit);
+            updates
+        };
+        let msg = GossipMessage {
+            kind: Some(kind),
+            updates,
+        };
+        self.send_raw(addr, &msg).await
+    }
+
+    fn next_seq(&self) -> (u64, oneshot::Receiver<()>) {
+        let mut st = self.state.lock().unwrap();
+        st.seq += 1;
+        let seq = st.seq;
+        let (tx, rx) = oneshot::channel();
+        st.acks.insert(seq, tx);
+        (seq, rx)
+    }
+
+    // Aplica lo que otro nodo dice de un miembro. Gana la incarnation más
+    // alta y, con la misma, el peor estado. Si hablan mal de nosotros lo
+    // desmentimos con una incarnation nueva.
+    fn merge(&self, st: &mut State, update: GossipMember) {
+        if update.name == self.config.node_name {
+            if st.left {
+                return;
+            }
+            let refute = update.incarnation > st.incarnation
+                || (update.incarnation == st.incarnation && update.status != MEMBER_ALIVE);
+            if refute {
+                st.incarnation = update.incarnation + 1;
+                let me = st.members.get_mut(&self.config.node_name).unwrap();
+                me.state.incarnation = st.incarnation;
+                me.state.status = MEMBER_ALIVE;
+                let member = me.state.clone();
+                queue(st, member);
+            }
+            return;
+        }
+
+        let was_alive = match st.members.get(&update.name) {
+            Some(m) => {
+                let newer = update.incarnation > m.state.incarnation
+                    || (update.incarnation == m.state.incarnation
+                        && update.status > m.state.status);
+                if !newer {
+                    return;
+                }
+                is_alive(m.state.status)
+            }
+            None => false,
+        };
+
+        let now_alive = is_alive(update.status);
+        if !was_alive && now_alive {
+            let addr = update.tags.get(RPC_ADDR_TAG).cloned().unwrap_or_default();
+            let _ = self.events.send(Event::Join(update.name.clone(), addr));
+        } else if was_alive && !now_alive {
+            let _ = self.events.send(Event::Leave(update.name.clone()));
+        }
+
+        let suspected_at = (update.status == MEMBER_SUSPECT).then(Instant::now);
+        st.members.insert(
+            update.name.clone(),
+            Member {
+                state: update.clone(),
+                suspected_at,
+            },
+        );
+        queue(st, update);
+    }
+
+    async fn run_receiver(self: Arc<Self>) {
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        loop {
+            let (n, from) = match self.socket.recv_from(&mut buf).await {
+                Ok(r) => r,
+                Err(_) => continue,
+            };
+            let Ok(msg) = GossipMessage::decode(&buf[..n]) else {
+                continue;
+            };
+            if let Err(e) = Arc::clone(&self).handle(msg, from).await {
+                tracing::warn!(%from, error = %e, "error con el mensaje de gossip");
+            }
+        }
+    }
+
+    async fn handle(self: Arc<Self>, msg: GossipMessage, from: SocketAddr) -> io::Result<()> {
+        {
+            let mut st = self.state.lock().unwrap();
+            for update in msg.updates {
+                self.merge(&mut st, update);
+            }
+        }
+
+        let from = from.to_string();
+        match msg.kind {
+            Some(gossip_message::Kind::Ping(ping)) => {
+                if ping.target == self.config.node_name {
+                    let ack = gossip_message::Kind::Ack(GossipAck { seq: ping.seq });
+                    self.send(&from, ack).await?;
+                }
+            }
+            Some(gossip_message::Kind::Ack(ack)) => {
+                if let Some(tx) = self.state.lock().unwrap().acks.remove(&ack.seq) {
+                    let _ = tx.send(());
+                }
+            }
+            Some(gossip_message::Kind::PingReq(req)) => {
+                // el ping puede tardar, no detenemos al receptor por eso
+                let membership = Arc::clone(&self);
+                tokio::spawn(async move {
+                    let (seq, rx) = membership.next_seq();
+                    let ping = gossip_message::Kind::Ping(GossipPing {
+                        seq,
+                        target: req.target,
+                    });
+                    if membership.send(&req.target_addr, ping).await.is_err() {
+                        return;
+                    }
+                    if let Ok(Ok(())) = timeout(membership.config.probe_timeout, rx).await {
+                        let ack = gossip_message::Kind::Ack(GossipAck { seq: req.seq });
+                        let _ = membership.send(&from, ack).await;
+                    }
+                    membership.state.lock().unwrap().acks.remove(&seq);
+                });
+            }
+            Some(gossip_message::Kind::Sync(sync)) => {
+                {
+                    let mut st = self.state.lock().unwrap();
+                    for member in sync.members {
+                        self.merge(&mut st, member);
+                    }
+                }
+                if sync.reply {
+                    self.send_raw(&from, &self.sync_message(false)).await?;
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    async fn run_prober(self: Arc<Self>) {
+        let mut ticker = interval(self.config.probe_interval);
+        let mut pending: Vec<GossipMember> = vec![];
+        loop {
+            ticker.tick().await;
+            self.expire_suspects();
+
+            // recorremos a los miembros en orden aleatorio, uno por tick
+            if pending.is_empty() {
+                let st = self.state.lock().unwrap();
+                pending = alive_peers(&st, &self.config.node_name);
+                pending.shuffle(&mut rand::thread_rng());
+            }
+            let Some(target) = pending.pop() else {
+                // solos: seguimos tocando a los seeds hasta que alguien conteste
+                self.join_seeds().await;
+                continue;
+            };
+            self.probe(target).await;
+        }
+    }
+
+    async fn probe(&self, target: GossipMember) {
+        let (seq, mut rx) = self.next_seq();
+        let ping = gossip_message::Kind::Ping(GossipPing {
+            seq,
+            target: target.name.clone(),
+        });
+        let _ = self.send(&target.addr, ping).await;
+
+        let acked = match timeout(self.config.probe_timeout, &mut rx).await {
+            Ok(res) => res.is_ok(),
+            Err(_) => {
+                let helpers: Vec<GossipMember> = {
+                    let st = self.state.lock().unwrap();
+                    alive_peers(&st, &self.config.node_name)
+                        .into_iter()
+                        .filter(|m| m.name != target.name)
+                        .collect::<Vec<_>>()
+                        .choose_multiple(&mut rand::thread_rng(), self.config.indirect_checks)
+                        .cloned()
+                        .collect()
+                };
+                for helper in helpers {
+                    let req = gossip_message::Kind::PingReq(GossipPingReq {
+                        seq,
+                        target: target.name.clone(),
+                        target_addr: target.addr.clone(),
+                    });
+                    let _ = self.send(&helper.addr, req).await;
+                }
+                // los pings indirectos cuestan un viaje más
+                matches!(timeout(self.config.probe_timeout * 2, rx).await, Ok(Ok(())))
+            }
+        };
+
+        let mut st = self.state.lock().unwrap();
+        st.acks.remove(&seq);
+        if acked {
+            return;
+        }
+        if let Some(m) = st.members.get(&target.name) {
+            if m.state.status == MEMBER_ALIVE && m.state.incarnation == target.incarnation {
+                let mut suspect = m.state.clone();
+                suspect.status = MEMBER_SUSPECT;
+                self.merge(&mut st, suspect);
+            }
+        }
+    }
+
+    fn expire_suspects(&self) {
+        let mut st = self.state.lock().unwrap();
+        let dead: Vec<GossipMember> = st
+            .members
+            .values()
+            .filter(|m| {
+                m.suspected_at
+                    .is_some_and(|at| at.elapsed() >= self.config.suspicion_timeout)
+            })
+            .map(|m| GossipMember {
+                status: MEMBER_DEAD,
+                ..m.state.clone()
+            })
+            .collect();
+        for member in dead {
+            self.merge(&mut st, member);
+        }
+    }
+
+    async fn run_syncer(self: Arc<Self>) {
+        loop {
+            sleep(self.config.sync_interval).await;
+            let peer = {
+                let st = self.state.lock().unwrap();
+                alive_peers(&st, &self.config.node_name)
+                    .choose(&mut rand::thread_rng())
+                    .cloned()
+            };
+            if let Some(peer) = peer {
+                let _ = self.send_raw(&peer.addr, &self.sync_message(true)).await;
+            }
+        }
+    }
+}
+
+fn alive_peers(st: &State, local: &str) -> Vec<GossipMember> {
+    st.members
+        .values()
+        .filter(|m| m.state.name != local && is_alive(m.state.status))
+        .map(|m| m.state.clone())
+        .collect()
+}
+
+fn queue(st: &mut State, member: GossipMember) {
+    // un cambio nuevo de un miembro reemplaza al que estaba pendiente
+    st.broadcasts.retain(|b| b.member.name != member.name);
+    st.broadcasts.insert(
+        0,
+        Broadcast {
+            member,
+            transmits: 0,
+        },
+    );
+}
+
+// los eventos se entregan en orden y sin detener al gossip
+async fn run_events(mut rx: mpsc::UnboundedReceiver<Event>, handler: Arc<dyn Handler>) {
+    while let Some(event) = rx.recv().await {
+        let res = match &event {
+            Event::Join(name, addr) => handler.join(name, addr).await,
+            Event::Leave(name) => handler.leave(name).await,
+        };
+        if let Err(e) = res {
+            tracing::error!(error = %e, "el handler de membership falló");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Duration;
+
+    #[derive(Default)]
+    struct TestHandler {
+        joins: Mutex<HashMap<String, String>>,
+        leaves: Mutex<Vec<String>>,
+    }
+
+    #[tonic::async_trait]
+    impl Handler for TestHandler {
+        async fn join(&self, name: &str, addr: &str) -> io::Result<()> {
+            self.joins
+                .lock()
+                .unwrap()
+                .insert(name.to_string(), addr.to_string());
+            Ok(())
+        }
+
+        async fn leave(&self, name: &str) -> io::Result<()> {
+            self.leaves.lock().unwrap().push(name.to_string());
+            Ok(())
+        }
+    }
+
+    async fn member(id: usize, seeds: Vec<String>) -> (Arc<Membership>, Arc<TestHandler>) {
+        let handler = Arc::new(TestHandler::default());
+        let config = MembershipConfig {
+            node_name: id.to_string(),
+            bind_addr: "127.0.0.1:0".to_string(),
+            tags: HashMap::from([(RPC_ADDR_TAG.to_string(), format!("rpc-{}", id))]),
+            start_join_addrs: seeds,
+            probe_interval: Duration::from_millis(50),
+            probe_timeout: Duration::from_millis(20),
+            suspicion_timeout: Duration::from_millis(200),
+            sync_interval: Duration::from_millis(100),
+            ..MembershipConfig::default()
+        };
+        let m = Membership::new(config, handler.clone()).await.unwrap();
+        (m, handler)
+    }
+
+    async fn eventually(f: impl Fn() -> bool) -> bool {
+        for _ in 0..150 {
+            if f() {
+                return true;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        f()
+    }
+
+    async fn cluster() -> Vec<(Arc<Membership>, Arc<TestHandler>)> {
+        let mut members = vec![member(0, vec![]).await];
+        let seed = members[0].0.addr().to_string();
+        for i in 1..3 {
+            members.push(member(i, vec![seed.clone()]).await);
+        }
+        for (m, _) in &members {
+            assert!(eventually(|| m.members().len() == 3).await);
+        }
+        members
+    }
+
+    #[tokio::test]
+    async fn join_and_leave() {
+        let members = cluster().await;
+
+        let (_, handler) = &members[0];
+        let joins = handler.joins.lock().unwrap().clone();
+        assert_eq!(joins.len(), 2);
+        assert_eq!(joins["1"], "rpc-1");
+        assert_eq!(joins["2"], "rpc-2");
+
+        members[2].0.leave().await.unwrap();
+        for (m, h) in &members[..2] {
+            assert!(eventually(|| h.leaves.lock().unwrap().as_slice() == ["2"]).await);
+            assert_eq!(m.members().len(), 2);
+        }
+
+        for (m, _) in &members {
+            m.close();
+        }
+    }
+
+    #[tokio::test]
+    async fn detect_failure() {
+        let members = cluster().await;
+
+        // se cae sin avisar, los demás lo tienen que descubrir con los pings
+        members[1].0.close();
+        for (m, h) in [&members[0], &members[2]] {
+            assert!(eventually(|| h.leaves.lock().unwrap().as_slice() == ["1"]).await);
+            assert_eq!(m.members().len(), 2);
+        }
+
+        for (m, _) in &members {
+            m.close();
+        }
+    }
+}
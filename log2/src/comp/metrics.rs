@@ -0,0 +1,212 @@
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::LazyLock;
+use std::task::{Context, Poll};
+use tonic::codegen::http;
+use tonic::Code;
+use tower::{Layer, Service};
+
+// Todas las métricas del proceso, en un solo registry que se expone en
+// /metrics (ver comp/gateway.rs). Los gauges de segmentos y bytes suman los
+// de todos los logs abiertos: el del nodo, las particiones y los internos.
+pub static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+pub static APPENDS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(IntCounter::new("log_appends_total", "Records appended to a log").unwrap())
+});
+
+pub static APPEND_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+    register(
+        Histogram::with_opts(HistogramOpts::new(
+            "log_append_duration_seconds",
+            "Time to append a record, including compression and encryption",
+        ))
+        .unwrap(),
+    )
+});
+
+pub static READS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(IntCounter::new("log_reads_total", "Records read from a log").unwrap())
+});
+
+pub static READ_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+    register(
+        Histogram::with_opts(HistogramOpts::new(
+            "log_read_duration_seconds",
+            "Time to read a record, including fetching remote segments",
+        ))
+        .unwrap(),
+    )
+});
+
+pub static ROLLS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(
+        IntCounter::new(
+            "log_segment_rolls_total",
+            "Segments sealed because they were full",
+        )
+        .unwrap(),
+    )
+});
+
+pub static SEGMENTS: LazyLock<IntGauge> =
+    LazyLock::new(|| register(IntGauge::new("log_segments", "Segments on local storage").unwrap()));
+
+pub static STORE_BYTES: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(
+        IntGauge::new(
+            "log_store_bytes",
+            "Bytes in the store files on local storage",
+        )
+        .unwrap(),
+    )
+});
+
+pub static RPCS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "rpc_requests_total",
+                "RPCs handled, by method and status code",
+            ),
+            &["method", "code"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static RPC_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "rpc_duration_seconds",
+                "Time to handle an RPC; for streams, to open it",
+            ),
+            &["method"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static STREAMS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new("rpc_active_streams", "Streams that are still open"),
+            &["method"],
+        )
+        .unwrap(),
+    )
+});
+
+fn register<M: prometheus::core::Collector + Clone + 'static>(metric: M) -> M {
+    REGISTRY.register(Box::new(metric.clone())).unwrap();
+    metric
+}
+
+// para que log_segments y log_store_bytes cuadren, el Log avisa cuando un
+// segmento entra o sale del disco
+pub fn segment_opened(store_bytes: u64) {
+    SEGMENTS.inc();
+    STORE_BYTES.add(store_bytes as i64);
+}
+
+pub fn segment_closed(store_bytes: u64) {
+    SEGMENTS.dec();
+    STORE_BYTES.sub(store_bytes as i64);
+}
+
+// Mide cada RPC: cuánto tardó en responder y con qué código. Cuando un
+// handler regresa error tonic manda el grpc-status en los headers; si no
+// viene, la llamada salió bien (o el stream se abrió bien).
+#[derive(Debug, Clone, Default)]
+pub struct RpcMetricsLayer;
+
+impl<S> Layer<S> for RpcMetricsLayer {
+    type Service = RpcMetrics<S>;
+
+    fn layer(&self, inner: S) -> RpcMetrics<S> {
+        RpcMetrics { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RpcMetrics<S> {
+    inner: S,
+}
+
+impl<S, B, RB> Service<http::Request<B>> for RpcMetrics<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<RB>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        // /record.Log/Produce -> Produce; lo demás junto, que no nos llenen
+        // de labels con paths inventados
+        let method = match req.uri().path().strip_prefix("/record.") {
+            Some(path) => path.rsplit('/').next().unwrap_or_default().to_string(),
+            None => "other".to_string(),
+        };
+        let timer = RPC_SECONDS.with_label_values(&[&method]).start_timer();
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            timer.observe_duration();
+            let code = res
+                .headers()
+                .get("grpc-status")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<i32>().ok())
+                .map(Code::from)
+                .unwrap_or(Code::Ok);
+            RPCS.with_label_values(&[&method, &format!("{:?}", code)])
+                .inc();
+            Ok(res)
+        })
+    }
+}
+
+// cuenta un stream abierto mientras viva; se mueve a la tarea que lo atiende
+pub struct StreamGuard(IntGauge);
+
+impl StreamGuard {
+    pub fn new(method: &str) -> Self {
+        let gauge = STREAMS.with_label_values(&[method]);
+        gauge.inc();
+        StreamGuard(gauge)
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+// el texto que espera Prometheus
+pub fn gather() -> String {
+    // que salgan aunque todavía no se hayan usado
+    LazyLock::force(&APPENDS);
+    LazyLock::force(&APPEND_SECONDS);
+    LazyLock::force(&READS);
+    LazyLock::force(&READ_SECONDS);
+    LazyLock::force(&ROLLS);
+    LazyLock::force(&SEGMENTS);
+    LazyLock::force(&STORE_BYTES);
+    let mut buf = vec![];
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buf)
+        .unwrap();
+    String::from_utf8(buf).unwrap()
+}
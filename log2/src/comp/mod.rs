@@ -1,6 +1,29 @@
+pub mod admin;
+pub mod auth;
+pub mod broker;
+pub mod compression;
 pub mod config;
+pub mod distributed;
+pub mod encryption;
+pub mod filter;
+pub mod gateway;
+pub mod health;
 pub mod index;
+pub mod loadbalance;
 pub mod log;
+pub mod membership;
+pub mod metrics;
+pub mod mux;
+pub mod offsets;
+pub mod quota;
+pub mod raft;
 pub mod record;
+pub mod replicator;
 pub mod segments;
+pub mod server;
+pub mod settings;
+pub mod snapshot;
+pub mod storage;
 pub mod store;
+pub mod tiered;
+pub mod trace;
@@ -0,0 +1,132 @@
+use crate::comp::raft::{StreamLayer, RAFT_RPC};
+use std::io;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Duration};
+use tokio_stream::wrappers::ReceiverStream;
+
+// lo que esperamos el primer byte de una conexión antes de soltarla
+const PEEK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Raft y gRPC en el mismo puerto. Las conexiones de raft empiezan con el byte
+// RAFT_RPC; gRPC empieza con el preface de HTTP/2 ("PRI ..."), así que con ver
+// el primer byte sin consumirlo basta para saber a quién le toca.
+pub struct Mux {
+    listener: TcpListener,
+}
+
+impl Mux {
+    pub async fn bind(addr: &str) -> io::Result<Self> {
+        Ok(Mux {
+            listener: TcpListener::bind(addr).await?,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<String> {
+        Ok(self.listener.local_addr()?.to_string())
+    }
+
+    // Empieza a aceptar y regresa las dos mitades: el StreamLayer para raft
+    // y las conexiones de gRPC para Server::serve_with_incoming.
+    pub fn split(self) -> io::Result<(StreamLayer, ReceiverStream<io::Result<TcpStream>>)> {
+        let addr = self.local_addr()?;
+        let (raft_tx, raft_rx) = mpsc::channel(16);
+        let (grpc_tx, grpc_rx) = mpsc::channel(16);
+
+        tokio::spawn(async move {
+            loop {
+                let conn = match self.listener.accept().await {
+                    Ok((conn, _)) => conn,
+                    Err(_) => continue,
+                };
+                if raft_tx.is_closed() && grpc_tx.is_closed() {
+                    return;
+                }
+                // el peek puede tardar, no detenemos al accept por eso
+                let (raft_tx, grpc_tx) = (raft_tx.clone(), grpc_tx.clone());
+                tokio::spawn(async move {
+                    let mut b = [0u8; 1];
+                    match timeout(PEEK_TIMEOUT, conn.peek(&mut b)).await {
+                        Ok(Ok(1)) if b[0] == RAFT_RPC => {
+                            let _ = raft_tx.send(conn).await;
+                        }
+                        Ok(Ok(1)) => {
+                            let _ = grpc_tx.send(Ok(conn)).await;
+                        }
+                        _ => {}
+                    }
+                });
+            }
+        });
+
+        Ok((
+            StreamLayer::from_mux(addr, raft_rx),
+            ReceiverStream::new(grpc_rx),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comp::config::test_config;
+    use crate::comp::log::Log;
+    use crate::comp::record::log_client::LogClient;
+    use crate::comp::record::{ConsumeRequest, ProduceRequest, Record};
+    use crate::comp::server::{new_grpc_server, CommitLog, ServerConfig};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::RwLock;
+    use tonic::transport::Server;
+
+    #[tokio::test]
+    async fn raft_and_grpc_share_a_port() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config();
+        let log = Log::new(dir.path().to_str().unwrap(), config).await.unwrap();
+        let commit_log: Arc<dyn CommitLog> = Arc::new(RwLock::new(log));
+
+        let mux = Mux::bind("127.0.0.1:0").await.unwrap();
+        let addr = mux.local_addr().unwrap();
+        let (layer, grpc) = mux.split().unwrap();
+        assert_eq!(layer.local_addr().unwrap(), addr);
+        tokio::spawn(
+            Server::builder()
+                .add_service(new_grpc_server(ServerConfig::new(commit_log)))
+                .serve_with_incoming(grpc),
+        );
+
+        let mut client = LogClient::connect(format!("http://{}", addr)).await.unwrap();
+        let offset = client
+            .produce(ProduceRequest {
+                record: Some(Record {
+                    value: b"por el mux".to_vec(),
+                    ..Record::default()
+                }),
+                ..ProduceRequest::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .offset;
+
+        let mut raft_conn = StreamLayer::dial(&addr).await.unwrap();
+        let mut accepted = layer.accept().await.unwrap();
+        raft_conn.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        accepted.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        let got = client
+            .consume(ConsumeRequest {
+                offset,
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .record
+            .unwrap();
+        assert_eq!(got.value, b"por el mux".to_vec());
+    }
+}
@@ -0,0 +1,243 @@
+use crate::comp::config::Config;
+use crate::comp::log::Log;
+use crate::comp::record::{CommittedOffset, Record};
+use prost::Message;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+
+// cuantas entradas dejamos crecer el log antes de pensar en compactarlo
+const COMPACT_MIN: u64 = 1024;
+
+// (grupo, topic, partición)
+type Key = (String, String, u32);
+
+struct Inner {
+    log: Log,
+    offsets: HashMap<Key, u64>,
+    // entradas en el log, contando las que ya quedaron viejas
+    entries: u64,
+}
+
+// Los offsets que cada grupo de consumidores va guardando. Cada commit es un
+// registro en un Log interno y al abrir se vuelve a leer todo; como solo nos
+// importa el último commit de cada llave, de vez en cuando el log se reescribe
+// con únicamente esos (compactación).
+//
+// En disco: <dir>/log es el log vigente. Compactar escribe <dir>/compact, mueve
+// el log viejo a <dir>/old y pone el compactado en su lugar, así si el proceso
+// se cae a medias al abrir siempre queda un log completo.
+pub struct OffsetStore {
+    dir: PathBuf,
+    config: Config,
+    inner: Mutex<Inner>,
+}
+
+impl OffsetStore {
+    pub async fn new(dir: &str, config: Config) -> io::Result<Self> {
+        // se compacta solo y es chiquito, no tiene caso mandarlo al object store
+        let mut config = config;
+        config.tiering = None;
+        config.validate()?;
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)?;
+        let (log_dir, compact_dir, old_dir) =
+            (dir.join("log"), dir.join("compact"), dir.join("old"));
+        if compact_dir.exists() {
+            if log_dir.exists() {
+                // no se terminó de escribir, el log vigente sigue siendo el bueno
+                fs::remove_dir_all(&compact_dir)?;
+            } else {
+                fs::rename(&compact_dir, &log_dir)?;
+            }
+        }
+        if old_dir.exists() {
+            fs::remove_dir_all(&old_dir)?;
+        }
+        fs::create_dir_all(&log_dir)?;
+
+        let log = Log::new(log_dir.to_str().unwrap(), config.clone()).await?;
+        let mut offsets = HashMap::new();
+        let mut entries = 0;
+        let mut next = log.lowest_offset().await?;
+        loop {
+            let record = match log.read(next).await {
+                Ok(record) => record,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+                Err(e) => return Err(e),
+            };
+            let c = CommittedOffset::decode(record.value.as_slice())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            offsets.insert((c.group, c.topic, c.partition), c.offset);
+            entries += 1;
+            next += 1;
+        }
+
+        Ok(OffsetStore {
+            dir,
+            config,
+            inner: Mutex::new(Inner {
+                log,
+                offsets,
+                entries,
+            }),
+        })
+    }
+
+    pub async fn commit(
+        &self,
+        group: &str,
+        topic: &str,
+        partition: u32,
+        offset: u64,
+    ) -> io::Result<()> {
+        let mut inner = self.inner.lock().await;
+        let c = CommittedOffset {
+            group: group.to_string(),
+            topic: topic.to_string(),
+            partition,
+            offset,
+        };
+        inner.log.append(to_record(&c)).await?;
+        inner
+            .offsets
+            .insert((c.group, c.topic, c.partition), c.offset);
+        inner.entries += 1;
+
+        // más de la mitad del log ya no sirve
+        if inner.entries >= COMPACT_MIN && inner.entries > 2 * inner.offsets.len() as u64 {
+            self.compact_locked(&mut inner).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn fetch(&self, group: &str, topic: &str, partition: u32) -> Option<u64> {
+        let key = (group.to_string(), topic.to_string(), partition);
+        self.inner.lock().await.offsets.get(&key).copied()
+    }
+
+    #[cfg(test)]
+    pub async fn compact(&self) -> io::Result<()> {
+        let mut inner = self.inner.lock().await;
+        self.compact_locked(&mut inner).await
+    }
+
+    async fn compact_locked(&self, inner: &mut Inner) -> io::Result<()> {
+        let (log_dir, compact_dir, old_dir) = (
+            self.dir.join("log"),
+            self.dir.join("compact"),
+            self.dir.join("old"),
+        );
+        if compact_dir.exists() {
+            fs::remove_dir_all(&compact_dir)?;
+        }
+        fs::create_dir_all(&compact_dir)?;
+
+        let mut compacted = Log::new(compact_dir.to_str().unwrap(), self.config.clone()).await?;
+        let mut live: Vec<(&Key, &u64)> = inner.offsets.iter().collect();
+        live.sort();
+        for ((group, topic, partition), offset) in live {
+            let c = CommittedOffset {
+                group: group.clone(),
+                topic: topic.clone(),
+                partition: *partition,
+                offset: *offset,
+            };
+            compacted.append(to_record(&c)).await?;
+        }
+        compacted.close().await?;
+        inner.log.close().await?;
+
+        fs::rename(&log_dir, &old_dir)?;
+        fs::rename(&compact_dir, &log_dir)?;
+        fs::remove_dir_all(&old_dir)?;
+
+        inner.log = Log::new(log_dir.to_str().unwrap(), self.config.clone()).await?;
+        inner.entries = inner.offsets.len() as u64;
+        Ok(())
+    }
+
+    pub async fn close(&self) -> io::Result<()> {
+        self.inner.lock().await.log.close().await
+    }
+}
+
+fn to_record(c: &CommittedOffset) -> Record {
+    Record {
+        value: c.encode_to_vec(),
+        ..Record::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comp::config::test_config;
+
+    #[tokio::test]
+    async fn commit_fetch_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let store = OffsetStore::new(path, test_config()).await.unwrap();
+
+        assert_eq!(store.fetch("g1", "pedidos", 0).await, None);
+        store.commit("g1", "pedidos", 0, 5).await.unwrap();
+        store.commit("g1", "pedidos", 0, 9).await.unwrap();
+        store.commit("g1", "pedidos", 1, 3).await.unwrap();
+        store.commit("g2", "pedidos", 0, 1).await.unwrap();
+        assert_eq!(store.fetch("g1", "pedidos", 0).await, Some(9));
+        assert_eq!(store.fetch("g1", "pedidos", 1).await, Some(3));
+        assert_eq!(store.fetch("g2", "pedidos", 0).await, Some(1));
+        store.close().await.unwrap();
+
+        let store = OffsetStore::new(path, test_config()).await.unwrap();
+        assert_eq!(store.fetch("g1", "pedidos", 0).await, Some(9));
+        assert_eq!(store.fetch("g2", "pedidos", 0).await, Some(1));
+        assert_eq!(store.inner.lock().await.entries, 4);
+    }
+
+    #[tokio::test]
+    async fn compaction_keeps_last_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let store = OffsetStore::new(path, test_config()).await.unwrap();
+
+        for i in 0..COMPACT_MIN {
+            store.commit("g", "t", (i % 2) as u32, i).await.unwrap();
+        }
+        // al llegar a COMPACT_MIN se compactó solo
+        assert_eq!(store.inner.lock().await.entries, 2);
+        assert_eq!(store.fetch("g", "t", 0).await, Some(COMPACT_MIN - 2));
+        assert_eq!(store.fetch("g", "t", 1).await, Some(COMPACT_MIN - 1));
+
+        store.commit("g", "t", 0, 7).await.unwrap();
+        store.close().await.unwrap();
+
+        let store = OffsetStore::new(path, test_config()).await.unwrap();
+        assert_eq!(store.inner.lock().await.entries, 3);
+        assert_eq!(store.fetch("g", "t", 0).await, Some(7));
+        assert_eq!(store.fetch("g", "t", 1).await, Some(COMPACT_MIN - 1));
+        assert!(!dir.path().join("compact").exists());
+        assert!(!dir.path().join("old").exists());
+    }
+
+    #[tokio::test]
+    async fn recover_interrupted_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let store = OffsetStore::new(path, test_config()).await.unwrap();
+        store.commit("g", "t", 0, 1).await.unwrap();
+        store.commit("g", "t", 0, 2).await.unwrap();
+        store.compact().await.unwrap();
+        store.close().await.unwrap();
+
+        // como si se hubiera caído justo después de mover el log viejo
+        fs::rename(dir.path().join("log"), dir.path().join("compact")).unwrap();
+        fs::create_dir_all(dir.path().join("old")).unwrap();
+        let store = OffsetStore::new(path, test_config()).await.unwrap();
+        assert_eq!(store.fetch("g", "t", 0).await, Some(2));
+        assert!(!dir.path().join("old").exists());
+    }
+}
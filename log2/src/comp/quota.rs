@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tonic::Status;
+
+// en los RESOURCE_EXHAUSTED: cuántos milisegundos esperar para reintentar
+pub const RETRY_AFTER: &str = "retry-after-ms";
+
+// Por segundo y por cliente; 0 es sin límite. Se permite una ráfaga de un
+// segundo completo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limit {
+    pub requests_per_sec: u64,
+    pub bytes_per_sec: u64,
+}
+
+impl Limit {
+    pub fn is_unlimited(&self) -> bool {
+        self.requests_per_sec == 0 && self.bytes_per_sec == 0
+    }
+}
+
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    // None si no hay límite
+    fn new(rate: u64, now: Instant) -> Option<Self> {
+        (rate > 0).then_some(Bucket {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last: now,
+        })
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now > self.last {
+            let elapsed = (now - self.last).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+            self.last = now;
+        }
+    }
+
+    // Lo que falta para poder tomar n. Algo más grande que la ráfaga pasa con
+    // el bucket lleno y lo deja en negativo, si no nunca pasaría.
+    fn wait(&self, n: f64) -> Duration {
+        let need = n.min(self.rate);
+        if self.tokens >= need {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((need - self.tokens) / self.rate)
+    }
+}
+
+struct Buckets {
+    requests: Option<Bucket>,
+    bytes: Option<Bucket>,
+}
+
+// Los token buckets de cada cliente (el CN de su certificado, "" sin TLS).
+// Los clientes sin límite propio usan el default.
+pub struct Quotas {
+    default: Limit,
+    clients: HashMap<String, Limit>,
+    buckets: Mutex<HashMap<String, Buckets>>,
+}
+
+impl Quotas {
+    pub fn new(default: Limit, clients: impl IntoIterator<Item = (String, Limit)>) -> Self {
+        Quotas {
+            default,
+            clients: clients.into_iter().collect(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Toma los requests y bytes si los dos alcanzan; si no, no toma nada y
+    // regresa cuánto esperar.
+    pub fn acquire(&self, client: &str, requests: u64, bytes: u64) -> Result<(), Duration> {
+        self.acquire_at(client, requests, bytes, Instant::now())
+    }
+
+    // lo que se supo después, por ejemplo el tamaño de lo que se leyó; puede
+    // dejar al cliente debiendo y lo paga en sus siguientes llamadas
+    pub fn charge(&self, client: &str, bytes: u64) {
+        self.with_buckets(client, Instant::now(), |b| {
+            if let Some(bucket) = &mut b.bytes {
+                bucket.tokens -= bytes as f64;
+            }
+        });
+    }
+
+    fn acquire_at(
+        &self,
+        client: &str,
+        requests: u64,
+        bytes: u64,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        self.with_buckets(client, now, |b| {
+            let wait = [(&b.requests, requests), (&b.bytes, bytes)]
+                .into_iter()
+                .filter_map(|(bucket, n)| bucket.as_ref().map(|bucket| bucket.wait(n as f64)))
+                .max()
+                .unwrap_or_default();
+            if !wait.is_zero() {
+                return Err(wait);
+            }
+            for (bucket, n) in [(&mut b.requests, requests), (&mut b.bytes, bytes)] {
+                if let Some(bucket) = bucket {
+                    bucket.tokens -= n as f64;
+                }
+            }
+            Ok(())
+        })
+        .unwrap_or(Ok(()))
+    }
+
+    // None si el cliente no tiene límite
+    fn with_buckets<T>(
+        &self,
+        client: &str,
+        now: Instant,
+        f: impl FnOnce(&mut Buckets) -> T,
+    ) -> Option<T> {
+        let limit = self.clients.get(client).unwrap_or(&self.default);
+        if limit.is_unlimited() {
+            return None;
+        }
+        let mut buckets = self.buckets.lock().unwrap();
+        let b = buckets
+            .entry(client.to_string())
+            .or_insert_with(|| Buckets {
+                requests: Bucket::new(limit.requests_per_sec, now),
+                bytes: Bucket::new(limit.bytes_per_sec, now),
+            });
+        for bucket in [&mut b.requests, &mut b.bytes].into_iter().flatten() {
+            bucket.refill(now);
+        }
+        Some(f(b))
+    }
+}
+
+pub fn exhausted(client: &str, wait: Duration) -> Status {
+    // redondeado hacia arriba para que el reintento ya pase
+    let ms = wait.as_nanos().div_ceil(1_000_000) as u64;
+    let who = if client.is_empty() {
+        "anonymous"
+    } else {
+        client
+    };
+    let mut status =
+        Status::resource_exhausted(format!("{} is over its quota, retry in {}ms", who, ms));
+    status.metadata_mut().insert(RETRY_AFTER, ms.into());
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_buckets() {
+        let limit = |requests_per_sec, bytes_per_sec| Limit {
+            requests_per_sec,
+            bytes_per_sec,
+        };
+        let quotas = Quotas::new(
+            limit(2, 0),
+            [
+                ("root".to_string(), limit(0, 0)),
+                ("bulk".to_string(), limit(0, 100)),
+            ],
+        );
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // la ráfaga es un segundo completo
+        assert_eq!(quotas.acquire_at("nobody", 1, 0, at(0)), Ok(()));
+        assert_eq!(quotas.acquire_at("nobody", 1, 0, at(0)), Ok(()));
+        assert_eq!(
+            quotas.acquire_at("nobody", 1, 0, at(0)),
+            Err(Duration::from_millis(500))
+        );
+        assert_eq!(
+            quotas.acquire_at("nobody", 1, 0, at(250)),
+            Err(Duration::from_millis(250))
+        );
+        assert_eq!(quotas.acquire_at("nobody", 1, 0, at(500)), Ok(()));
+        // cada cliente tiene sus buckets
+        assert_eq!(quotas.acquire_at("", 1, 0, at(500)), Ok(()));
+        for _ in 0..100 {
+            assert_eq!(quotas.acquire_at("root", 1, 1 << 20, at(0)), Ok(()));
+        }
+
+        // algo más grande que la ráfaga pasa una vez y se paga después
+        assert_eq!(quotas.acquire_at("bulk", 1, 250, at(0)), Ok(()));
+        assert_eq!(
+            quotas.acquire_at("bulk", 1, 10, at(0)),
+            Err(Duration::from_millis(1600))
+        );
+        assert_eq!(quotas.acquire_at("bulk", 1, 10, at(1700)), Ok(()));
+
+        let status = exhausted("", Duration::from_micros(1500));
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(status.metadata().get(RETRY_AFTER).unwrap(), "2");
+        assert!(status.message().starts_with("anonymous"));
+    }
+}
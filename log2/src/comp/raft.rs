@@ -0,0 +1,1030 @@
+use crate::comp::config::{Config, RaftConfig};
+use crate::comp::log::Log;
+use crate::comp::record::{
+    raft_request, raft_response, AppendEntriesRequest, AppendEntriesResponse, ProduceRequest,
+    RaftConfiguration, RaftRequest, RaftResponse, RaftServer, RaftStableState, Record,
+    RequestVoteRequest, RequestVoteResponse,
+};
+use prost::Message;
+use rand::Rng;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot, watch, Mutex, Notify, RwLock};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{sleep, timeout, Duration, Instant};
+
+// primer byte de toda conexión de raft, así se distinguen de las de gRPC
+pub const RAFT_RPC: u8 = 1;
+
+// valores de Record.type en el log de raft
+pub const ENTRY_COMMAND: u32 = 0;
+pub const ENTRY_CONFIGURATION: u32 = 1;
+pub const ENTRY_NOOP: u32 = 2;
+
+const MAX_APPEND_ENTRIES: usize = 64;
+// el largo de un frame llega de la red, arriba de esto se corta la conexión
+const MAX_FRAME_BYTES: u64 = 64 << 20;
+// un AppendEntries junta entradas hasta este tamaño, y ningún comando lo pasa
+const MAX_APPEND_BYTES: usize = 16 << 20;
+
+#[derive(Debug)]
+pub struct NotLeader;
+
+impl fmt::Display for NotLeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "node is not the raft leader")
+    }
+}
+
+impl std::error::Error for NotLeader {}
+
+pub fn is_not_leader(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<NotLeader>())
+}
+
+// Escucha las conexiones de los otros nodos. El que marca escribe RAFT_RPC
+// antes de cualquier mensaje y el que acepta lo verifica.
+pub struct StreamLayer {
+    incoming: Incoming,
+}
+
+// las conexiones llegan de un puerto propio o de las que el Mux separó
+enum Incoming {
+    #[cfg(test)]
+    Listener(tokio::net::TcpListener),
+    Mux(String, Mutex<mpsc::Receiver<TcpStream>>),
+}
+
+impl StreamLayer {
+    // en los tests raft tiene su propio puerto, el binario usa el Mux
+    #[cfg(test)]
+    pub fn new(listener: tokio::net::TcpListener) -> Self {
+        StreamLayer {
+            incoming: Incoming::Listener(listener),
+        }
+    }
+
+    #[cfg(test)]
+    pub async fn bind(addr: &str) -> io::Result<Self> {
+        Ok(StreamLayer::new(tokio::net::TcpListener::bind(addr).await?))
+    }
+
+    // `addr` es la dirección del puerto compartido
+    pub fn from_mux(addr: String, conns: mpsc::Receiver<TcpStream>) -> Self {
+        StreamLayer {
+            incoming: Incoming::Mux(addr, Mutex::new(conns)),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<String> {
+        match &self.incoming {
+            #[cfg(test)]
+            Incoming::Listener(listener) => Ok(listener.local_addr()?.to_string()),
+            Incoming::Mux(addr, _) => Ok(addr.clone()),
+        }
+    }
+
+    pub async fn accept(&self) -> io::Result<TcpStream> {
+        let mut conn = match &self.incoming {
+            #[cfg(test)]
+            Incoming::Listener(listener) => listener.accept().await?.0,
+            Incoming::Mux(_, conns) => conns.lock().await.recv().await.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotConnected, "mux closed")
+            })?,
+        };
+        conn.set_nodelay(true)?;
+        let mut b = [0u8; 1];
+        conn.read_exact(&mut b).await?;
+        if b[0] != RAFT_RPC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a raft connection",
+            ));
+        }
+        Ok(conn)
+    }
+
+    pub async fn dial(addr: &str) -> io::Result<TcpStream> {
+        let mut conn = TcpStream::connect(addr).await?;
+        conn.set_nodelay(true)?;
+        conn.write_all(&[RAFT_RPC]).await?;
+        Ok(conn)
+    }
+}
+
+// mismo framing que el store: largo en u64 big endian y luego el mensaje
+async fn write_frame<W: AsyncWrite + Unpin, M: Message>(w: &mut W, msg: &M) -> io::Result<()> {
+    // un solo write, si no Nagle detiene el mensaje esperando el ack del largo
+    let len = msg.encoded_len();
+    let mut buf = Vec::with_capacity(8 + len);
+    buf.extend_from_slice(&(len as u64).to_be_bytes());
+    msg.encode(&mut buf)?;
+    w.write_all(&buf).await?;
+    w.flush().await
+}
+
+// `applied` guarda last_applied y el next_offset que dejó en el FSM, los dos en
+// un solo write. Si el FSM va más adelante el proceso se cayó entre el append y
+// ese write, así que la entrada siguiente ya está aplicada y no se repite.
+fn applied_state(applied: &std::fs::File, fsm_next: u64) -> io::Result<u64> {
+    let mut buf = [0u8; 16];
+    let n = applied.read_at(&mut buf, 0)?;
+    if n < 8 {
+        return Ok(0);
+    }
+    let last_applied = u64::from_be_bytes(buf[..8].try_into().unwrap());
+    // los archivos de antes solo tienen last_applied
+    if n == 16 && fsm_next > u64::from_be_bytes(buf[8..].try_into().unwrap()) {
+        write_applied(applied, last_applied + 1, fsm_next)?;
+        return Ok(last_applied + 1);
+    }
+    Ok(last_applied)
+}
+
+fn write_applied(applied: &std::fs::File, index: u64, fsm_next: u64) -> io::Result<()> {
+    let mut buf = [0u8; 16];
+    buf[..8].copy_from_slice(&index.to_be_bytes());
+    buf[8..].copy_from_slice(&fsm_next.to_be_bytes());
+    applied.write_all_at(&buf, 0)
+}
+
+async fn read_frame<R: AsyncRead + Unpin, M: Message + Default>(r: &mut R) -> io::Result<M> {
+    let mut len = [0u8; 8];
+    r.read_exact(&mut len).await?;
+    let len = u64::from_be_bytes(len);
+    if len > MAX_FRAME_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("raft frame of {} bytes exceeds {}", len, MAX_FRAME_BYTES),
+        ));
+    }
+    let mut buf = vec![0u8; len as usize];
+    r.read_exact(&mut buf).await?;
+    Ok(M::decode(&*buf)?)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+struct State {
+    role: Role,
+    current_term: u64,
+    voted_for: String,
+    commit_index: u64,
+    last_applied: u64,
+    leader_id: String,
+    log: Log,
+    // terms[i] es el term de la entrada i + 1, para no ir al disco en cada comparación
+    terms: Vec<u64>,
+    configurations: Vec<(u64, Vec<RaftServer>)>,
+    next_index: HashMap<String, u64>,
+    match_index: HashMap<String, u64>,
+    waiters: HashMap<u64, oneshot::Sender<io::Result<u64>>>,
+    election_deadline: Instant,
+    last_contact: Instant,
+}
+
+impl State {
+    fn last_index(&self) -> u64 {
+        self.terms.len() as u64
+    }
+
+    fn last_term(&self) -> u64 {
+        self.terms.last().copied().unwrap_or(0)
+    }
+
+    fn term_at(&self, index: u64) -> u64 {
+        if index == 0 {
+            return 0;
+        }
+        self.terms.get(index as usize - 1).copied().unwrap_or(0)
+    }
+
+    fn servers(&self) -> &[RaftServer] {
+        self.configurations
+            .last()
+            .map_or(&[], |(_, servers)| servers.as_slice())
+    }
+}
+
+pub struct Raft {
+    config: RaftConfig,
+    stable_path: PathBuf,
+    // last_applied va en su propio archivo y se sobreescribe en su lugar; con
+    // el rename de `stable` cada apply tardaba lo que tarda el disco en sincronizar.
+    // Junto a él va el next_offset del FSM, ver `applied_state`.
+    applied: std::fs::File,
+    state: Mutex<State>,
+    fsm: Arc<RwLock<Log>>,
+    // último index del log, los que replican se despiertan cuando cambia
+    replicate: watch::Sender<u64>,
+    apply: Notify,
+    leader: watch::Sender<String>,
+    tasks: std::sync::Mutex<Vec<JoinHandle<()>>>,
+    replicators: std::sync::Mutex<HashMap<String, JoinHandle<()>>>,
+}
+
+impl Raft {
+    // `dir` guarda el log de raft y el estado estable, `fsm` es el Log donde
+    // se aplican los registros ya comprometidos.
+    pub async fn new(
+        dir: &Path,
+        config: Config,
+        raft_config: RaftConfig,
+        fsm: Arc<RwLock<Log>>,
+    ) -> io::Result<Arc<Raft>> {
+        let log_dir = dir.join("log");
+        std::fs::create_dir_all(&log_dir)?;
+
+        // los index de raft empiezan en 1; raft lee y trunca su log todo el
+        // tiempo, así que se queda en disco
+        let mut log_config = config;
+        log_config.segment.initial_offset = 1;
+        log_config.tiering = None;
+        let log = Log::new(log_dir.to_str().unwrap(), log_config).await?;
+
+        let stable_path = dir.join("stable");
+        let stable = match tokio::fs::read(&stable_path).await {
+            Ok(b) => RaftStableState::decode(&*b)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => RaftStableState::default(),
+            Err(e) => return Err(e),
+        };
+        let applied = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join("applied"))?;
+        let fsm_next = fsm.read().await.next_offset().await;
+        let last_applied = applied_state(&applied, fsm_next)?;
+
+        let mut state = State {
+            role: Role::Follower,
+            current_term: stable.current_term,
+            voted_for: stable.voted_for,
+            commit_index: last_applied,
+            last_applied,
+            leader_id: String::new(),
+            log,
+            terms: vec![],
+            configurations: vec![],
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            waiters: HashMap::new(),
+            election_deadline: Instant::now(),
+            last_contact: Instant::now(),
+        };
+
+        let lowest = state.log.lowest_offset().await?;
+        let highest = state.log.highest_offset().await?;
+        for index in lowest..=highest {
+            let entry = state.log.read(index).await?;
+            state.terms.push(entry.term);
+            if entry.r#type == ENTRY_CONFIGURATION {
+                let c = RaftConfiguration::decode(&*entry.value)?;
+                state.configurations.push((index, c.servers));
+            }
+        }
+
+        let (replicate, _) = watch::channel(state.last_index());
+        let (leader, _) = watch::channel(String::new());
+
+        let raft = Arc::new(Raft {
+            config: raft_config,
+            stable_path,
+            applied,
+            state: Mutex::new(state),
+            fsm,
+            replicate,
+            apply: Notify::new(),
+            leader,
+            tasks: std::sync::Mutex::new(vec![]),
+            replicators: std::sync::Mutex::new(HashMap::new()),
+        });
+
+        {
+            let mut st = raft.state.lock().await;
+            if raft.config.bootstrap && st.last_index() == 0 {
+                st.current_term = 1;
+                let servers = vec![RaftServer {
+                    id: raft.config.local_id.clone(),
+                    address: raft.config.bind_addr.clone(),
+                    rpc_addr: raft.config.rpc_addr.clone(),
+                }];
+                let value = RaftConfiguration { servers }.encode_to_vec();
+                raft.append_entry(&mut st, ENTRY_CONFIGURATION, value).await?;
+                raft.persist(&st).await?;
+            }
+            st.election_deadline = raft.next_deadline();
+        }
+
+        Ok(raft)
+    }
+
+    pub fn start(self: &Arc<Self>, layer: StreamLayer) {
+        let mut tasks = self.tasks.lock().unwrap();
+
+        let raft = Arc::clone(self);
+        tasks.push(tokio::spawn(async move {
+            // si abortan este task el JoinSet cierra también las conexiones abiertas
+            let mut conns = JoinSet::new();
+            loop {
+                let conn = match layer.accept().await {
+                    Ok(conn) => conn,
+                    Err(e) if e.kind() == io::ErrorKind::NotConnected => return,
+                    Err(_) => continue,
+                };
+                let raft = Arc::clone(&raft);
+                conns.spawn(async move {
+                    let _ = raft.serve_conn(conn).await;
+                });
+                while conns.try_join_next().is_some() {}
+            }
+        }));
+
+        let raft = Arc::clone(self);
+        tasks.push(tokio::spawn(async move { raft.run_ticker().await }));
+
+        let raft = Arc::clone(self);
+        tasks.push(tokio::spawn(async move { raft.run_applier().await }));
+    }
+
+    #[cfg(test)]
+    pub fn id(&self) -> &str {
+        &self.config.local_id
+    }
+
+    pub fn leader(&self) -> String {
+        self.leader.borrow().clone()
+    }
+
+    pub fn subscribe_leader(&self) -> watch::Receiver<String> {
+        self.leader.subscribe()
+    }
+
+    // Agrega un comando al log y regresa el offset que le tocó en el fsm
+    // cuando la mayoría ya lo tiene.
+    pub async fn apply(self: &Arc<Self>, value: Vec<u8>) -> io::Result<u64> {
+        if value.len() > MAX_APPEND_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "command of {} bytes exceeds {}",
+                    value.len(),
+                    MAX_APPEND_BYTES
+                ),
+            ));
+        }
+        self.propose(ENTRY_COMMAND, value).await
+    }
+
+    pub async fn servers(&self) -> Vec<RaftServer> {
+        self.state.lock().await.servers().to_vec()
+    }
+
+    pub async fn add_voter(
+        self: &Arc<Self>,
+        id: &str,
+        address: &str,
+        rpc_addr: &str,
+    ) -> io::Result<()> {
+        let servers = {
+            let st = self.state.lock().await;
+            let mut servers = st.servers().to_vec();
+            if servers
+                .iter()
+                .any(|s| s.id == id && s.address == address && s.rpc_addr == rpc_addr)
+            {
+                return Ok(());
+            }
+            servers.retain(|s| s.id != id && s.address != address);
+            servers.push(RaftServer {
+                id: id.to_string(),
+                address: address.to_string(),
+                rpc_addr: rpc_addr.to_string(),
+            });
+            servers
+        };
+        let value = RaftConfiguration { servers }.encode_to_vec();
+        self.propose(ENTRY_CONFIGURATION, value).await.map(|_| ())
+    }
+
+    pub async fn remove_server(self: &Arc<Self>, id: &str) -> io::Result<()> {
+        let servers = {
+            let st = self.state.lock().await;
+            let mut servers = st.servers().to_vec();
+            if !servers.iter().any(|s| s.id == id) {
+                return Ok(());
+            }
+            servers.retain(|s| s.id != id);
+            servers
+        };
+        let value = RaftConfiguration { servers }.encode_to_vec();
+        self.propose(ENTRY_CONFIGURATION, value).await.map(|_| ())
+    }
+
+    async fn propose(self: &Arc<Self>, kind: u32, value: Vec<u8>) -> io::Result<u64> {
+        let rx = {
+            let mut st = self.state.lock().await;
+            if st.role != Role::Leader {
+                return Err(io::Error::other(NotLeader));
+            }
+            let index = self.append_entry(&mut st, kind, value).await?;
+            let (tx, rx) = oneshot::channel();
+            st.waiters.insert(index, tx);
+            self.advance_commit(&mut st);
+            rx
+        };
+
+        match timeout(self.config.commit_timeout, rx).await {
+            Ok(Ok(res)) => res,
+            Ok(Err(_)) => Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "entry was discarded by a new leader",
+            )),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "timed out waiting for the entry to commit",
+            )),
+        }
+    }
+
+    pub async fn shutdown(&self) -> io::Result<()> {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        for (_, task) in self.replicators.lock().unwrap().drain() {
+            task.abort();
+        }
+        let mut st = self.state.lock().await;
+        st.role = Role::Follower;
+        self.persist(&st).await?;
+        st.log.close().await
+    }
+
+    fn next_deadline(&self) -> Instant {
+        let base = self.config.election_timeout;
+        let jitter = rand::thread_rng().gen_range(0..=base.as_millis() as u64);
+        Instant::now() + base + Duration::from_millis(jitter)
+    }
+
+    async fn persist(&self, st: &State) -> io::Result<()> {
+        let stable = RaftStableState {
+            current_term: st.current_term,
+            voted_for: st.voted_for.clone(),
+        };
+        let tmp = self.stable_path.with_extension("tmp");
+        tokio::fs::write(&tmp, stable.encode_to_vec()).await?;
+        tokio::fs::rename(&tmp, &self.stable_path).await
+    }
+
+    fn set_leader(&self, st: &mut State, id: &str) {
+        if st.leader_id != id {
+            st.leader_id = id.to_string();
+            self.leader.send_replace(id.to_string());
+        }
+    }
+
+    async fn append_entry(
+        self: &Arc<Self>,
+        st: &mut State,
+        kind: u32,
+        value: Vec<u8>,
+    ) -> io::Result<u64> {
+        let entry = Record {
+            value,
+            term: st.current_term,
+            r#type: kind,
+            ..Record::default()
+        };
+        let index = self.store_entry(st, entry).await?;
+        if kind == ENTRY_CONFIGURATION && st.role == Role::Leader {
+            self.sync_replicators(st);
+        }
+        self.replicate.send_replace(index);
+        Ok(index)
+    }
+
+    async fn store_entry(&self, st: &mut State, entry: Record) -> io::Result<u64> {
+        let term = entry.term;
+        let config = if entry.r#type == ENTRY_CONFIGURATION {
+            Some(RaftConfiguration::decode(&*entry.value)?)
+        } else {
+            None
+        };
+        let index = st.log.append(entry).await?;
+        st.terms.push(term);
+        if let Some(c) = config {
+            st.configurations.push((index, c.servers));
+        }
+        Ok(index)
+    }
+
+    // borra index y todo lo que le sigue en el log de raft
+    async fn truncate(&self, st: &mut State, index: u64) -> io::Result<()> {
+        st.log.truncate_from(index).await?;
+        st.terms.truncate(index as usize - 1);
+        st.configurations.retain(|(i, _)| *i < index);
+        st.waiters.retain(|i, _| *i < index);
+        Ok(())
+    }
+
+    async fn become_follower(&self, st: &mut State, term: u64) -> io::Result<()> {
+        if term > st.current_term {
+            st.current_term = term;
+            st.voted_for.clear();
+        }
+        st.role = Role::Follower;
+        self.persist(st).await
+    }
+
+    async fn become_leader(self: &Arc<Self>, term: u64) -> io::Result<()> {
+        let mut st = self.state.lock().await;
+        if st.role != Role::Candidate || st.current_term != term {
+            return Ok(());
+        }
+        st.role = Role::Leader;
+        let id = self.config.local_id.clone();
+        self.set_leader(&mut st, &id);
+
+        let next = st.last_index() + 1;
+        let peers: Vec<String> = st.servers().iter().map(|s| s.id.clone()).collect();
+        st.next_index.clear();
+        st.match_index.clear();
+        for peer in peers {
+            st.next_index.insert(peer.clone(), next);
+            st.match_index.insert(peer, 0);
+        }
+
+        // un lider nuevo solo puede comprometer entradas de su propio term
+        self.append_entry(&mut st, ENTRY_NOOP, vec![]).await?;
+        self.sync_replicators(&mut st);
+        self.advance_commit(&mut st);
+        Ok(())
+    }
+
+    fn sync_replicators(self: &Arc<Self>, st: &mut State) {
+        let mut replicators = self.replicators.lock().unwrap();
+        let peers: Vec<RaftServer> = st
+            .servers()
+            .iter()
+            .filter(|s| s.id != self.config.local_id)
+            .cloned()
+            .collect();
+
+        replicators.retain(|id, task| {
+            let keep = peers.iter().any(|s| &s.id == id) && !task.is_finished();
+            if !keep {
+                task.abort();
+            }
+            keep
+        });
+
+        let next = st.last_index() + 1;
+        for peer in peers {
+            if replicators.contains_key(&peer.id) {
+                continue;
+            }
+            st.next_index.entry(peer.id.clone()).or_insert(next);
+            st.match_index.entry(peer.id.clone()).or_insert(0);
+            let raft = Arc::clone(self);
+            let term = st.current_term;
+            let id = peer.id.clone();
+            replicators.insert(
+                id,
+                tokio::spawn(async move { raft.replicate_to(peer, term).await }),
+            );
+        }
+    }
+
+    fn advance_commit(&self, st: &mut State) {
+        let servers = st.servers().to_vec();
+        let mut n = st.last_index();
+        while n > st.commit_index && st.term_at(n) == st.current_term {
+            let votes = servers
+                .iter()
+                .filter(|s| {
+                    if s.id == self.config.local_id {
+                        true
+                    } else {
+                        st.match_index.get(&s.id).copied().unwrap_or(0) >= n
+                    }
+                })
+                .count();
+            if votes * 2 > servers.len() {
+                st.commit_index = n;
+                self.apply.notify_one();
+                return;
+            }
+            n -= 1;
+        }
+    }
+
+    async fn run_ticker(self: Arc<Self>) {
+        loop {
+            sleep(Duration::from_millis(10)).await;
+            let campaign = {
+                let st = self.state.lock().await;
+                st.role != Role::Leader
+                    && Instant::now() >= st.election_deadline
+                    && st.servers().iter().any(|s| s.id == self.config.local_id)
+            };
+            if campaign {
+                let _ = self.run_election().await;
+            }
+        }
+    }
+
+    async fn run_election(self: &Arc<Self>) -> io::Result<()> {
+        let (req, peers) = {
+            let mut st = self.state.lock().await;
+            st.role = Role::Candidate;
+            st.current_term += 1;
+            st.voted_for = self.config.local_id.clone();
+            st.election_deadline = self.next_deadline();
+            self.set_leader(&mut st, "");
+            self.persist(&st).await?;
+
+            let req = RequestVoteRequest {
+                term: st.current_term,
+                candidate_id: self.config.local_id.clone(),
+                last_log_index: st.last_index(),
+                last_log_term: st.last_term(),
+            };
+            let peers: Vec<RaftServer> = st
+                .servers()
+                .iter()
+                .filter(|s| s.id != self.config.local_id)
+                .cloned()
+                .collect();
+            (req, peers)
+        };
+
+        let term = req.term;
+        let needed = peers.len().div_ceil(2) + 1;
+        let mut votes = 1;
+        if votes >= needed {
+            return self.become_leader(term).await;
+        }
+
+        let (tx, mut rx) = mpsc::channel(peers.len());
+        for peer in peers {
+            let tx = tx.clone();
+            let req = RaftRequest {
+                rpc: Some(raft_request::Rpc::RequestVote(req.clone())),
+            };
+            let wait = self.config.election_timeout;
+            tokio::spawn(async move {
+                let mut conn = None;
+                let _ = tx.send(call(&mut conn, &peer.address, req, wait).await).await;
+            });
+        }
+        drop(tx);
+
+        let wait = timeout(self.config.election_timeout, async {
+            while let Some(res) = rx.recv().await {
+                let resp = match res {
+                    Ok(RaftResponse {
+                        rpc: Some(raft_response::Rpc::RequestVote(resp)),
+                    }) => resp,
+                    _ => continue,
+                };
+                if resp.term > term {
+                    let mut st = self.state.lock().await;
+                    if resp.term > st.current_term {
+                        self.become_follower(&mut st, resp.term).await?;
+                    }
+                    return Ok(false);
+                }
+                if resp.vote_granted {
+                    votes += 1;
+                    if votes >= needed {
+                        return Ok(true);
+                    }
+                }
+            }
+            Ok::<bool, io::Error>(false)
+        })
+        .await;
+
+        match wait {
+            Ok(Ok(true)) => self.become_leader(term).await,
+            Ok(Err(e)) => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    async fn replicate_to(self: Arc<Self>, peer: RaftServer, term: u64) {
+        let mut updates = self.replicate.subscribe();
+        let mut conn = None;
+        loop {
+            let req = {
+                let st = self.state.lock().await;
+                if st.role != Role::Leader
+                    || st.current_term != term
+                    || !st.servers().iter().any(|s| s.id == peer.id)
+                {
+                    return;
+                }
+                let next = st
+                    .next_index
+                    .get(&peer.id)
+                    .copied()
+                    .unwrap_or(st.last_index() + 1);
+                let mut entries = vec![];
+                let mut bytes = 0;
+                let mut index = next;
+                while index <= st.last_index()
+                    && entries.len() < MAX_APPEND_ENTRIES
+                    && bytes < MAX_APPEND_BYTES
+                {
+                    match st.log.read(index).await {
+                        Ok(entry) => {
+                            bytes += entry.encoded_len();
+                            entries.push(entry)
+                        }
+                        Err(_) => break,
+                    }
+                    index += 1;
+                }
+                AppendEntriesRequest {
+                    term,
+                    leader_id: self.config.local_id.clone(),
+                    prev_log_index: next - 1,
+                    prev_log_term: st.term_at(next - 1),
+                    entries,
+                    leader_commit: st.commit_index,
+                }
+            };
+
+            let prev = req.prev_log_index;
+            let sent = req.entries.len() as u64;
+            let req = RaftRequest {
+                rpc: Some(raft_request::Rpc::AppendEntries(req)),
+            };
+            let res = call(&mut conn, &peer.address, req, self.config.election_timeout).await;
+
+            if let Ok(RaftResponse {
+                rpc: Some(raft_response::Rpc::AppendEntries(resp)),
+            }) = res
+            {
+                let mut st = self.state.lock().await;
+                if resp.term > st.current_term {
+                    let _ = self.become_follower(&mut st, resp.term).await;
+                    return;
+                }
+                if st.role != Role::Leader || st.current_term != term {
+                    return;
+                }
+                if resp.success {
+                    let matched = prev + sent;
+                    st.match_index.insert(peer.id.clone(), matched);
+                    st.next_index.insert(peer.id.clone(), matched + 1);
+                    self.advance_commit(&mut st);
+                    if matched < st.last_index() {
+                        continue;
+                    }
+                } else {
+                    // el follower no tiene prev, retrocedemos hasta donde sí coincide
+                    let next = (prev).min(resp.last_log_index + 1).max(1);
+                    st.next_index.insert(peer.id.clone(), next);
+                    continue;
+                }
+            }
+
+            tokio::select! {
+                _ = updates.changed() => {}
+                _ = sleep(self.config.heartbeat_timeout) => {}
+            }
+        }
+    }
+
+    async fn run_applier(self: Arc<Self>) {
+        loop {
+            self.apply.notified().await;
+            loop {
+                let (index, entry) = {
+                    let st = self.state.lock().await;
+                    if st.last_applied >= st.commit_index {
+                        break;
+                    }
+                    let index = st.last_applied + 1;
+                    match st.log.read(index).await {
+                        Ok(entry) => (index, entry),
+                        Err(_) => break,
+                    }
+                };
+
+                // el FSM se escribe sin el lock de raft, así los RPC y los
+                // heartbeats no esperan al disco; solo este task mueve last_applied
+                let (res, fsm_next) = {
+                    let mut fsm = self.fsm.write().await;
+                    let res = match entry.r#type {
+                        ENTRY_COMMAND => Self::apply_command(&mut fsm, &entry).await,
+                        _ => Ok(0),
+                    };
+                    (res, fsm.next_offset().await)
+                };
+                let res = write_applied(&self.applied, index, fsm_next).and(res);
+
+                let mut st = self.state.lock().await;
+                st.last_applied = index;
+                if let Some(tx) = st.waiters.remove(&index) {
+                    let _ = tx.send(res);
+                }
+            }
+
+            let mut st = self.state.lock().await;
+            // un lider que se quitó del cluster deja de serlo cuando se compromete
+            if st.role == Role::Leader
+                && st.configurations.last().is_some_and(|(i, _)| *i <= st.commit_index)
+                && !st.servers().iter().any(|s| s.id == self.config.local_id)
+            {
+                st.role = Role::Follower;
+                self.set_leader(&mut st, "");
+            }
+        }
+    }
+
+    async fn apply_command(fsm: &mut Log, entry: &Record) -> io::Result<u64> {
+        let req = ProduceRequest::decode(&*entry.value)?;
+        let record = req.record.unwrap_or_default();
+        fsm.append(record).await
+    }
+
+    async fn serve_conn(self: Arc<Self>, mut conn: TcpStream) -> io::Result<()> {
+        loop {
+            let req: RaftRequest = read_frame(&mut conn).await?;
+            let rpc = match req.rpc {
+                Some(raft_request::Rpc::RequestVote(req)) => {
+                    raft_response::Rpc::RequestVote(self.handle_request_vote(req).await?)
+                }
+                Some(raft_request::Rpc::AppendEntries(req)) => {
+                    raft_response::Rpc::AppendEntries(self.handle_append_entries(req).await?)
+                }
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "empty raft request",
+                    ))
+                }
+            };
+            write_frame(&mut conn, &RaftResponse { rpc: Some(rpc) }).await?;
+        }
+    }
+
+    async fn handle_request_vote(&self, req: RequestVoteRequest) -> io::Result<RequestVoteResponse> {
+        let mut st = self.state.lock().await;
+
+        // Si tenemos un lider vivo ignoramos la elección. Así un nodo que ya
+        // sacaron del cluster (y no se enteró) no tumba al lider con terms nuevos.
+        let has_leader = st.role == Role::Leader
+            || (!st.leader_id.is_empty()
+                && st.last_contact.elapsed() < self.config.election_timeout);
+        if has_leader && req.candidate_id != st.leader_id {
+            return Ok(RequestVoteResponse {
+                term: st.current_term,
+                vote_granted: false,
+            });
+        }
+
+        if req.term > st.current_term {
+            self.become_follower(&mut st, req.term).await?;
+            self.set_leader(&mut st, "");
+        }
+
+        let up_to_date = req.last_log_term > st.last_term()
+            || (req.last_log_term == st.last_term() && req.last_log_index >= st.last_index());
+        let granted = req.term == st.current_term
+            && (st.voted_for.is_empty() || st.voted_for == req.candidate_id)
+            && up_to_date;
+
+        if granted {
+            st.voted_for = req.candidate_id;
+            st.election_deadline = self.next_deadline();
+            self.persist(&st).await?;
+        }
+
+        Ok(RequestVoteResponse {
+            term: st.current_term,
+            vote_granted: granted,
+        })
+    }
+
+    async fn handle_append_entries(
+        &self,
+        req: AppendEntriesRequest,
+    ) -> io::Result<AppendEntriesResponse> {
+        let mut st = self.state.lock().await;
+        if req.term < st.current_term {
+            return Ok(AppendEntriesResponse {
+                term: st.current_term,
+                success: false,
+                last_log_index: st.last_index(),
+            });
+        }
+        if req.term > st.current_term || st.role != Role::Follower {
+            self.become_follower(&mut st, req.term).await?;
+        }
+        self.set_leader(&mut st, &req.leader_id);
+        st.election_deadline = self.next_deadline();
+        st.last_contact = Instant::now();
+
+        if req.prev_log_index > st.last_index() {
+            return Ok(AppendEntriesResponse {
+                term: st.current_term,
+                success: false,
+                last_log_index: st.last_index(),
+            });
+        }
+        if st.term_at(req.prev_log_index) != req.prev_log_term {
+            self.truncate(&mut st, req.prev_log_index).await?;
+            return Ok(AppendEntriesResponse {
+                term: st.current_term,
+                success: false,
+                last_log_index: st.last_index(),
+            });
+        }
+
+        let mut index = req.prev_log_index;
+        for entry in req.entries {
+            index += 1;
+            if index <= st.last_index() {
+                if st.term_at(index) == entry.term {
+                    continue;
+                }
+                self.truncate(&mut st, index).await?;
+            }
+            self.store_entry(&mut st, entry).await?;
+        }
+
+        if req.leader_commit > st.commit_index {
+            st.commit_index = req.leader_commit.min(index);
+            self.apply.notify_one();
+        }
+
+        Ok(AppendEntriesResponse {
+            term: st.current_term,
+            success: true,
+            last_log_index: st.last_index(),
+        })
+    }
+}
+
+async fn call(
+    conn: &mut Option<TcpStream>,
+    addr: &str,
+    req: RaftRequest,
+    wait: Duration,
+) -> io::Result<RaftResponse> {
+    let res = timeout(wait, async {
+        if conn.is_none() {
+            *conn = Some(StreamLayer::dial(addr).await?);
+        }
+        let stream = conn.as_mut().unwrap();
+        write_frame(stream, &req).await?;
+        read_frame(stream).await
+    })
+    .await
+    .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "raft rpc timed out")));
+
+    if res.is_err() {
+        *conn = None;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn oversized_frame() {
+        let (mut a, mut b) = tokio::io::duplex(64);
+        let vote = RequestVoteRequest {
+            term: 3,
+            candidate_id: "1".to_string(),
+            ..RequestVoteRequest::default()
+        };
+        write_frame(&mut a, &vote).await.unwrap();
+        let got: RequestVoteRequest = read_frame(&mut b).await.unwrap();
+        assert_eq!(got, vote);
+
+        // solo el largo, sin reservar memoria para lo que nunca va a llegar
+        a.write_all(&u64::MAX.to_be_bytes()).await.unwrap();
+        let err = read_frame::<_, RequestVoteRequest>(&mut b)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
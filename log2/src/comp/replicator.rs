@@ -0,0 +1,326 @@
+use crate::comp::membership::Handler;
+use crate::comp::offsets::OffsetStore;
+use crate::comp::record::log_client::LogClient;
+use crate::comp::record::ConsumeRequest;
+use crate::comp::server::CommitLog;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+use tonic::transport::Endpoint;
+use tracing::Instrument;
+
+// si se cae la conexión con un peer esperamos esto antes de volver a intentar
+const RETRY: Duration = Duration::from_millis(500);
+
+// Replicación sin consenso: por cada peer abre un ConsumeStream y produce en
+// el log local lo que le llega. Solo se jalan los registros que se produjeron
+// en ese peer (origin vacío), así lo que replicamos no regresa de vuelta.
+// Hasta dónde vamos con cada peer se guarda en cursors (el grupo es el nombre
+// del peer), así al reiniciar no se vuelve a jalar todo.
+pub struct Replicator {
+    local_name: String,
+    local: Arc<dyn CommitLog>,
+    cursors: Arc<OffsetStore>,
+    servers: Mutex<HashMap<String, JoinHandle<()>>>,
+}
+
+impl Replicator {
+    pub fn new(local_name: &str, local: Arc<dyn CommitLog>, cursors: Arc<OffsetStore>) -> Self {
+        Replicator {
+            local_name: local_name.to_string(),
+            local,
+            cursors,
+            servers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn join(&self, name: &str, addr: &str) {
+        let mut servers = self.servers.lock().unwrap();
+        if name == self.local_name || servers.contains_key(name) {
+            return;
+        }
+        let span = tracing::info_span!("replicate", peer = name, addr);
+        let task = tokio::spawn(
+            replicate(
+                name.to_string(),
+                addr.to_string(),
+                Arc::clone(&self.local),
+                Arc::clone(&self.cursors),
+            )
+            .instrument(span),
+        );
+        servers.insert(name.to_string(), task);
+    }
+
+    pub fn leave(&self, name: &str) {
+        if let Some(task) = self.servers.lock().unwrap().remove(name) {
+            task.abort();
+        }
+    }
+
+    pub async fn close(&self) -> io::Result<()> {
+        for (_, task) in self.servers.lock().unwrap().drain() {
+            task.abort();
+        }
+        self.cursors.close().await
+    }
+}
+
+// así la membresía le avisa cuando entra o sale un nodo
+#[tonic::async_trait]
+impl Handler for Replicator {
+    async fn join(&self, name: &str, addr: &str) -> io::Result<()> {
+        Replicator::join(self, name, addr);
+        Ok(())
+    }
+
+    async fn leave(&self, name: &str) -> io::Result<()> {
+        Replicator::leave(self, name);
+        Ok(())
+    }
+}
+
+async fn replicate(
+    name: String,
+    addr: String,
+    local: Arc<dyn CommitLog>,
+    cursors: Arc<OffsetStore>,
+) {
+    let endpoint = match Endpoint::from_shared(format!("http://{}", addr)) {
+        Ok(endpoint) => endpoint,
+        Err(e) => {
+            tracing::error!(error = %e, "dirección inválida");
+            return;
+        }
+    };
+
+    // siguiente offset que le vamos a pedir al peer, sobrevive a las reconexiones
+    // y con cursors a los reinicios
+    let mut offset = cursors.fetch(&name, "", 0).await.unwrap_or(0);
+    loop {
+        let mut client = match LogClient::connect(endpoint.clone()).await {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::debug!(error = %e, "no se pudo conectar, reintentando");
+                sleep(RETRY).await;
+                continue;
+            }
+        };
+        let mut stream = match client
+            .consume_stream(ConsumeRequest {
+                offset,
+                ..ConsumeRequest::default()
+            })
+            .await
+        {
+            Ok(res) => res.into_inner(),
+            Err(_) => {
+                sleep(RETRY).await;
+                continue;
+            }
+        };
+
+        while let Ok(Some(res)) = stream.message().await {
+            let Some(mut record) = res.record else {
+                continue;
+            };
+            let next = record.offset + 1;
+            if !record.origin.is_empty() {
+                offset = next;
+                continue;
+            }
+            record.offset = 0;
+            record.origin = name.clone();
+            if let Err(e) = local.append(record).await {
+                // offset no avanza, al reconectar se vuelve a pedir este registro
+                tracing::error!(error = %e, "no se pudo producir lo del peer, reintentando");
+                break;
+            }
+            offset = next;
+            // lo que no se produjo aquí no hace falta guardarlo, si se vuelve
+            // a jalar se salta otra vez. Si nos caemos antes de guardar, ese
+            // registro sí se repite
+            if let Err(e) = cursors.commit(&name, "", 0, offset).await {
+                tracing::warn!(error = %e, "no se pudo guardar hasta dónde vamos");
+            }
+        }
+        sleep(RETRY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comp::config::test_config;
+    use crate::comp::log::Log;
+    use crate::comp::record::Record;
+    use crate::comp::server::{new_grpc_server, ServerConfig};
+    use std::path::Path;
+    use tokio::net::TcpListener;
+    use tokio::sync::RwLock;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Server;
+
+    async fn node(dir: &Path) -> (Arc<dyn CommitLog>, String) {
+        let log = Log::new(dir.to_str().unwrap(), test_config())
+            .await
+            .unwrap();
+        let commit_log: Arc<dyn CommitLog> = Arc::new(RwLock::new(log));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(
+            Server::builder()
+                .add_service(new_grpc_server(ServerConfig::new(Arc::clone(&commit_log))))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        (commit_log, addr)
+    }
+
+    async fn cursors(dir: &Path) -> Arc<OffsetStore> {
+        let dir = dir.join("replication");
+        Arc::new(
+            OffsetStore::new(dir.to_str().unwrap(), test_config())
+                .await
+                .unwrap(),
+        )
+    }
+
+    fn record(value: &[u8]) -> Record {
+        Record {
+            value: value.to_vec(),
+            ..Record::default()
+        }
+    }
+
+    async fn eventually_read(log: &Arc<dyn CommitLog>, offset: u64) -> io::Result<Record> {
+        for _ in 0..100 {
+            if let Ok(record) = log.read(offset).await {
+                return Ok(record);
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        log.read(offset).await
+    }
+
+    #[tokio::test]
+    async fn replicate_between_nodes() {
+        let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let (a, a_addr) = node(dirs[0].path()).await;
+        let (b, b_addr) = node(dirs[1].path()).await;
+
+        let a_rep = Replicator::new("a", Arc::clone(&a), cursors(dirs[0].path()).await);
+        let b_rep = Replicator::new("b", Arc::clone(&b), cursors(dirs[1].path()).await);
+        a_rep.join("b", &b_addr);
+        a_rep.join("a", &a_addr);
+        b_rep.join("a", &a_addr);
+
+        a.append(record(b"desde a")).await.unwrap();
+        b.append(record(b"desde b")).await.unwrap();
+
+        let got = eventually_read(&b, 1).await.unwrap();
+        assert_eq!(got.value, b"desde a".to_vec());
+        assert_eq!(got.origin, "a");
+        let got = eventually_read(&a, 1).await.unwrap();
+        assert_eq!(got.value, b"desde b".to_vec());
+        assert_eq!(got.origin, "b");
+
+        // lo replicado no regresa a su origen
+        sleep(Duration::from_millis(200)).await;
+        assert!(a.read(2).await.is_err());
+        assert!(b.read(2).await.is_err());
+
+        a_rep.leave("b");
+        b.append(record(b"ya no")).await.unwrap();
+        sleep(Duration::from_millis(200)).await;
+        assert!(a.read(2).await.is_err());
+
+        a_rep.close().await.unwrap();
+        b_rep.close().await.unwrap();
+    }
+
+    // un log local que falla los primeros appends
+    struct Flaky {
+        log: Arc<dyn CommitLog>,
+        failures: std::sync::atomic::AtomicUsize,
+    }
+
+    #[tonic::async_trait]
+    impl CommitLog for Flaky {
+        async fn append(&self, record: Record) -> io::Result<u64> {
+            use std::sync::atomic::Ordering;
+            let left = self.failures.load(Ordering::SeqCst);
+            if left > 0 {
+                self.failures.store(left - 1, Ordering::SeqCst);
+                return Err(io::Error::other("disco lleno"));
+            }
+            self.log.append(record).await
+        }
+
+        async fn read(&self, offset: u64) -> io::Result<Record> {
+            self.log.read(offset).await
+        }
+
+        async fn read_committed(&self, offset: u64) -> io::Result<Option<Record>> {
+            self.log.read_committed(offset).await
+        }
+
+        async fn close(&self) -> io::Result<()> {
+            self.log.close().await
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_failed_appends() {
+        let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let (a, a_addr) = node(dirs[0].path()).await;
+        let (b, _) = node(dirs[1].path()).await;
+        a.append(record(b"uno")).await.unwrap();
+        a.append(record(b"dos")).await.unwrap();
+
+        let flaky: Arc<dyn CommitLog> = Arc::new(Flaky {
+            log: Arc::clone(&b),
+            failures: 1.into(),
+        });
+        let rep = Replicator::new("b", flaky, cursors(dirs[1].path()).await);
+        rep.join("a", &a_addr);
+        assert_eq!(eventually_read(&b, 0).await.unwrap().value, b"uno".to_vec());
+        assert_eq!(eventually_read(&b, 1).await.unwrap().value, b"dos".to_vec());
+        rep.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn resume_after_restart() {
+        let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let (a, a_addr) = node(dirs[0].path()).await;
+        let (b, _) = node(dirs[1].path()).await;
+        a.append(record(b"uno")).await.unwrap();
+        a.append(record(b"dos")).await.unwrap();
+
+        let rep = Replicator::new("b", Arc::clone(&b), cursors(dirs[1].path()).await);
+        rep.join("a", &a_addr);
+        eventually_read(&b, 1).await.unwrap();
+        // se guarda después del append, hay que esperarlo
+        for _ in 0..100 {
+            if rep.cursors.fetch("a", "", 0).await == Some(2) {
+                break;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        rep.close().await.unwrap();
+
+        // el nuevo sigue donde se quedó el otro, sin repetir uno y dos
+        let rep = Replicator::new("b", Arc::clone(&b), cursors(dirs[1].path()).await);
+        rep.join("a", &a_addr);
+        a.append(record(b"tres")).await.unwrap();
+        assert_eq!(
+            eventually_read(&b, 2).await.unwrap().value,
+            b"tres".to_vec()
+        );
+        sleep(Duration::from_millis(200)).await;
+        assert!(b.read(3).await.is_err());
+        rep.close().await.unwrap();
+    }
+}
@@ -1,13 +1,12 @@
-use crate::comp::config::Config;
+use crate::comp::compression::{compress, decompress};
+use crate::comp::config::{Compression, Config};
+use crate::comp::encryption::{missing_key, ENCRYPTED};
 use crate::comp::index::Index;
 use crate::comp::record::Record;
+use crate::comp::storage::Storage;
 use crate::comp::store::Store;
 use prost::Message;
-use std::fs::{File, OpenOptions};
-// use std::io::{Read, Seek, SeekFrom, Write};
-use std::path::Path;
-
-use tokio::fs::File as AsyncFile;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct Segment {
@@ -16,34 +15,86 @@ pub struct Segment {
     pub base_offset: u64,
     pub next_offset: u64,
     pub config: Box<Config>,
+    // los path son nombres dentro del storage
+    pub storage: Arc<dyn Storage>,
     pub path_index: String,
     pub path_store: String,
+    // <base>.key: con qué llave se cifran los frames de este segmento
+    pub path_key: String,
+    pub key_id: Option<String>,
 }
 
 impl Segment {
-    pub async fn new(dir: &str, base_offset: u64, config: Config) -> Result<Self, std::io::Error> {
-        let store_file_path = Path::new(dir).join(format!("{}.store", base_offset));
-        let store_file = AsyncFile::open(&store_file_path).await?;
-        let store = Box::new(Store::new(store_file, dir.to_string()).await?);
-
-        let index_file_path = Path::new(dir).join(format!("{}.index", base_offset));
-        let index_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&index_file_path)?;
-
-        let index = Box::new(Index::new(&index_file, &config, dir.to_string())?);
+    pub async fn new(
+        storage: Arc<dyn Storage>,
+        base_offset: u64,
+        config: Config,
+    ) -> Result<Self, std::io::Error> {
+        let path_store = format!("{}.store", base_offset);
+        let store_file = storage.open(&path_store).await?;
+        let mut store = Box::new(Store::new(store_file, path_store.clone()).await?);
+
+        let path_index = format!("{}.index", base_offset);
+        let index_file = storage.open(&path_index).await?;
+        let mut index = Box::new(Index::new(index_file, &config, path_index.clone()).await?);
+
+        // Si el proceso murió a medio append, el index puede apuntar a un frame
+        // que no quedó completo en el store, y el store puede tener bytes que
+        // el index no tiene. Nos quedamos hasta el último registro completo.
+        let mut end = 0;
+        while let Ok((off, pos)) = index.read(-1) {
+            if let Some(frame_end) = store.frame_end(pos).await? {
+                end = frame_end;
+                break;
+            }
+            tracing::warn!(
+                base = base_offset,
+                off,
+                "el index apunta a un frame incompleto"
+            );
+            index.truncate(off as u64);
+        }
+        if store.size > end {
+            tracing::warn!(
+                base = base_offset,
+                bytes = store.size - end,
+                "se cortan los bytes después del último registro"
+            );
+            store.truncate(end).await?;
+        }
 
         let next_offset = match index.read(-1) {
             Ok((off, _)) => base_offset + off as u64 + 1,
             Err(_) => base_offset,
         };
-        let path_index = index_file_path.to_string_lossy().into_owned();
-
-        let path_store = store_file_path.to_string_lossy().into_owned();
-
+        let path_key = format!("{}.key", base_offset);
+        let key_id = if storage.exists(&path_key).await? {
+            let id = storage.read_file(&path_key).await?;
+            Some(String::from_utf8_lossy(&id).trim().to_string())
+        } else {
+            None
+        };
+        // mejor fallar al abrir que con el primer read
+        if let Some(id) = &key_id {
+            if !config.encryption.as_ref().is_some_and(|k| k.contains(id)) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!(
+                        "segment {} in {} is encrypted with key {:?}, which is not in the keyfile",
+                        base_offset,
+                        storage.location(),
+                        id
+                    ),
+                ));
+            }
+        }
         let config = Box::new(config);
+        tracing::debug!(
+            base = base_offset,
+            next = next_offset,
+            location = %storage.location(),
+            "segmento abierto"
+        );
 
         Ok(Self {
             store,
@@ -51,11 +102,15 @@ impl Segment {
             base_offset,
             next_offset,
             config,
+            storage,
             path_index,
             path_store,
+            path_key,
+            key_id,
         })
     }
 
+    #[tracing::instrument(level = "trace", skip_all, fields(base = self.base_offset))]
     pub async fn append(&mut self, record: Record) -> Result<u64, std::io::Error> {
         let current_offset = self.next_offset;
         let mut record = record.clone();
@@ -63,37 +118,89 @@ impl Segment {
 
         let mut buf = Vec::new();
         record.encode(&mut buf)?;
+        let (codec, buf) = compress(self.config.compression, buf)?;
+        let (flags, buf) = match self.config.encryption.clone() {
+            Some(keyring) => {
+                // el segmento se queda con la llave que estaba activa en su primer
+                // frame cifrado; las rotaciones aplican a los segmentos nuevos
+                if self.key_id.is_none() {
+                    self.storage
+                        .write_file(&self.path_key, keyring.active_id().as_bytes())
+                        .await?;
+                    self.key_id = Some(keyring.active_id().to_string());
+                }
+                let key_id = self.key_id.as_deref().unwrap();
+                let sealed = keyring.encrypt(key_id, &buf, &current_offset.to_be_bytes())?;
+                (codec.flag() | ENCRYPTED, sealed)
+            }
+            None => (codec.flag(), buf),
+        };
 
-        let pos = self.store.append(&buf).await?;
+        let pos = self.store.append(&buf, flags).await?;
         self.index
             .write((self.next_offset - self.base_offset) as u32, pos.1)?;
 
+        tracing::trace!(offset = current_offset, pos = pos.1, flags, "frame escrito");
         self.next_offset += 1;
         Ok(current_offset)
     }
 
+    #[tracing::instrument(level = "trace", skip(self), fields(base = self.base_offset))]
     pub async fn read(&self, offset: u64) -> Result<Record, std::io::Error> {
         let pos = self.index.read((offset - self.base_offset) as i64)?.1;
-        let data = self.store.read(pos).await?;
+        let (flags, data) = self.store.read(pos).await?;
+        let data = if flags & ENCRYPTED != 0 {
+            let key_id = self.key_id.as_deref().ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "segment {} has encrypted frames but no key id",
+                        self.base_offset
+                    ),
+                )
+            })?;
+            let keyring = self
+                .config
+                .encryption
+                .as_ref()
+                .ok_or_else(|| missing_key(key_id))?;
+            keyring.decrypt(key_id, &data, &offset.to_be_bytes())?
+        } else {
+            data
+        };
+        let data = decompress(Compression::from_flags(flags)?, data)?;
 
         let record = Record::decode(&*data)?;
         Ok(record)
     }
 
-    pub async fn is_maxed(& self) -> bool {
+    pub async fn is_maxed(&self) -> bool {
         self.store.size >= self.config.segment.max_store_bytes
             || self.index.size >= self.config.segment.max_index_bytes
     }
 
     pub async fn remove(&mut self) -> Result<(), std::io::Error> {
-        self.close();
-        std::fs::remove_file(self.path_index.clone());
-        tokio::fs::remove_file(self.path_store.clone());
+        self.close().await?;
+        self.storage.remove(&self.path_index).await?;
+        self.storage.remove(&self.path_store).await?;
+        if self.key_id.is_some() {
+            self.storage.remove(&self.path_key).await?;
+        }
+        Ok(())
+    }
+
+    // borra offset y todos los registros que le siguen dentro del segmento
+    pub async fn truncate(&mut self, offset: u64) -> Result<(), std::io::Error> {
+        let rel = offset - self.base_offset;
+        let (_, pos) = self.index.read(rel as i64)?;
+        self.index.truncate(rel);
+        self.store.truncate(pos).await?;
+        self.next_offset = offset;
         Ok(())
     }
 
     pub async fn close(&mut self) -> Result<(), std::io::Error> {
-        self.index.close(&mut File::open(&self.path_index)?)?;
+        self.index.close().await?;
         self.store.close().await?;
         Ok(())
     }
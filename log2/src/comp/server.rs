@@ -0,0 +1,1317 @@
+use crate::comp::auth::{
+    self, Authorizer, ADMIN_ACTION, CONSUME_ACTION, OBJECT_WILDCARD, PRODUCE_ACTION,
+};
+use crate::comp::broker::Broker;
+use crate::comp::distributed::DistributedLog;
+use crate::comp::filter::Filter;
+use crate::comp::log::Log;
+use crate::comp::metrics;
+use crate::comp::offsets::OffsetStore;
+use crate::comp::quota::{self, Quotas};
+use crate::comp::raft::is_not_leader;
+use crate::comp::record::log_server;
+use crate::comp::record::{
+    CommitOffsetRequest, CommitOffsetResponse, ConsumeRequest, ConsumeResponse,
+    CreateTopicRequest, CreateTopicResponse, DeleteTopicRequest, DeleteTopicResponse,
+    FetchOffsetRequest, FetchOffsetResponse, GetServersRequest, GetServersResponse,
+    ListTopicsRequest, ListTopicsResponse, ProduceRequest, ProduceResponse, Record, Server, Topic,
+};
+use prost::Message;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch, RwLock};
+use tokio::time::{sleep, Duration};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::Instrument;
+
+// lo que el servidor necesita del log, igual que la interfaz CommitLog de server/server.go
+#[tonic::async_trait]
+pub trait CommitLog: Send + Sync + 'static {
+    async fn append(&self, record: Record) -> io::Result<u64>;
+    async fn read(&self, offset: u64) -> io::Result<Record>;
+    // ver Log::read_committed
+    async fn read_committed(&self, offset: u64) -> io::Result<Option<Record>>;
+    // al apagar el servidor, ya sin RPCs en curso
+    async fn close(&self) -> io::Result<()>;
+
+    // ver Log::retain; el log de raft no tiene retención, raft no hace
+    // snapshots y borrar entradas lo dejaría sin poder ponerse al día
+    async fn retain(&self, _max_records: u64) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this log does not support retention",
+        ))
+    }
+
+    // solo tiene sentido en un cluster
+    async fn get_servers(&self) -> io::Result<Vec<Server>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this log is not part of a cluster",
+        ))
+    }
+}
+
+#[tonic::async_trait]
+impl CommitLog for RwLock<Log> {
+    async fn append(&self, record: Record) -> io::Result<u64> {
+        self.write().await.append(record).await
+    }
+
+    async fn read(&self, offset: u64) -> io::Result<Record> {
+        self.read().await.read(offset).await
+    }
+
+    async fn read_committed(&self, offset: u64) -> io::Result<Option<Record>> {
+        self.read().await.read_committed(offset).await
+    }
+
+    async fn close(&self) -> io::Result<()> {
+        self.write().await.close().await
+    }
+
+    async fn retain(&self, max_records: u64) -> io::Result<()> {
+        self.write().await.retain(max_records).await
+    }
+}
+
+#[tonic::async_trait]
+impl CommitLog for DistributedLog {
+    async fn append(&self, record: Record) -> io::Result<u64> {
+        DistributedLog::append(self, record).await
+    }
+
+    async fn read(&self, offset: u64) -> io::Result<Record> {
+        DistributedLog::read(self, offset).await
+    }
+
+    async fn read_committed(&self, offset: u64) -> io::Result<Option<Record>> {
+        DistributedLog::read_committed(self, offset).await
+    }
+
+    async fn close(&self) -> io::Result<()> {
+        DistributedLog::close(self).await
+    }
+
+    async fn get_servers(&self) -> io::Result<Vec<Server>> {
+        Ok(DistributedLog::get_servers(self).await)
+    }
+}
+
+pub struct ServerConfig {
+    pub commit_log: Arc<dyn CommitLog>,
+    // sin broker solo está el log del nodo y las llamadas con topic fallan
+    pub broker: Option<Arc<Broker>>,
+    // donde los grupos de consumidores guardan su offset
+    pub offsets: Option<Arc<OffsetStore>>,
+    // con ACL solo producen y consumen los subjects de la política; crear y
+    // borrar topics pide la acción admin y los offsets de los grupos, consume
+    pub authorizer: Option<Arc<Authorizer>>,
+    // límites por cliente de Produce y Consume, ver comp/quota.rs
+    pub quotas: Option<Arc<Quotas>>,
+}
+
+impl ServerConfig {
+    pub fn new(commit_log: Arc<dyn CommitLog>) -> Self {
+        ServerConfig {
+            commit_log,
+            broker: None,
+            offsets: None,
+            authorizer: None,
+            quotas: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LogService {
+    commit_log: Arc<dyn CommitLog>,
+    broker: Option<Arc<Broker>>,
+    offsets: Option<Arc<OffsetStore>>,
+    authorizer: Option<Arc<Authorizer>>,
+    quotas: Option<Arc<Quotas>>,
+    // en true cuando el servidor se está apagando, ver shutdown
+    closing: Arc<watch::Sender<bool>>,
+}
+
+#[cfg(test)]
+pub fn new_grpc_server(config: ServerConfig) -> log_server::LogServer<LogService> {
+    log_server::LogServer::new(new_log_service(config))
+}
+
+// el mismo servicio lo usa el gateway HTTP (ver comp/gateway.rs)
+pub fn new_log_service(config: ServerConfig) -> LogService {
+    LogService {
+        commit_log: config.commit_log,
+        broker: config.broker,
+        offsets: config.offsets,
+        authorizer: config.authorizer,
+        quotas: config.quotas,
+        closing: Arc::new(watch::channel(false).0),
+    }
+}
+
+impl LogService {
+    // Termina los streams abiertos: ConsumeStream manda lo que ya está escrito
+    // y ProduceStream deja de recibir después del request en curso. Los dos
+    // cierran con UNAVAILABLE para que el cliente se vaya a otro nodo.
+    pub fn shutdown(&self) {
+        self.closing.send_replace(true);
+    }
+
+    fn authorize(&self, subject: &str, action: &str) -> io::Result<()> {
+        match &self.authorizer {
+            Some(authorizer) => authorizer.authorize(subject, OBJECT_WILDCARD, action),
+            None => Ok(()),
+        }
+    }
+
+    // Err con lo que hay que esperar si el cliente ya gastó su cuota
+    fn throttle(&self, client: &str, requests: u64, bytes: u64) -> Result<(), Duration> {
+        match &self.quotas {
+            Some(quotas) => quotas.acquire(client, requests, bytes),
+            None => Ok(()),
+        }
+    }
+
+    fn charge(&self, client: &str, bytes: u64) {
+        if let Some(quotas) = &self.quotas {
+            quotas.charge(client, bytes);
+        }
+    }
+
+    // el log del nodo o, si viene topic, una de sus particiones
+    async fn log_for(&self, topic: &str, partition: u32) -> Result<Arc<dyn CommitLog>, Status> {
+        if topic.is_empty() {
+            return Ok(Arc::clone(&self.commit_log));
+        }
+        let log = self
+            .broker
+            .as_ref()
+            .ok_or_else(topics_disabled)?
+            .partition(topic, partition)
+            .await
+            .map_err(topic_status)?;
+        Ok(log)
+    }
+
+    async fn append(&self, req: ProduceRequest) -> Result<ProduceResponse, Status> {
+        let record = req.record.unwrap_or_default();
+        let partition = if req.topic.is_empty() {
+            0
+        } else if record.transaction_id != 0 {
+            // toda la transacción va a la partición que escogió el productor
+            req.partition
+        } else {
+            self.broker
+                .as_ref()
+                .ok_or_else(topics_disabled)?
+                .partition_for(&req.topic, &record.key)
+                .await
+                .map_err(topic_status)?
+        };
+        let offset = self
+            .log_for(&req.topic, partition)
+            .await?
+            .append(record)
+            .await
+            .map_err(|e| to_status(e, 0))?;
+        Ok(ProduceResponse { offset, partition })
+    }
+}
+
+fn offsets_disabled() -> Status {
+    Status::unimplemented("consumer groups are not enabled on this server")
+}
+
+fn topics_disabled() -> Status {
+    Status::unimplemented("topics are not enabled on this server")
+}
+
+fn topic_status(e: io::Error) -> Status {
+    match e.kind() {
+        io::ErrorKind::NotFound => Status::not_found(e.to_string()),
+        io::ErrorKind::AlreadyExists => Status::already_exists(e.to_string()),
+        io::ErrorKind::InvalidInput => Status::invalid_argument(e.to_string()),
+        io::ErrorKind::WouldBlock => Status::unavailable(e.to_string()),
+        _ => Status::internal(e.to_string()),
+    }
+}
+
+fn to_status(e: io::Error, offset: u64) -> Status {
+    if is_not_leader(&e) {
+        return Status::failed_precondition(e.to_string());
+    }
+    match e.kind() {
+        io::ErrorKind::NotFound => Status::not_found(format!("offset out of range: {}", offset)),
+        io::ErrorKind::Unsupported => Status::unimplemented(e.to_string()),
+        io::ErrorKind::InvalidInput => Status::invalid_argument(e.to_string()),
+        // read_committed sobre una transacción abierta: hay que reintentar
+        io::ErrorKind::WouldBlock => Status::unavailable(e.to_string()),
+        _ => {
+            tracing::error!(offset, error = %e, "error interno del log");
+            Status::internal(e.to_string())
+        }
+    }
+}
+
+fn denied(e: io::Error) -> Status {
+    Status::permission_denied(e.to_string())
+}
+
+// Con filtro, ConsumeStream manda una respuesta sin registro al llegar al final
+// del log o después de saltarse tantos, para que el cliente pueda seguir desde
+// next_offset sin volver a leerlos.
+const SKIPPED_PROGRESS: u32 = 1000;
+
+fn progress(next_offset: u64) -> ConsumeResponse {
+    ConsumeResponse {
+        record: None,
+        next_offset,
+    }
+}
+
+fn shutting_down() -> Status {
+    Status::unavailable("server is shutting down")
+}
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl log_server::Log for LogService {
+    async fn produce(
+        &self,
+        request: Request<ProduceRequest>,
+    ) -> Result<Response<ProduceResponse>, Status> {
+        let client = auth::subject(&request);
+        self.authorize(&client, PRODUCE_ACTION).map_err(denied)?;
+        let req = request.into_inner();
+        self.throttle(&client, 1, req.encoded_len() as u64)
+            .map_err(|wait| quota::exhausted(&client, wait))?;
+        Ok(Response::new(self.append(req).await?))
+    }
+
+    async fn consume(
+        &self,
+        request: Request<ConsumeRequest>,
+    ) -> Result<Response<ConsumeResponse>, Status> {
+        let client = auth::subject(&request);
+        self.authorize(&client, CONSUME_ACTION).map_err(denied)?;
+        // los bytes se cobran después de leer, cuando se sabe cuántos son
+        self.throttle(&client, 1, 0)
+            .map_err(|wait| quota::exhausted(&client, wait))?;
+        let req = request.into_inner();
+        if !req.filter.is_empty() {
+            return Err(Status::invalid_argument(
+                "filter is only supported by ConsumeStream",
+            ));
+        }
+        let commit_log = self.log_for(&req.topic, req.partition).await?;
+        let record = if req.read_committed {
+            commit_log
+                .read_committed(req.offset)
+                .await
+                .map_err(|e| to_status(e, req.offset))?
+                .ok_or_else(|| {
+                    Status::not_found(format!("offset {} has no committed record", req.offset))
+                })?
+        } else {
+            commit_log
+                .read(req.offset)
+                .await
+                .map_err(|e| to_status(e, req.offset))?
+        };
+        self.charge(&client, record.encoded_len() as u64);
+        Ok(Response::new(ConsumeResponse {
+            record: Some(record),
+            next_offset: req.offset + 1,
+        }))
+    }
+
+    async fn get_servers(
+        &self,
+        _request: Request<GetServersRequest>,
+    ) -> Result<Response<GetServersResponse>, Status> {
+        let servers = self
+            .commit_log
+            .get_servers()
+            .await
+            .map_err(|e| to_status(e, 0))?;
+        Ok(Response::new(GetServersResponse { servers }))
+    }
+
+    async fn create_topic(
+        &self,
+        request: Request<CreateTopicRequest>,
+    ) -> Result<Response<CreateTopicResponse>, Status> {
+        self.authorize(&auth::subject(&request), ADMIN_ACTION)
+            .map_err(denied)?;
+        let req = request.into_inner();
+        self.broker
+            .as_ref()
+            .ok_or_else(topics_disabled)?
+            .create_topic(&req.name, req.partitions)
+            .await
+            .map_err(topic_status)?;
+        Ok(Response::new(CreateTopicResponse {}))
+    }
+
+    async fn delete_topic(
+        &self,
+        request: Request<DeleteTopicRequest>,
+    ) -> Result<Response<DeleteTopicResponse>, Status> {
+        self.authorize(&auth::subject(&request), ADMIN_ACTION)
+            .map_err(denied)?;
+        self.broker
+            .as_ref()
+            .ok_or_else(topics_disabled)?
+            .delete_topic(&request.into_inner().name)
+            .await
+            .map_err(topic_status)?;
+        Ok(Response::new(DeleteTopicResponse {}))
+    }
+
+    async fn list_topics(
+        &self,
+        request: Request<ListTopicsRequest>,
+    ) -> Result<Response<ListTopicsResponse>, Status> {
+        self.authorize(&auth::subject(&request), CONSUME_ACTION)
+            .map_err(denied)?;
+        let topics = self
+            .broker
+            .as_ref()
+            .ok_or_else(topics_disabled)?
+            .list_topics()
+            .await
+            .into_iter()
+            .map(|(name, partitions)| Topic { name, partitions })
+            .collect();
+        Ok(Response::new(ListTopicsResponse { topics }))
+    }
+
+    async fn commit_offset(
+        &self,
+        request: Request<CommitOffsetRequest>,
+    ) -> Result<Response<CommitOffsetResponse>, Status> {
+        self.authorize(&auth::subject(&request), CONSUME_ACTION)
+            .map_err(denied)?;
+        let req = request.into_inner();
+        let offsets = self.offsets.as_ref().ok_or_else(offsets_disabled)?;
+        if req.group.is_empty() {
+            return Err(Status::invalid_argument("group is required"));
+        }
+        // que no se guarden offsets de topics que no existen
+        self.log_for(&req.topic, req.partition).await?;
+        offsets
+            .commit(&req.group, &req.topic, req.partition, req.offset)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(CommitOffsetResponse {}))
+    }
+
+    async fn fetch_offset(
+        &self,
+        request: Request<FetchOffsetRequest>,
+    ) -> Result<Response<FetchOffsetResponse>, Status> {
+        self.authorize(&auth::subject(&request), CONSUME_ACTION)
+            .map_err(denied)?;
+        let req = request.into_inner();
+        let offsets = self.offsets.as_ref().ok_or_else(offsets_disabled)?;
+        let offset = offsets
+            .fetch(&req.group, &req.topic, req.partition)
+            .await
+            .ok_or_else(|| {
+                Status::not_found(format!("no committed offset for group {}", req.group))
+            })?;
+        Ok(Response::new(FetchOffsetResponse { offset }))
+    }
+
+    type ConsumeStreamStream = ResponseStream<ConsumeResponse>;
+
+    async fn consume_stream(
+        &self,
+        request: Request<ConsumeRequest>,
+    ) -> Result<Response<Self::ConsumeStreamStream>, Status> {
+        let client = auth::subject(&request);
+        self.authorize(&client, CONSUME_ACTION).map_err(denied)?;
+        self.throttle(&client, 1, 0)
+            .map_err(|wait| quota::exhausted(&client, wait))?;
+        let req = request.into_inner();
+        let filter = match req.filter.as_str() {
+            "" => None,
+            expr => Some(Filter::parse(expr).map_err(|e| to_status(e, req.offset))?),
+        };
+        let mut offset = req.offset;
+        if !req.group.is_empty() {
+            let offsets = self.offsets.as_ref().ok_or_else(offsets_disabled)?;
+            if let Some(committed) = offsets.fetch(&req.group, &req.topic, req.partition).await {
+                offset = committed;
+            }
+        }
+        let commit_log = self.log_for(&req.topic, req.partition).await?;
+        let (tx, rx) = mpsc::channel(16);
+
+        let read_committed = req.read_committed;
+        let service = self.clone();
+        let closing = self.closing.subscribe();
+        let open = metrics::StreamGuard::new("ConsumeStream");
+        let task = async move {
+            let _open = open;
+            // los que el filtro se saltó desde la última respuesta
+            let mut skipped = 0;
+            loop {
+                let read = if read_committed {
+                    commit_log.read_committed(offset).await
+                } else {
+                    commit_log.read(offset).await.map(Some)
+                };
+                match read {
+                    Ok(Some(record)) if filter.as_ref().is_none_or(|f| f.matches(&record)) => {
+                        // en el stream la cuota no corta, solo frena la lectura
+                        let n = record.encoded_len() as u64;
+                        while let Err(wait) = service.throttle(&client, 0, n) {
+                            if tx.is_closed() {
+                                return;
+                            }
+                            sleep(wait).await;
+                        }
+                        let res = ConsumeResponse {
+                            record: Some(record),
+                            next_offset: offset + 1,
+                        };
+                        if tx.send(Ok(res)).await.is_err() {
+                            return;
+                        }
+                        offset += 1;
+                        skipped = 0;
+                    }
+                    // marcador, registro abortado o uno que el filtro no
+                    // quiere: no se manda
+                    Ok(_) => {
+                        offset += 1;
+                        // con filtro, que el cliente no se quede sin saber nada
+                        // mucho tiempo
+                        if filter.is_some() {
+                            skipped += 1;
+                            if skipped == SKIPPED_PROGRESS {
+                                if tx.send(Ok(progress(offset))).await.is_err() {
+                                    return;
+                                }
+                                skipped = 0;
+                            }
+                        }
+                    }
+                    // todavía no hay nada en ese offset (o su transacción no ha
+                    // terminado), esperamos a que lo escriban
+                    Err(e)
+                        if e.kind() == io::ErrorKind::NotFound
+                            || e.kind() == io::ErrorKind::WouldBlock =>
+                    {
+                        // con filtro, hasta dónde se leyó antes de esperar
+                        if skipped > 0 {
+                            if tx.send(Ok(progress(offset))).await.is_err() {
+                                return;
+                            }
+                            skipped = 0;
+                        }
+                        if tx.is_closed() {
+                            return;
+                        }
+                        // ya se mandó todo lo que había
+                        if *closing.borrow() {
+                            let _ = tx.send(Err(shutting_down())).await;
+                            return;
+                        }
+                        sleep(Duration::from_millis(10)).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(Err(to_status(e, offset))).await;
+                        return;
+                    }
+                }
+            }
+        };
+        // la tarea sigue en el span del rpc que abrió el stream
+        tokio::spawn(task.instrument(tracing::Span::current()));
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    type ProduceStreamStream = ResponseStream<ProduceResponse>;
+
+    async fn produce_stream(
+        &self,
+        request: Request<Streaming<ProduceRequest>>,
+    ) -> Result<Response<Self::ProduceStreamStream>, Status> {
+        let client = auth::subject(&request);
+        self.authorize(&client, PRODUCE_ACTION).map_err(denied)?;
+        let mut stream = request.into_inner();
+        let service = self.clone();
+        let (tx, rx) = mpsc::channel(16);
+
+        let mut closing = self.closing.subscribe();
+        let open = metrics::StreamGuard::new("ProduceStream");
+        let task = async move {
+            let _open = open;
+            loop {
+                let message = tokio::select! {
+                    message = stream.message() => message,
+                    _ = async { closing.wait_for(|c| *c).await.map(|_| ()) } => {
+                        let _ = tx.send(Err(shutting_down())).await;
+                        return;
+                    }
+                };
+                let req = match message {
+                    Ok(Some(req)) => req,
+                    Ok(None) => return,
+                    Err(status) => {
+                        let _ = tx.send(Err(status)).await;
+                        return;
+                    }
+                };
+                // Igual que en ConsumeStream: sin leer el siguiente mensaje
+                // hasta que haya cuota, y HTTP/2 frena al cliente.
+                while let Err(wait) = service.throttle(&client, 1, req.encoded_len() as u64) {
+                    if tx.is_closed() {
+                        return;
+                    }
+                    sleep(wait).await;
+                }
+                let res = service.append(req).await;
+                let failed = res.is_err();
+                if tx.send(res).await.is_err() || failed {
+                    return;
+                }
+            }
+        };
+        tokio::spawn(task.instrument(tracing::Span::current()));
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comp::broker::partition_for_key;
+    use crate::comp::config::test_config;
+    use crate::comp::log::{CONTROL_ABORT, CONTROL_BEGIN, CONTROL_COMMIT, CONTROL_NONE};
+    use crate::comp::record::log_client::LogClient;
+    use crate::comp::trace::TraceLayer;
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tokio_stream::StreamExt;
+    use tonic::transport::{Channel, Server};
+
+    async fn setup(dir: &tempfile::TempDir) -> LogClient<Channel> {
+        let path = dir.path().join("log");
+        std::fs::create_dir_all(&path).unwrap();
+        let log = Log::new(path.to_str().unwrap(), test_config())
+            .await
+            .unwrap();
+        let commit_log: Arc<dyn CommitLog> = Arc::new(RwLock::new(log));
+        let broker = Broker::new(dir.path().join("topics").to_str().unwrap(), test_config())
+            .await
+            .unwrap();
+        let offsets = OffsetStore::new(dir.path().join("offsets").to_str().unwrap(), test_config())
+            .await
+            .unwrap();
+
+        serve(ServerConfig {
+            commit_log,
+            broker: Some(Arc::new(broker)),
+            offsets: Some(Arc::new(offsets)),
+            authorizer: None,
+            quotas: None,
+        })
+        .await
+    }
+
+    async fn serve(config: ServerConfig) -> LogClient<Channel> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .layer(metrics::RpcMetricsLayer)
+                .layer(TraceLayer)
+                .add_service(new_grpc_server(config))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        LogClient::connect(format!("http://{}", addr)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn produce_consume() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = setup(&dir).await;
+
+        let want = Record {
+            value: b"hello world".to_vec(),
+            ..Record::default()
+        };
+        let produce = client
+            .produce(ProduceRequest {
+                record: Some(want.clone()),
+                ..ProduceRequest::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let consume = client
+            .consume(ConsumeRequest {
+                offset: produce.offset,
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let got = consume.record.unwrap();
+        assert_eq!(got.value, want.value);
+        assert_eq!(got.offset, produce.offset);
+    }
+
+    #[tokio::test]
+    async fn produce_retry_is_deduplicated() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = setup(&dir).await;
+
+        let req = ProduceRequest {
+            record: Some(Record {
+                value: b"una vez".to_vec(),
+                producer_id: 42,
+                sequence: 0,
+                ..Record::default()
+            }),
+            ..ProduceRequest::default()
+        };
+        let first = client.produce(req.clone()).await.unwrap().into_inner();
+        let retry = client.produce(req).await.unwrap().into_inner();
+        assert_eq!(first.offset, retry.offset);
+        let err = client
+            .consume(ConsumeRequest {
+                offset: first.offset + 1,
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn consume_past_boundary() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = setup(&dir).await;
+
+        let produce = client
+            .produce(ProduceRequest {
+                record: Some(Record {
+                    value: b"hello world".to_vec(),
+                    ..Record::default()
+                }),
+                ..ProduceRequest::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let err = client
+            .consume(ConsumeRequest {
+                offset: produce.offset + 1,
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn metrics() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = setup(&dir).await;
+
+        let produce = client
+            .produce(ProduceRequest {
+                record: Some(Record {
+                    value: b"contado".to_vec(),
+                    ..Record::default()
+                }),
+                ..ProduceRequest::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        client
+            .consume(ConsumeRequest {
+                offset: produce.offset + 1,
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap_err();
+
+        // el registry es de todo el proceso y los demás tests también suman,
+        // así que solo vemos que las series existan
+        let text = metrics::gather();
+        assert!(text.contains(r#"rpc_requests_total{code="Ok",method="Produce"}"#));
+        assert!(text.contains(r#"rpc_requests_total{code="NotFound",method="Consume"}"#));
+        assert!(text.contains(r#"rpc_duration_seconds_count{method="Produce"}"#));
+        assert!(text.contains("log_appends_total"));
+        assert!(text.contains("log_segments"));
+    }
+
+    #[tokio::test]
+    async fn acl_without_client_cert() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Log::new(dir.path().to_str().unwrap(), test_config())
+            .await
+            .unwrap();
+        let policy = "p, root, *, produce\np, root, *, consume\n";
+        let mut client = serve(ServerConfig {
+            authorizer: Some(Arc::new(Authorizer::parse(policy).unwrap())),
+            ..ServerConfig::new(Arc::new(RwLock::new(log)))
+        })
+        .await;
+
+        // sin TLS no hay CN y el subject vacío no está en la política
+        let err = client
+            .produce(ProduceRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        assert_eq!(err.message(), " not permitted to produce to *");
+        let err = client
+            .consume_stream(ConsumeRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        // los topics y los offsets de los grupos también pasan por la ACL
+        let err = client
+            .create_topic(CreateTopicRequest {
+                name: "pedidos".to_string(),
+                partitions: 1,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.message(), " not permitted to admin to *");
+        let err = client
+            .delete_topic(DeleteTopicRequest {
+                name: "pedidos".to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        let err = client
+            .list_topics(ListTopicsRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        let err = client
+            .commit_offset(CommitOffsetRequest {
+                group: "g".to_string(),
+                ..CommitOffsetRequest::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.message(), " not permitted to consume to *");
+        let err = client
+            .fetch_offset(FetchOffsetRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn quotas() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Log::new(dir.path().to_str().unwrap(), test_config())
+            .await
+            .unwrap();
+        let limit = quota::Limit {
+            requests_per_sec: 2,
+            bytes_per_sec: 0,
+        };
+        let mut client = serve(ServerConfig {
+            quotas: Some(Arc::new(Quotas::new(limit, []))),
+            ..ServerConfig::new(Arc::new(RwLock::new(log)))
+        })
+        .await;
+
+        let req = ProduceRequest {
+            record: Some(Record {
+                value: b"limitado".to_vec(),
+                ..Record::default()
+            }),
+            ..ProduceRequest::default()
+        };
+        client.produce(req.clone()).await.unwrap();
+        client.produce(req.clone()).await.unwrap();
+        let err = client.produce(req.clone()).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+        let ms: u64 = err
+            .metadata()
+            .get(quota::RETRY_AFTER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(ms > 0 && ms <= 500, "{}", ms);
+
+        // después de esperar lo que dice vuelve a pasar
+        sleep(Duration::from_millis(ms)).await;
+        client.produce(req).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn produce_consume_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = setup(&dir).await;
+
+        let records: Vec<Record> = [&b"first message"[..], b"second message"]
+            .iter()
+            .map(|v| Record {
+                value: v.to_vec(),
+                ..Record::default()
+            })
+            .collect();
+
+        let reqs: Vec<ProduceRequest> = records
+            .iter()
+            .map(|r| ProduceRequest {
+                record: Some(r.clone()),
+                ..ProduceRequest::default()
+            })
+            .collect();
+        let mut produced = client
+            .produce_stream(tokio_stream::iter(reqs))
+            .await
+            .unwrap()
+            .into_inner();
+        for offset in 0..records.len() as u64 {
+            assert_eq!(produced.next().await.unwrap().unwrap().offset, offset);
+        }
+
+        let mut consumed = client
+            .consume_stream(ConsumeRequest::default())
+            .await
+            .unwrap()
+            .into_inner();
+        for (offset, want) in records.iter().enumerate() {
+            let got = consumed.next().await.unwrap().unwrap().record.unwrap();
+            assert_eq!(got.value, want.value);
+            assert_eq!(got.offset, offset as u64);
+        }
+    }
+
+    #[tokio::test]
+    async fn consume_stream_with_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = setup(&dir).await;
+
+        for (tipo, value) in [
+            ("pedido", r#"{"cliente": 1}"#),
+            ("pago", r#"{"cliente": 1}"#),
+            ("pedido", r#"{"cliente": 2}"#),
+            ("pago", r#"{"cliente": 2}"#),
+        ] {
+            let record = Record {
+                value: value.as_bytes().to_vec(),
+                headers: [("tipo".to_string(), tipo.to_string())].into(),
+                ..Record::default()
+            };
+            client
+                .produce(ProduceRequest {
+                    record: Some(record),
+                    ..ProduceRequest::default()
+                })
+                .await
+                .unwrap();
+        }
+
+        let mut consumed = client
+            .consume_stream(ConsumeRequest {
+                filter: r#"header.tipo == "pedido" && json.cliente == 2"#.to_string(),
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let res = consumed.next().await.unwrap().unwrap();
+        assert_eq!(res.record.unwrap().offset, 2);
+        assert_eq!(res.next_offset, 3);
+        // se saltó el último y avisa hasta dónde leyó
+        let res = consumed.next().await.unwrap().unwrap();
+        assert_eq!((res.record, res.next_offset), (None, 4));
+
+        // con read_committed los marcadores también cuentan como saltados
+        for control in [CONTROL_BEGIN, CONTROL_ABORT] {
+            let record = Record {
+                transaction_id: 3,
+                control,
+                ..Record::default()
+            };
+            client
+                .produce(ProduceRequest {
+                    record: Some(record),
+                    ..ProduceRequest::default()
+                })
+                .await
+                .unwrap();
+        }
+        let mut consumed = client
+            .consume_stream(ConsumeRequest {
+                offset: 4,
+                read_committed: true,
+                filter: r#"header.tipo == "pedido""#.to_string(),
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let res = timeout(Duration::from_secs(1), consumed.next())
+            .await
+            .expect("no progress after the skipped markers")
+            .unwrap()
+            .unwrap();
+        assert_eq!((res.record, res.next_offset), (None, 6));
+
+        let err = client
+            .consume_stream(ConsumeRequest {
+                filter: "offset == 1".to_string(),
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        let err = client
+            .consume(ConsumeRequest {
+                filter: "key ^= \"a\"".to_string(),
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_streams() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Log::new(dir.path().to_str().unwrap(), test_config())
+            .await
+            .unwrap();
+        let service = new_log_service(ServerConfig::new(Arc::new(RwLock::new(log))));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(log_server::LogServer::new(service.clone()))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let mut client = LogClient::connect(format!("http://{}", addr)).await.unwrap();
+
+        let req = |value: &[u8]| ProduceRequest {
+            record: Some(Record {
+                value: value.to_vec(),
+                ..Record::default()
+            }),
+            ..ProduceRequest::default()
+        };
+        let (tx, rx) = mpsc::channel(4);
+        let mut produced = client
+            .produce_stream(ReceiverStream::new(rx))
+            .await
+            .unwrap()
+            .into_inner();
+        for (offset, value) in [&b"uno"[..], b"dos"].iter().enumerate() {
+            tx.send(req(value)).await.unwrap();
+            assert_eq!(
+                produced.next().await.unwrap().unwrap().offset,
+                offset as u64
+            );
+        }
+        let mut consumed = client
+            .consume_stream(ConsumeRequest::default())
+            .await
+            .unwrap()
+            .into_inner();
+        let first = consumed.next().await.unwrap().unwrap();
+        assert_eq!(first.record.unwrap().value, b"uno");
+
+        service.shutdown();
+        // el consumidor recibe lo que faltaba y luego UNAVAILABLE
+        let second = consumed.next().await.unwrap().unwrap();
+        assert_eq!(second.record.unwrap().value, b"dos");
+        let err = consumed.next().await.unwrap().unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unavailable);
+        let err = produced.next().await.unwrap().unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn produce_consume_topics() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = setup(&dir).await;
+
+        client
+            .create_topic(CreateTopicRequest {
+                name: "pedidos".to_string(),
+                partitions: 3,
+            })
+            .await
+            .unwrap();
+        let err = client
+            .create_topic(CreateTopicRequest {
+                name: "pedidos".to_string(),
+                partitions: 1,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::AlreadyExists);
+        let topics = client
+            .list_topics(ListTopicsRequest {})
+            .await
+            .unwrap()
+            .into_inner()
+            .topics;
+        assert_eq!(topics.len(), 1);
+        assert_eq!(topics[0].partitions, 3);
+
+        // los registros con la misma key caen en la misma partición
+        let mut partitions = vec![];
+        for value in [&b"uno"[..], b"dos"] {
+            let res = client
+                .produce(ProduceRequest {
+                    record: Some(Record {
+                        value: value.to_vec(),
+                        key: b"cliente-7".to_vec(),
+                        ..Record::default()
+                    }),
+                    topic: "pedidos".to_string(),
+                    ..ProduceRequest::default()
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(res.partition, partition_for_key(b"cliente-7", 3));
+            partitions.push((res.partition, res.offset));
+        }
+        assert_eq!(partitions[1].1, partitions[0].1 + 1);
+
+        let got = client
+            .consume(ConsumeRequest {
+                offset: partitions[1].1,
+                topic: "pedidos".to_string(),
+                partition: partitions[1].0,
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .record
+            .unwrap();
+        assert_eq!(got.value, b"dos".to_vec());
+
+        // el log del nodo no se mezcla con los topics
+        let err = client.consume(ConsumeRequest::default()).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+
+        let err = client
+            .consume(ConsumeRequest {
+                topic: "no-existe".to_string(),
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+
+        client
+            .delete_topic(DeleteTopicRequest {
+                name: "pedidos".to_string(),
+            })
+            .await
+            .unwrap();
+        let topics = client
+            .list_topics(ListTopicsRequest {})
+            .await
+            .unwrap()
+            .into_inner()
+            .topics;
+        assert!(topics.is_empty());
+    }
+
+    #[tokio::test]
+    async fn consume_stream_resumes_from_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Log::new(dir.path().to_str().unwrap(), test_config())
+            .await
+            .unwrap();
+        let commit_log: Arc<dyn CommitLog> = Arc::new(RwLock::new(log));
+        let offsets_dir = tempfile::tempdir().unwrap();
+        let offsets_path = offsets_dir.path().to_str().unwrap();
+        let offsets = Arc::new(OffsetStore::new(offsets_path, test_config()).await.unwrap());
+        let mut server = ServerConfig::new(Arc::clone(&commit_log));
+        server.offsets = Some(Arc::clone(&offsets));
+        let mut client = serve(server).await;
+
+        for value in [&b"uno"[..], b"dos", b"tres"] {
+            commit_log
+                .append(Record {
+                    value: value.to_vec(),
+                    ..Record::default()
+                })
+                .await
+                .unwrap();
+        }
+
+        let fetch = FetchOffsetRequest {
+            group: "lectores".to_string(),
+            ..FetchOffsetRequest::default()
+        };
+        let err = client.fetch_offset(fetch.clone()).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+        let err = client
+            .commit_offset(CommitOffsetRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        // sin broker no hay topics a los que hacerles commit
+        let err = client
+            .commit_offset(CommitOffsetRequest {
+                group: "lectores".to_string(),
+                topic: "pedidos".to_string(),
+                ..CommitOffsetRequest::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unimplemented);
+
+        client
+            .commit_offset(CommitOffsetRequest {
+                group: "lectores".to_string(),
+                offset: 2,
+                ..CommitOffsetRequest::default()
+            })
+            .await
+            .unwrap();
+        offsets.close().await.unwrap();
+
+        // otro servidor con el mismo directorio, como si el nodo se reiniciara
+        let offsets = OffsetStore::new(offsets_path, test_config()).await.unwrap();
+        let mut server = ServerConfig::new(commit_log);
+        server.offsets = Some(Arc::new(offsets));
+        let mut client = serve(server).await;
+
+        let got = client.fetch_offset(fetch).await.unwrap().into_inner();
+        assert_eq!(got.offset, 2);
+        let mut consumed = client
+            .consume_stream(ConsumeRequest {
+                group: "lectores".to_string(),
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let got = consumed.next().await.unwrap().unwrap().record.unwrap();
+        assert_eq!(got.offset, 2);
+        assert_eq!(got.value, b"tres".to_vec());
+
+        // un grupo sin commit empieza en el offset que pidió
+        let mut consumed = client
+            .consume_stream(ConsumeRequest {
+                offset: 1,
+                group: "nuevo".to_string(),
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let got = consumed.next().await.unwrap().unwrap().record.unwrap();
+        assert_eq!(got.offset, 1);
+    }
+
+    #[tokio::test]
+    async fn consume_read_committed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = setup(&dir).await;
+
+        let record = |transaction_id: u64, control: u32, value: &[u8]| ProduceRequest {
+            record: Some(Record {
+                value: value.to_vec(),
+                transaction_id,
+                control,
+                ..Record::default()
+            }),
+            ..ProduceRequest::default()
+        };
+        let reqs = vec![
+            record(9, CONTROL_BEGIN, b""),
+            record(9, CONTROL_NONE, b"abortado"),
+            record(9, CONTROL_ABORT, b""),
+            record(10, CONTROL_BEGIN, b""),
+            record(10, CONTROL_NONE, b"confirmado"),
+            record(0, CONTROL_NONE, b"suelto"),
+            record(10, CONTROL_COMMIT, b""),
+        ];
+        for req in reqs {
+            client.produce(req).await.unwrap();
+        }
+        let err = client.produce(record(11, CONTROL_COMMIT, b"")).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        let mut consumed = client
+            .consume_stream(ConsumeRequest {
+                read_committed: true,
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        for want in [&b"confirmado"[..], b"suelto"] {
+            let got = consumed.next().await.unwrap().unwrap().record.unwrap();
+            assert_eq!(got.value, want.to_vec());
+        }
+
+        let err = client
+            .consume(ConsumeRequest {
+                offset: 1,
+                read_committed: true,
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+        let got = client
+            .consume(ConsumeRequest {
+                offset: 1,
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(got.record.unwrap().value, b"abortado".to_vec());
+
+        // lo de una transacción abierta todavía no se puede leer
+        client
+            .produce(record(12, CONTROL_BEGIN, b""))
+            .await
+            .unwrap();
+        let off = client
+            .produce(record(12, CONTROL_NONE, b"pendiente"))
+            .await
+            .unwrap()
+            .into_inner()
+            .offset;
+        let err = client
+            .consume(ConsumeRequest {
+                offset: off,
+                read_committed: true,
+                ..ConsumeRequest::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unavailable);
+    }
+}
@@ -0,0 +1,622 @@
+use crate::comp::config::{Compression, Config, SegmentConfig, Tiering};
+use crate::comp::encryption::Keyring;
+use crate::comp::quota::{Limit, Quotas};
+use crate::comp::tiered::LocalObjectStore;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+// La configuración del servidor. Cada fuente pisa a la anterior: los
+// defaults, el archivo de --config (TOML o YAML según la extensión), las
+// variables LOG_* y al final los argumentos. Las rutas relativas de TLS y ACL
+// se buscan en CONFIG_DIR, igual que config/files.go.
+//
+// Las variables son el nombre del campo en mayúsculas con su sección:
+// LOG_DATA_DIR, LOG_SEGMENT_MAX_STORE_BYTES, LOG_TLS_CERT_FILE, ... Las
+// listas (LOG_PEERS, LOG_JOIN_ADDRS) van separadas por comas.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub id: String,
+    pub data_dir: String,
+    pub rpc_addr: String,
+    pub http_addr: Option<String>,
+    pub raft: bool,
+    pub bootstrap: bool,
+    // <id>=<addr>, como --peer
+    pub peers: Vec<String>,
+    pub gossip_addr: Option<String>,
+    pub join_addrs: Vec<String>,
+    pub compression: String,
+    pub keyfile: Option<String>,
+    pub tier_dir: Option<String>,
+    pub segment: SegmentSettings,
+    pub tls: TlsSettings,
+    pub acl: AclSettings,
+    pub retention: RetentionSettings,
+    pub quotas: QuotaSettings,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SegmentSettings {
+    pub max_store_bytes: u64,
+    pub max_index_bytes: u64,
+    pub initial_offset: u64,
+}
+
+// con cert_file y key_file gRPC va por TLS; con ca_file además se le pide su
+// certificado al cliente
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSettings {
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+    pub ca_file: Option<String>,
+}
+
+// el model.conf y el policy.csv de casbin, ver comp/auth.rs
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AclSettings {
+    pub model_file: Option<String>,
+    pub policy_file: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionSettings {
+    // cuantos registros se guardan en cada log, 0 es sin límite
+    pub max_records: u64,
+    pub interval_secs: u64,
+}
+
+// Límites de Produce y Consume por cliente (ver comp/quota.rs); los que no
+// vienen en clients usan default. Por ejemplo:
+//
+//   [quotas.default]
+//   requests_per_sec = 100
+//   [quotas.clients.root]
+//   bytes_per_sec = 10485760
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaSettings {
+    pub default: Limit,
+    // por CN del certificado
+    pub clients: BTreeMap<String, Limit>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            id: String::new(),
+            data_dir: String::new(),
+            rpc_addr: String::new(),
+            http_addr: None,
+            raft: false,
+            bootstrap: false,
+            peers: vec![],
+            gossip_addr: None,
+            join_addrs: vec![],
+            compression: "none".to_string(),
+            keyfile: None,
+            tier_dir: None,
+            segment: SegmentSettings::default(),
+            tls: TlsSettings::default(),
+            acl: AclSettings::default(),
+            retention: RetentionSettings::default(),
+            quotas: QuotaSettings::default(),
+        }
+    }
+}
+
+impl Default for SegmentSettings {
+    fn default() -> Self {
+        SegmentSettings {
+            max_store_bytes: 1024,
+            max_index_bytes: 1200,
+            initial_offset: 0,
+        }
+    }
+}
+
+impl Default for RetentionSettings {
+    fn default() -> Self {
+        RetentionSettings {
+            max_records: 0,
+            interval_secs: 60,
+        }
+    }
+}
+
+impl Settings {
+    // args sin el nombre del programa; var lee el entorno (std::env::var en main)
+    pub fn load(args: &[String], var: impl Fn(&str) -> Option<String>) -> io::Result<Self> {
+        let mut settings = match config_path(args)? {
+            Some(path) => Settings::from_file(path)?,
+            None => Settings::default(),
+        };
+        settings.apply_env(&var)?;
+        settings.apply_args(args)?;
+        if let Some(dir) = var("CONFIG_DIR").filter(|d| !d.is_empty()) {
+            settings.resolve_files(Path::new(&dir));
+        }
+        Ok(settings)
+    }
+
+    pub fn from_file(path: &str) -> io::Result<Self> {
+        let yaml = match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("toml") => false,
+            Some("yaml" | "yml") => true,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{}: the config file must be .toml, .yaml or .yml", path),
+                ))
+            }
+        };
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+        let invalid =
+            |e: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, e));
+        if yaml {
+            serde_yaml::from_str(&text).map_err(|e| invalid(e.to_string()))
+        } else {
+            toml::from_str(&text).map_err(|e| invalid(e.to_string()))
+        }
+    }
+
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> io::Result<()> {
+        let var = |name: &str| var(name).filter(|v| !v.is_empty());
+        let number = |name: &str, n: &mut u64| -> io::Result<()> {
+            if let Some(v) = var(name) {
+                *n = v.parse().map_err(|_| env_error(name, &v, "a number"))?;
+            }
+            Ok(())
+        };
+        let flag = |name: &str, b: &mut bool| -> io::Result<()> {
+            if let Some(v) = var(name) {
+                *b = v
+                    .parse()
+                    .map_err(|_| env_error(name, &v, "true or false"))?;
+            }
+            Ok(())
+        };
+        let list = |name: &str, l: &mut Vec<String>| {
+            if let Some(v) = var(name) {
+                *l = v.split(',').map(|s| s.trim().to_string()).collect();
+            }
+        };
+
+        if let Some(v) = var("LOG_ID") {
+            self.id = v;
+        }
+        if let Some(v) = var("LOG_DATA_DIR") {
+            self.data_dir = v;
+        }
+        if let Some(v) = var("LOG_RPC_ADDR") {
+            self.rpc_addr = v;
+        }
+        if let Some(v) = var("LOG_COMPRESSION") {
+            self.compression = v;
+        }
+        flag("LOG_RAFT", &mut self.raft)?;
+        flag("LOG_BOOTSTRAP", &mut self.bootstrap)?;
+        list("LOG_PEERS", &mut self.peers);
+        list("LOG_JOIN_ADDRS", &mut self.join_addrs);
+        for (name, field) in [
+            ("LOG_HTTP_ADDR", &mut self.http_addr),
+            ("LOG_GOSSIP_ADDR", &mut self.gossip_addr),
+            ("LOG_KEYFILE", &mut self.keyfile),
+            ("LOG_TIER_DIR", &mut self.tier_dir),
+            ("LOG_TLS_CERT_FILE", &mut self.tls.cert_file),
+            ("LOG_TLS_KEY_FILE", &mut self.tls.key_file),
+            ("LOG_TLS_CA_FILE", &mut self.tls.ca_file),
+            ("LOG_ACL_MODEL_FILE", &mut self.acl.model_file),
+            ("LOG_ACL_POLICY_FILE", &mut self.acl.policy_file),
+        ] {
+            if let Some(v) = var(name) {
+                *field = Some(v);
+            }
+        }
+        number(
+            "LOG_SEGMENT_MAX_STORE_BYTES",
+            &mut self.segment.max_store_bytes,
+        )?;
+        number(
+            "LOG_SEGMENT_MAX_INDEX_BYTES",
+            &mut self.segment.max_index_bytes,
+        )?;
+        number(
+            "LOG_SEGMENT_INITIAL_OFFSET",
+            &mut self.segment.initial_offset,
+        )?;
+        number("LOG_RETENTION_MAX_RECORDS", &mut self.retention.max_records)?;
+        number(
+            "LOG_RETENTION_INTERVAL_SECS",
+            &mut self.retention.interval_secs,
+        )?;
+        let default = &mut self.quotas.default;
+        number(
+            "LOG_QUOTAS_DEFAULT_REQUESTS_PER_SEC",
+            &mut default.requests_per_sec,
+        )?;
+        number(
+            "LOG_QUOTAS_DEFAULT_BYTES_PER_SEC",
+            &mut default.bytes_per_sec,
+        )?;
+        Ok(())
+    }
+
+    // <id> <data_dir> <rpc_addr> siguen siendo posicionales, pero con --config
+    // pueden venir del archivo
+    fn apply_args(&mut self, args: &[String]) -> io::Result<()> {
+        let mut positional = 0;
+        let mut rest = args.iter();
+        while let Some(arg) = rest.next() {
+            let mut value = || {
+                rest.next().cloned().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} needs a value", arg),
+                    )
+                })
+            };
+            match arg.as_str() {
+                // ya los leyó load y main
+                "--config" => {
+                    value()?;
+                }
+                "--print-config" => {}
+                "--raft" => self.raft = true,
+                "--bootstrap" => self.bootstrap = true,
+                "--peer" => self.peers.push(value()?),
+                "--gossip" => self.gossip_addr = Some(value()?),
+                "--join" => self.join_addrs.push(value()?),
+                "--compression" => self.compression = value()?,
+                "--keyfile" => self.keyfile = Some(value()?),
+                "--tier-dir" => self.tier_dir = Some(value()?),
+                "--http" => self.http_addr = Some(value()?),
+                flag if flag.starts_with("--") => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unknown flag {}", flag),
+                    ));
+                }
+                _ => {
+                    let field = match positional {
+                        0 => &mut self.id,
+                        1 => &mut self.data_dir,
+                        2 => &mut self.rpc_addr,
+                        _ => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                format!("unexpected argument {}", arg),
+                            ))
+                        }
+                    };
+                    *field = arg.clone();
+                    positional += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_files(&mut self, dir: &Path) {
+        for file in [
+            &mut self.tls.cert_file,
+            &mut self.tls.key_file,
+            &mut self.tls.ca_file,
+            &mut self.acl.model_file,
+            &mut self.acl.policy_file,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if Path::new(file).is_relative() {
+                *file = dir.join(&*file).to_string_lossy().into_owned();
+            }
+        }
+    }
+
+    // lo que falta o no cuadra, antes de abrir nada
+    pub fn check(&self) -> io::Result<()> {
+        let invalid = |msg: String| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        for (name, value, var) in [
+            ("id", &self.id, "LOG_ID"),
+            ("data_dir", &self.data_dir, "LOG_DATA_DIR"),
+            ("rpc_addr", &self.rpc_addr, "LOG_RPC_ADDR"),
+        ] {
+            if value.is_empty() {
+                return invalid(format!(
+                    "missing {}: pass it as an argument, in the config file or in {}",
+                    name, var
+                ));
+            }
+        }
+        self.compression.parse::<Compression>()?;
+        self.peers()?;
+        if self.tls.cert_file.is_some() != self.tls.key_file.is_some() {
+            return invalid("tls needs both cert_file and key_file".to_string());
+        }
+        if self.tls.ca_file.is_some() && self.tls.cert_file.is_none() {
+            return invalid("tls.ca_file needs cert_file and key_file".to_string());
+        }
+        if self.acl.model_file.is_some() != self.acl.policy_file.is_some() {
+            return invalid("acl needs both model_file and policy_file".to_string());
+        }
+        // el subject es el CN del certificado del cliente
+        if self.acl.model_file.is_some() && self.tls.ca_file.is_none() {
+            return invalid("acl needs tls.ca_file to know who the clients are".to_string());
+        }
+        if self.retention.max_records > 0 && self.retention.interval_secs == 0 {
+            return invalid("retention.interval_secs must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn peers(&self) -> io::Result<Vec<(String, String)>> {
+        self.peers
+            .iter()
+            .map(|p| match p.split_once('=') {
+                Some((name, addr)) => Ok((name.to_string(), addr.to_string())),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("peer {:?} is not <id>=<addr>", p),
+                )),
+            })
+            .collect()
+    }
+
+    pub fn config(&self) -> io::Result<Config> {
+        let config = Config {
+            segment: SegmentConfig {
+                max_store_bytes: self.segment.max_store_bytes,
+                max_index_bytes: self.segment.max_index_bytes,
+                initial_offset: self.segment.initial_offset,
+            },
+            compression: self.compression.parse()?,
+            encryption: match &self.keyfile {
+                Some(path) => Some(Arc::new(Keyring::load(path)?)),
+                None => None,
+            },
+            // por ahora el object store es un directorio, por ejemplo un disco de red
+            tiering: self
+                .tier_dir
+                .as_ref()
+                .map(|dir| Tiering::new(Arc::new(LocalObjectStore::new(dir)))),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    // None si nadie tiene límite
+    pub fn quotas(&self) -> Option<Quotas> {
+        let quotas = &self.quotas;
+        if quotas.default.is_unlimited() && quotas.clients.values().all(Limit::is_unlimited) {
+            return None;
+        }
+        Some(Quotas::new(quotas.default, quotas.clients.clone()))
+    }
+
+    // None si gRPC va sin TLS
+    pub fn server_tls(&self) -> io::Result<Option<ServerTlsConfig>> {
+        let (Some(cert), Some(key)) = (&self.tls.cert_file, &self.tls.key_file) else {
+            return Ok(None);
+        };
+        let read = |path: &str| {
+            std::fs::read(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))
+        };
+        let mut tls = ServerTlsConfig::new().identity(Identity::from_pem(read(cert)?, read(key)?));
+        if let Some(ca) = &self.tls.ca_file {
+            tls = tls.client_ca_root(Certificate::from_pem(read(ca)?));
+        }
+        Ok(Some(tls))
+    }
+
+    // para --print-config
+    pub fn to_toml(&self) -> String {
+        // todos los campos son strings, números o tablas, no puede fallar
+        toml::to_string_pretty(self).expect("settings are always valid toml")
+    }
+}
+
+fn config_path(args: &[String]) -> io::Result<Option<&str>> {
+    match args.iter().position(|a| a == "--config") {
+        Some(i) => match args.get(i + 1) {
+            Some(path) => Ok(Some(path)),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--config needs a value",
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+fn env_error(name: &str, value: &str, expected: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{}={:?}: expected {}", name, value, expected),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn toml_and_yaml() {
+        let dir = tempfile::tempdir().unwrap();
+        let toml_path = dir.path().join("log.toml");
+        std::fs::write(
+            &toml_path,
+            r#"
+id = "uno"
+data_dir = "/var/lib/log"
+rpc_addr = "127.0.0.1:8400"
+peers = ["dos=127.0.0.1:8401"]
+
+[segment]
+max_store_bytes = 4096
+
+[tls]
+cert_file = "server.pem"
+key_file = "server-key.pem"
+
+[retention]
+max_records = 100
+
+[quotas.default]
+requests_per_sec = 50
+
+[quotas.clients.root]
+bytes_per_sec = 1048576
+"#,
+        )
+        .unwrap();
+        let yaml_path = dir.path().join("log.yaml");
+        std::fs::write(
+            &yaml_path,
+            "
+id: uno
+data_dir: /var/lib/log
+rpc_addr: 127.0.0.1:8400
+peers: [dos=127.0.0.1:8401]
+segment:
+  max_store_bytes: 4096
+tls:
+  cert_file: server.pem
+  key_file: server-key.pem
+retention:
+  max_records: 100
+quotas:
+  default:
+    requests_per_sec: 50
+  clients:
+    root:
+      bytes_per_sec: 1048576
+",
+        )
+        .unwrap();
+
+        let from_toml = Settings::from_file(toml_path.to_str().unwrap()).unwrap();
+        let from_yaml = Settings::from_file(yaml_path.to_str().unwrap()).unwrap();
+        assert_eq!(from_toml, from_yaml);
+        assert_eq!(from_toml.segment.max_store_bytes, 4096);
+        // lo que no viene queda con su default
+        assert_eq!(from_toml.segment.max_index_bytes, 1200);
+        assert_eq!(from_toml.retention.interval_secs, 60);
+        assert_eq!(from_toml.compression, "none");
+        assert_eq!(from_toml.quotas.default.requests_per_sec, 50);
+        assert_eq!(from_toml.quotas.clients["root"].bytes_per_sec, 1 << 20);
+        assert!(from_toml.quotas().is_some());
+        assert_eq!(
+            from_toml.peers().unwrap(),
+            [("dos".to_string(), "127.0.0.1:8401".to_string())]
+        );
+        from_toml.check().unwrap();
+
+        // --print-config se puede volver a leer
+        let printed = dir.path().join("printed.toml");
+        std::fs::write(&printed, from_toml.to_toml()).unwrap();
+        assert_eq!(
+            Settings::from_file(printed.to_str().unwrap()).unwrap(),
+            from_toml
+        );
+
+        let bad = dir.path().join("bad.toml");
+        std::fs::write(&bad, "data_dri = \"/tmp\"\n").unwrap();
+        let err = Settings::from_file(bad.to_str().unwrap()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("data_dri"), "{}", err);
+        let err = Settings::from_file("log.json").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn env_and_args_override_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.toml");
+        std::fs::write(
+            &path,
+            r#"
+id = "uno"
+data_dir = "/var/lib/log"
+rpc_addr = "127.0.0.1:8400"
+compression = "zstd"
+
+[segment]
+max_store_bytes = 4096
+
+[tls]
+cert_file = "server.pem"
+key_file = "/etc/log/server-key.pem"
+"#,
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        let settings = Settings::load(
+            &args(&format!("--config {} --http 127.0.0.1:8080", path)),
+            env(&[
+                ("LOG_DATA_DIR", "/tmp/log"),
+                ("LOG_SEGMENT_MAX_STORE_BYTES", "8192"),
+                ("LOG_RAFT", "true"),
+                ("LOG_HTTP_ADDR", "127.0.0.1:9090"),
+                ("CONFIG_DIR", "/etc/certs"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(settings.id, "uno");
+        assert_eq!(settings.data_dir, "/tmp/log");
+        assert_eq!(settings.compression, "zstd");
+        assert_eq!(settings.segment.max_store_bytes, 8192);
+        assert!(settings.raft);
+        // los argumentos le ganan al entorno
+        assert_eq!(settings.http_addr.as_deref(), Some("127.0.0.1:8080"));
+        // CONFIG_DIR solo cambia las rutas relativas
+        assert_eq!(
+            settings.tls.cert_file.as_deref(),
+            Some("/etc/certs/server.pem")
+        );
+        assert_eq!(
+            settings.tls.key_file.as_deref(),
+            Some("/etc/log/server-key.pem")
+        );
+
+        // sin archivo, como antes
+        let settings =
+            Settings::load(&args("dos /tmp/dos 127.0.0.1:8401 --raft"), env(&[])).unwrap();
+        assert_eq!(settings.id, "dos");
+        assert_eq!(settings.rpc_addr, "127.0.0.1:8401");
+        assert!(settings.raft);
+
+        let err = Settings::load(&args(""), env(&[("LOG_RAFT", "si")])).unwrap_err();
+        assert!(err.to_string().contains("LOG_RAFT"), "{}", err);
+        let err = Settings::load(&args("uno /tmp/uno"), env(&[]))
+            .unwrap()
+            .check()
+            .unwrap_err();
+        assert!(err.to_string().contains("rpc_addr"), "{}", err);
+        assert!(Settings::load(&args("--peer"), env(&[])).is_err());
+        assert!(Settings::load(&args("a b c d"), env(&[])).is_err());
+    }
+}
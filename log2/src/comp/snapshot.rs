@@ -0,0 +1,204 @@
+use crate::comp::config::Tiering;
+use crate::comp::record::{SnapshotManifest, SnapshotSegment};
+use crate::comp::storage::StorageFile;
+use prost::Message;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+const MANIFEST: &str = "manifest";
+
+// Un segmento como estaba al tomar el snapshot (ver Log::checkpoint). El store
+// se abre en el checkpoint: si después truncate, la retención o el offload
+// borran el segmento, el archivo abierto se sigue leyendo; los appends solo
+// escriben después de store_bytes.
+pub struct Part {
+    pub base_offset: u64,
+    pub next_offset: u64,
+    pub key_id: Option<String>,
+    pub source: Source,
+}
+
+pub enum Source {
+    // el index se copia al momento, del store solo los primeros store_bytes
+    Local {
+        index: Vec<u8>,
+        store: Box<dyn StorageFile>,
+        store_bytes: u64,
+    },
+    // ya está sellado en el object store, se baja al escribir
+    Remote(Tiering),
+}
+
+// lo que falta para escribir el tar; ya no necesita al Log
+pub struct Snapshot {
+    pub parts: Vec<Part>,
+}
+
+impl Snapshot {
+    // Escribe el tar en dest.tmp y al final lo renombra, así dest o no existe
+    // o está completo. Los segmentos se leen de uno en uno y el tar se escribe
+    // con spawn_blocking.
+    pub async fn write(self, dest: &str) -> io::Result<SnapshotManifest> {
+        let tmp = PathBuf::from(format!("{}.tmp", dest));
+        let file = tmp.clone();
+        let mut tar = blocking(move || Ok(tar::Builder::new(fs::File::create(file)?))).await?;
+        let mut manifest = SnapshotManifest::default();
+
+        for part in self.parts {
+            let base = part.base_offset;
+            let (index, store, key_id) = match part.source {
+                Source::Local {
+                    index,
+                    store,
+                    store_bytes,
+                } => {
+                    // solo truncate_from corta un store abierto
+                    let mut data = vec![0u8; store_bytes as usize];
+                    store.read_at(&mut data, 0).await.map_err(|e| {
+                        io::Error::new(
+                            e.kind(),
+                            format!("segment {} changed during the snapshot: {}", base, e),
+                        )
+                    })?;
+                    (index, data, part.key_id)
+                }
+                Source::Remote(tiering) => {
+                    let get = |ext: &str| {
+                        let key = format!("{}/{}.{}", tiering.prefix, base, ext);
+                        let store = tiering.store.clone();
+                        async move { store.get(&key).await }
+                    };
+                    let key_id = match get("key").await {
+                        Ok(id) => Some(String::from_utf8_lossy(&id).trim().to_string()),
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                        Err(e) => return Err(e),
+                    };
+                    (get("index").await?, get("store").await?, key_id)
+                }
+            };
+
+            manifest.segments.push(SnapshotSegment {
+                base_offset: base,
+                next_offset: part.next_offset,
+                store_bytes: store.len() as u64,
+                key_id: key_id.clone().unwrap_or_default(),
+            });
+            tar = blocking(move || {
+                append(&mut tar, &format!("{}.index", base), &index)?;
+                append(&mut tar, &format!("{}.store", base), &store)?;
+                if let Some(id) = &key_id {
+                    append(&mut tar, &format!("{}.key", base), id.as_bytes())?;
+                }
+                Ok(tar)
+            })
+            .await?;
+        }
+
+        let (data, dest) = (manifest.encode_to_vec(), dest.to_string());
+        blocking(move || {
+            append(&mut tar, MANIFEST, &data)?;
+            let file = tar.into_inner()?;
+            file.sync_all()?;
+            fs::rename(&tmp, dest)
+        })
+        .await?;
+        Ok(manifest)
+    }
+}
+
+// el tar y el directorio se tocan fuera del runtime
+pub async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> io::Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(io::Error::other)?
+}
+
+fn append(tar: &mut tar::Builder<fs::File>, name: &str, data: &[u8]) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.append_data(&mut header, name, data)
+}
+
+// Saca el snapshot en dir, que no debe existir o estar vacío. Se arma en
+// dir.restore y se renombra hasta revisar que no falte nada.
+pub fn unpack(archive: &str, dir: &str) -> io::Result<()> {
+    let dir = Path::new(dir);
+    if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} is not empty", dir.display()),
+        ));
+    }
+    let tmp = PathBuf::from(format!("{}.restore", dir.display()));
+    if tmp.exists() {
+        fs::remove_dir_all(&tmp)?;
+    }
+    fs::create_dir_all(&tmp)?;
+    if let Err(e) = extract(archive, &tmp) {
+        let _ = fs::remove_dir_all(&tmp);
+        return Err(e);
+    }
+
+    if dir.exists() {
+        fs::remove_dir(dir)?;
+    }
+    fs::rename(&tmp, dir)
+}
+
+fn extract(archive: &str, tmp: &Path) -> io::Result<()> {
+    let mut manifest = None;
+    let mut sizes = HashMap::new();
+    let mut tar = tar::Archive::new(fs::File::open(archive)?);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let name = match path.components().collect::<Vec<_>>()[..] {
+            [Component::Normal(name)] => name.to_string_lossy().into_owned(),
+            _ => return Err(invalid(format!("unexpected entry {}", path.display()))),
+        };
+        let mut data = vec![];
+        entry.read_to_end(&mut data)?;
+
+        if name == MANIFEST {
+            manifest = Some(SnapshotManifest::decode(&*data)?);
+            continue;
+        }
+        let valid = name.split_once('.').is_some_and(|(base, ext)| {
+            base.parse::<u64>().is_ok() && ["store", "index", "key"].contains(&ext)
+        });
+        if !valid {
+            return Err(invalid(format!("unexpected entry {}", name)));
+        }
+        let mut file = fs::File::create(tmp.join(&name))?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        sizes.insert(name, data.len() as u64);
+    }
+
+    // el manifest va al final: si está, el tar se escribió completo
+    let manifest = manifest.ok_or_else(|| invalid("snapshot has no manifest".to_string()))?;
+    for segment in &manifest.segments {
+        let base = segment.base_offset;
+        let store = sizes.get(&format!("{}.store", base));
+        if store != Some(&segment.store_bytes) || !sizes.contains_key(&format!("{}.index", base)) {
+            return Err(invalid(format!("segment {} is incomplete", base)));
+        }
+        if !segment.key_id.is_empty() && !sizes.contains_key(&format!("{}.key", base)) {
+            return Err(invalid(format!("segment {} has no key id", base)));
+        }
+    }
+    Ok(())
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid snapshot: {}", msg),
+    )
+}
@@ -0,0 +1,335 @@
+use memmap2::{MmapMut, MmapOptions};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::ops::DerefMut;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
+use tokio::sync::Mutex;
+
+// Dónde viven los archivos de un log (<base>.store, <base>.index, <base>.key).
+// DirStorage es un directorio de verdad y MemoryStorage los tiene en memoria,
+// para pruebas o caches que no necesitan sobrevivir al proceso.
+#[tonic::async_trait]
+pub trait Storage: Send + Sync + fmt::Debug {
+    // abre el archivo, si no existe lo crea vacío
+    async fn open(&self, name: &str) -> io::Result<Box<dyn StorageFile>>;
+    async fn exists(&self, name: &str) -> io::Result<bool>;
+    async fn list(&self) -> io::Result<Vec<String>>;
+    async fn remove(&self, name: &str) -> io::Result<()>;
+    // para los mensajes de error
+    fn location(&self) -> String;
+
+    async fn read_file(&self, name: &str) -> io::Result<Vec<u8>> {
+        let file = self.open(name).await?;
+        let mut buf = vec![0u8; file.size().await? as usize];
+        file.read_at(&mut buf, 0).await?;
+        Ok(buf)
+    }
+
+    async fn write_file(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        let file = self.open(name).await?;
+        file.set_len(0).await?;
+        file.write_at(data, 0).await?;
+        file.sync().await
+    }
+}
+
+#[tonic::async_trait]
+pub trait StorageFile: Send + Sync + fmt::Debug {
+    async fn size(&self) -> io::Result<u64>;
+    // llena todo buf o regresa UnexpectedEof
+    async fn read_at(&self, buf: &mut [u8], pos: u64) -> io::Result<()>;
+    async fn write_at(&self, data: &[u8], pos: u64) -> io::Result<()>;
+    async fn set_len(&self, len: u64) -> io::Result<()>;
+    async fn sync(&self) -> io::Result<()>;
+    // los primeros len bytes como memoria; lo escrito llega al archivo con flush
+    async fn map(&self, len: usize) -> io::Result<Box<dyn Mapping>>;
+}
+
+pub trait Mapping: DerefMut<Target = [u8]> + Send + Sync + fmt::Debug {
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone)]
+pub struct DirStorage {
+    dir: PathBuf,
+}
+
+impl DirStorage {
+    pub fn new(dir: &str) -> Self {
+        DirStorage {
+            dir: Path::new(dir).to_path_buf(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Storage for DirStorage {
+    async fn open(&self, name: &str) -> io::Result<Box<dyn StorageFile>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.dir.join(name))
+            .await?;
+        Ok(Box::new(DirFile {
+            file: Mutex::new(file),
+        }))
+    }
+
+    async fn exists(&self, name: &str) -> io::Result<bool> {
+        tokio::fs::try_exists(self.dir.join(name)).await
+    }
+
+    async fn list(&self) -> io::Result<Vec<String>> {
+        let mut names = vec![];
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+        Ok(names)
+    }
+
+    async fn remove(&self, name: &str) -> io::Result<()> {
+        tokio::fs::remove_file(self.dir.join(name)).await
+    }
+
+    fn location(&self) -> String {
+        self.dir.to_string_lossy().into_owned()
+    }
+}
+
+// el Mutex porque cada lectura o escritura mueve el seek
+#[derive(Debug)]
+struct DirFile {
+    file: Mutex<File>,
+}
+
+#[tonic::async_trait]
+impl StorageFile for DirFile {
+    async fn size(&self) -> io::Result<u64> {
+        Ok(self.file.lock().await.metadata().await?.len())
+    }
+
+    async fn read_at(&self, buf: &mut [u8], pos: u64) -> io::Result<()> {
+        let mut file = self.file.lock().await;
+        file.seek(SeekFrom::Start(pos)).await?;
+        file.read_exact(buf).await?;
+        Ok(())
+    }
+
+    async fn write_at(&self, data: &[u8], pos: u64) -> io::Result<()> {
+        let mut file = self.file.lock().await;
+        file.seek(SeekFrom::Start(pos)).await?;
+        file.write_all(data).await?;
+        // el File de tokio escribe en otro hilo, el flush espera a que acabe
+        file.flush().await
+    }
+
+    async fn set_len(&self, len: u64) -> io::Result<()> {
+        self.file.lock().await.set_len(len).await
+    }
+
+    async fn sync(&self) -> io::Result<()> {
+        self.file.lock().await.sync_all().await
+    }
+
+    async fn map(&self, len: usize) -> io::Result<Box<dyn Mapping>> {
+        let file = self.file.lock().await;
+        let mmap = unsafe { MmapOptions::new().len(len).map_mut(&*file)? };
+        Ok(Box::new(mmap))
+    }
+}
+
+impl Mapping for MmapMut {
+    fn flush(&mut self) -> io::Result<()> {
+        MmapMut::flush(self)
+    }
+}
+
+type Bytes = Arc<std::sync::Mutex<Vec<u8>>>;
+
+// Los archivos viven mientras viva algún clon del MemoryStorage, así que un Log
+// se puede cerrar y volver a abrir sobre el mismo.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    files: Arc<std::sync::Mutex<HashMap<String, Bytes>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+}
+
+#[tonic::async_trait]
+impl Storage for MemoryStorage {
+    async fn open(&self, name: &str) -> io::Result<Box<dyn StorageFile>> {
+        let data = self
+            .files
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone();
+        Ok(Box::new(MemoryFile { data }))
+    }
+
+    async fn exists(&self, name: &str) -> io::Result<bool> {
+        Ok(self.files.lock().unwrap().contains_key(name))
+    }
+
+    async fn list(&self) -> io::Result<Vec<String>> {
+        Ok(self.files.lock().unwrap().keys().cloned().collect())
+    }
+
+    async fn remove(&self, name: &str) -> io::Result<()> {
+        match self.files.lock().unwrap().remove(name) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("file not found: {}", name),
+            )),
+        }
+    }
+
+    fn location(&self) -> String {
+        "memory".to_string()
+    }
+}
+
+#[derive(Debug)]
+struct MemoryFile {
+    data: Bytes,
+}
+
+#[tonic::async_trait]
+impl StorageFile for MemoryFile {
+    async fn size(&self) -> io::Result<u64> {
+        Ok(self.data.lock().unwrap().len() as u64)
+    }
+
+    async fn read_at(&self, buf: &mut [u8], pos: u64) -> io::Result<()> {
+        let data = self.data.lock().unwrap();
+        let start = pos as usize;
+        if start + buf.len() > data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "read past the end of the file",
+            ));
+        }
+        buf.copy_from_slice(&data[start..start + buf.len()]);
+        Ok(())
+    }
+
+    async fn write_at(&self, buf: &[u8], pos: u64) -> io::Result<()> {
+        let mut data = self.data.lock().unwrap();
+        let start = pos as usize;
+        if data.len() < start + buf.len() {
+            data.resize(start + buf.len(), 0);
+        }
+        data[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+
+    async fn set_len(&self, len: u64) -> io::Result<()> {
+        self.data.lock().unwrap().resize(len as usize, 0);
+        Ok(())
+    }
+
+    async fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn map(&self, len: usize) -> io::Result<Box<dyn Mapping>> {
+        let mut buf = vec![0u8; len];
+        let data = self.data.lock().unwrap();
+        let n = len.min(data.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(Box::new(MemoryMapping {
+            buf,
+            data: self.data.clone(),
+        }))
+    }
+}
+
+// una copia que se regresa al archivo en cada flush; como con mmap, lo que
+// queda más allá del final del archivo se pierde
+#[derive(Debug)]
+struct MemoryMapping {
+    buf: Vec<u8>,
+    data: Bytes,
+}
+
+impl std::ops::Deref for MemoryMapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl DerefMut for MemoryMapping {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+}
+
+impl Mapping for MemoryMapping {
+    fn flush(&mut self) -> io::Result<()> {
+        let mut data = self.data.lock().unwrap();
+        let n = self.buf.len().min(data.len());
+        data[..n].copy_from_slice(&self.buf[..n]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // los dos backends se tienen que portar igual
+    async fn check(storage: &dyn Storage) {
+        assert!(!storage.exists("0.store").await.unwrap());
+        let file = storage.open("0.store").await.unwrap();
+        assert!(storage.exists("0.store").await.unwrap());
+        assert_eq!(file.size().await.unwrap(), 0);
+
+        file.write_at(b"hola mundo", 0).await.unwrap();
+        file.write_at(b"MUNDO", 5).await.unwrap();
+        let mut buf = [0u8; 10];
+        file.read_at(&mut buf, 0).await.unwrap();
+        assert_eq!(&buf, b"hola MUNDO");
+        let err = file.read_at(&mut buf, 5).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        file.set_len(4).await.unwrap();
+        assert_eq!(storage.read_file("0.store").await.unwrap(), b"hola");
+
+        let index = storage.open("0.index").await.unwrap();
+        index.set_len(16).await.unwrap();
+        let mut mapping = index.map(16).await.unwrap();
+        mapping[..3].copy_from_slice(b"abc");
+        mapping.flush().unwrap();
+        index.set_len(3).await.unwrap();
+        assert_eq!(storage.read_file("0.index").await.unwrap(), b"abc");
+
+        storage.write_file("0.key", b"k1").await.unwrap();
+        let mut names = storage.list().await.unwrap();
+        names.sort();
+        assert_eq!(names, ["0.index", "0.key", "0.store"]);
+        storage.remove("0.key").await.unwrap();
+        assert!(!storage.exists("0.key").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn backends() {
+        let dir = tempfile::tempdir().unwrap();
+        check(&DirStorage::new(dir.path().to_str().unwrap())).await;
+        check(&MemoryStorage::new()).await;
+    }
+}
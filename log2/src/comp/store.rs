@@ -1,161 +1,106 @@
-use std::cell::{Cell, RefCell};
-use std::io::{self, Error};
-use std::os::unix::raw::off_t;
-use std::sync::{Arc, RwLock};
-use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, BufWriter, SeekFrom};
-
-use byteorder::{BigEndian, WriteBytesExt};
-
-// voy a manejar el mutex Mutex::new(Store) ya que asi me evito pedos.
+use crate::comp::storage::StorageFile;
+use std::io;
 
 pub const LEN_WIDTH: usize = 8;
 
+// El byte más alto del largo lleva las banderas del frame (por ejemplo con qué
+// se comprimió). En 0 el frame es idéntico al que escribe Go.
+const FLAGS_SHIFT: u32 = 56;
+const LEN_MASK: u64 = (1 << FLAGS_SHIFT) - 1;
+
 #[derive(Debug)]
 pub struct Store {
-    pub reader: Arc<RwLock<BufReader<File>>>,
-    pub writer: Arc<RwLock<BufWriter<File>>>,
+    pub file: Box<dyn StorageFile>,
     pub size: u64,
     pub path: String,
 }
 
 impl Store {
-    pub async fn new(file: File, path: String) -> io::Result<Store> {
-        let metadata = file.metadata().await?;
-        let size = metadata.len();
-        let reader = BufReader::new(file.try_clone().await?);
-        let writer = BufWriter::new(file);
-
-        Ok(Store {
-            reader: Arc::new(RwLock::new(reader)),
-            writer: Arc::new(RwLock::new(writer)),
-            size,
-            path,
-        })
+    pub async fn new(file: Box<dyn StorageFile>, path: String) -> io::Result<Store> {
+        let size = file.size().await?;
+        Ok(Store { file, size, path })
     }
 
-    pub async fn append(&mut self, p: &[u8]) -> io::Result<(u64, u64)> {
-        // Hacemos que apunte a la dirección donde quiere escribir el archivo en este caso
-        // La ultima posición de el archivo
-
-        match self
-            .writer
-            .write()
-            .unwrap()
-            .seek(SeekFrom::Start(self.size))
-            .await
-        {
-            Ok(_) => {}
-            Err(e) => {
-                println!(
-                    "Error no existe la dirección donde se quiere escribir {}",
-                    e
-                );
-                return Err(e);
-            }
-        };
-
-        let size = (p.len() as u64).to_be_bytes();
+    pub async fn append(&mut self, p: &[u8], flags: u8) -> io::Result<(u64, u64)> {
+        // el frame va al final del archivo: largo con banderas y luego los datos
+        let size = ((p.len() as u64) | (flags as u64) << FLAGS_SHIFT).to_be_bytes();
+        let mut frame = Vec::with_capacity(LEN_WIDTH + p.len());
+        frame.extend_from_slice(&size);
+        frame.extend_from_slice(p);
 
-        self.writer.write().unwrap().write_all(&size).await?;
         let pos = self.size;
+        if let Err(e) = self.file.write_at(&frame, pos).await {
+            tracing::error!(path = %self.path, pos, error = %e, "error al escribir el frame");
+            return Err(e);
+        }
 
         // Actualizamos el tamaño
-
-        let mut bytes_written = self.writer.write().unwrap().write(p).await? as u64;
-        bytes_written += LEN_WIDTH as u64;
-        self.writer.write().unwrap().flush().await?;
-        self.size += bytes_written as u64;
+        let bytes_written = frame.len() as u64;
+        self.size += bytes_written;
 
         Ok((bytes_written, pos))
     }
 
-    pub async fn read(&self, pos: u64) -> io::Result<Vec<u8>> {
-        // el flush para saber que ya acabo de escribir
-        self.writer.write().unwrap().flush().await?;
-
-        match self
-            .reader
-            .write()
-            .unwrap()
-            .seek(SeekFrom::Start(pos))
-            .await
-        {
-            Ok(_) => {}
-            Err(e) => {
-                println!("Error no existe la dirección donde se quiere leer {}", e);
-                return Err(e);
-            }
-        };
-
+    // regresa las banderas del frame y los datos
+    pub async fn read(&self, pos: u64) -> io::Result<(u8, Vec<u8>)> {
         let mut buf = [0u8; LEN_WIDTH];
 
-        // print preventivo para ver que chingados estaba leyendo
-        // println!("{:?}", self.reader);
-
-        match self.reader.write().unwrap().read_exact(&mut buf).await {
+        match self.file.read_at(&mut buf, pos).await {
             Ok(_) => {}
             Err(e) => {
-                println!("Error al leer los datos {}", e);
+                tracing::error!(path = %self.path, pos, error = %e, "error al leer el largo");
+                return Err(e);
             }
         };
 
-        println!("El bufer normal es {:?}", buf);
-
         let size = u64::from_be_bytes(buf);
+        let flags = (size >> FLAGS_SHIFT) as u8;
+        tracing::trace!(path = %self.path, pos, len = size & LEN_MASK, flags, "frame");
+
+        // un largo corrupto no debe reservar memoria ni leer fuera del store
+        let len = size & LEN_MASK;
+        if pos + LEN_WIDTH as u64 + len > self.size {
+            tracing::error!(path = %self.path, pos, len, "el frame se sale del store");
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "frame at {} of {} bytes goes past the end of the store ({} bytes)",
+                    pos, len, self.size
+                ),
+            ));
+        }
+        let mut data_buf = vec![0u8; len as usize];
 
-        let mut data_buf = vec![0u8; size as usize];
-
-        match self.reader.write().unwrap().read_exact(&mut data_buf).await {
-            Ok(_) => Ok(data_buf),
+        let data_pos = pos + LEN_WIDTH as u64;
+        match self.file.read_at(&mut data_buf, data_pos).await {
+            Ok(_) => Ok((flags, data_buf)),
             Err(e) => {
-                println!("Error al leer los datos {}", e);
+                tracing::error!(path = %self.path, pos, error = %e, "error al leer los datos");
                 Err(e)
             }
         }
     }
 
-    pub async fn name(self) -> String {
-        self.path
+    // dónde acaba el frame que empieza en pos, o None si no cabe en el store
+    pub async fn frame_end(&self, pos: u64) -> io::Result<Option<u64>> {
+        if pos + LEN_WIDTH as u64 > self.size {
+            return Ok(None);
+        }
+        let mut buf = [0u8; LEN_WIDTH];
+        self.file.read_at(&mut buf, pos).await?;
+        let end = pos + LEN_WIDTH as u64 + (u64::from_be_bytes(buf) & LEN_MASK);
+        Ok((end <= self.size).then_some(end))
     }
 
     pub async fn close(&mut self) -> io::Result<()> {
         self.path = "".to_string();
-        match self.writer.write().unwrap().flush().await {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
-        };
-        match self.reader.write().unwrap().flush().await {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
-        }
+        self.file.sync().await
     }
 
-    pub async fn reat_at(&self, mut buf: &mut [u8], off: u64) -> io::Result<usize> {
-        match self
-            .reader
-            .write()
-            .unwrap()
-            .seek(SeekFrom::Start(off))
-            .await
-        {
-            Ok(_) => {
-                println!(
-                    "El tamaño es {}",
-                    self.reader
-                        .write()
-                        .unwrap()
-                        .read_exact(&mut buf)
-                        .await
-                        .unwrap() as u64
-                );
-            }
-            Err(e) => {
-                println!("Error no existe la dirección donde se quiere leer {}", e);
-                return Err(e);
-            }
-        };
-
-        self.reader.write().unwrap().read_exact(&mut buf).await
+    // corta el store en pos, raft lo usa para borrar entradas que no coinciden con el lider
+    pub async fn truncate(&mut self, pos: u64) -> io::Result<()> {
+        self.file.set_len(pos).await?;
+        self.size = pos;
+        Ok(())
     }
 }
@@ -0,0 +1,203 @@
+use crate::comp::config::{Config, Tiering};
+use crate::comp::segments::Segment;
+use crate::comp::storage::{MemoryStorage, Storage};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// Donde se van los segmentos sellados para no llenar el disco. Las keys son
+// "<prefix>/<base>.store" y así; S3 y compañía encajan aquí.
+#[tonic::async_trait]
+pub trait ObjectStore: Send + Sync + fmt::Debug {
+    async fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()>;
+    // NotFound si no existe
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>>;
+    // los nombres que hay directo bajo prefix, sin el prefix
+    async fn list(&self, prefix: &str) -> io::Result<Vec<String>>;
+    async fn delete(&self, key: &str) -> io::Result<()>;
+}
+
+// un directorio hace de object store, cada "/" de la key es un subdirectorio
+#[derive(Debug, Clone)]
+pub struct LocalObjectStore {
+    dir: PathBuf,
+}
+
+impl LocalObjectStore {
+    pub fn new(dir: &str) -> Self {
+        LocalObjectStore {
+            dir: Path::new(dir).to_path_buf(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ObjectStore for LocalObjectStore {
+    async fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()> {
+        let path = self.dir.join(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // que nadie vea un objeto a medias
+        let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, &path).await
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        tokio::fs::read(self.dir.join(key)).await
+    }
+
+    async fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let mut entries = match tokio::fs::read_dir(self.dir.join(prefix)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let mut names = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type().await?.is_file() && !name.ends_with(".tmp") {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        tokio::fs::remove_file(self.dir.join(key)).await
+    }
+}
+
+// el mismo config pero con los objetos bajo otro prefix; cada log necesita el suyo
+pub fn under(config: &Config, name: &str) -> Config {
+    let mut config = config.clone();
+    if let Some(tiering) = &mut config.tiering {
+        tiering.prefix = if tiering.prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", tiering.prefix, name)
+        };
+    }
+    config
+}
+
+// el .key va primero y el .store al final: si está el .store el segmento está completo
+const EXTENSIONS: [&str; 3] = ["key", "index", "store"];
+
+fn key(tiering: &Tiering, name: &str) -> String {
+    format!("{}/{}", tiering.prefix, name)
+}
+
+// base offsets de los segmentos que ya están en el object store, ordenados
+pub async fn remote_segments(tiering: &Tiering) -> io::Result<Vec<u64>> {
+    let mut bases: Vec<u64> = tiering
+        .store
+        .list(&tiering.prefix)
+        .await?
+        .iter()
+        .filter_map(|name| name.strip_suffix(".store")?.parse().ok())
+        .collect();
+    bases.sort();
+    Ok(bases)
+}
+
+// sube un segmento cerrado; el que llama lo borra del disco después
+pub async fn upload(tiering: &Tiering, segment: &Segment) -> io::Result<()> {
+    let names = [&segment.path_key, &segment.path_index, &segment.path_store];
+    for name in names {
+        if *name == segment.path_key && segment.key_id.is_none() {
+            continue;
+        }
+        let data = segment.storage.read_file(name).await?;
+        tiering.store.put(&key(tiering, name), data).await?;
+    }
+    Ok(())
+}
+
+pub async fn delete(tiering: &Tiering, base: u64) -> io::Result<()> {
+    for ext in EXTENSIONS.iter().rev() {
+        match tiering
+            .store
+            .delete(&key(tiering, &format!("{}.{}", base, ext)))
+            .await
+        {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Los segmentos remotos que se leyeron hace poco, en memoria. Se bajan
+// completos la primera vez que se lee algo de ellos.
+#[derive(Default)]
+pub struct RemoteCache {
+    segments: Mutex<VecDeque<(u64, Arc<Segment>)>>,
+}
+
+impl RemoteCache {
+    pub async fn get(&self, base: u64, config: &Config) -> io::Result<Arc<Segment>> {
+        let tiering = config
+            .tiering
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "tiering is disabled"))?;
+        let mut segments = self.segments.lock().await;
+        if let Some(i) = segments.iter().position(|(b, _)| *b == base) {
+            let entry = segments.remove(i).unwrap();
+            let segment = entry.1.clone();
+            segments.push_back(entry);
+            return Ok(segment);
+        }
+
+        let storage = MemoryStorage::new();
+        for ext in EXTENSIONS {
+            let name = format!("{}.{}", base, ext);
+            match tiering.store.get(&key(tiering, &name)).await {
+                Ok(data) => storage.write_file(&name, &data).await?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound && ext == "key" => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let segment = Arc::new(Segment::new(Arc::new(storage), base, config.clone()).await?);
+
+        segments.push_back((base, segment.clone()));
+        while segments.len() > tiering.cache_segments.max(1) {
+            segments.pop_front();
+        }
+        Ok(segment)
+    }
+
+    pub async fn evict(&self, base: u64) {
+        self.segments.lock().await.retain(|(b, _)| *b != base);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn local_object_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalObjectStore::new(dir.path().to_str().unwrap());
+
+        assert!(store.list("a/b").await.unwrap().is_empty());
+        store.put("a/b/0.store", b"hola".to_vec()).await.unwrap();
+        store.put("a/b/9.store", b"adios".to_vec()).await.unwrap();
+        store.put("a/c/0.store", b"otro".to_vec()).await.unwrap();
+
+        assert_eq!(store.get("a/b/0.store").await.unwrap(), b"hola");
+        let mut names = store.list("a/b").await.unwrap();
+        names.sort();
+        assert_eq!(names, ["0.store", "9.store"]);
+
+        store.delete("a/b/0.store").await.unwrap();
+        let err = store.get("a/b/0.store").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(store.list("a/b").await.unwrap(), ["9.store"]);
+    }
+}
@@ -0,0 +1,215 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tonic::codegen::http;
+use tower::{Layer, Service};
+use tracing::Instrument;
+
+// El contexto de traza viaja en el header "traceparent" de W3C, que en gRPC es
+// un dato más de la metadata: "00-<trace id>-<span id>-<flags>" en hex. Así un
+// produce se puede seguir desde el cliente hasta el store buscando su trace id
+// en los logs.
+pub const TRACEPARENT: &str = "traceparent";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceContext {
+    // 32 caracteres hex, el mismo en toda la traza
+    pub trace_id: String,
+    // 16 caracteres hex, el span de quien hizo la llamada
+    pub parent_id: String,
+}
+
+impl TraceContext {
+    // una traza nueva, para cuando nadie nos mandó una
+    pub fn new() -> Self {
+        TraceContext {
+            trace_id: format!("{:032x}", rand::random::<u128>().max(1)),
+            parent_id: format!("{:016x}", rand::random::<u64>().max(1)),
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        let parts: Vec<&str> = value.trim().split('-').collect();
+        let [version, trace_id, parent_id, flags] = parts[..] else {
+            return None;
+        };
+        let hex = |s: &str, len: usize| {
+            s.len() == len
+                && s.bytes()
+                    .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+                && s.bytes().any(|b| b != b'0')
+        };
+        // la versión ff no es válida y los ceros quieren decir "sin traza"
+        if version.len() != 2 || version == "ff" || !hex(trace_id, 32) || !hex(parent_id, 16) {
+            return None;
+        }
+        if flags.len() != 2 || u8::from_str_radix(flags, 16).is_err() {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id: trace_id.to_string(),
+            parent_id: parent_id.to_string(),
+        })
+    }
+
+    // el header para la siguiente llamada: misma traza, span nuevo
+    pub fn child(&self) -> String {
+        format!(
+            "00-{}-{:016x}-01",
+            self.trace_id,
+            rand::random::<u64>().max(1)
+        )
+    }
+
+    pub fn inject<T>(&self, req: &mut tonic::Request<T>) {
+        if let Ok(value) = self.child().parse() {
+            req.metadata_mut().insert(TRACEPARENT, value);
+        }
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        TraceContext::new()
+    }
+}
+
+// Abre un span "rpc" por cada request con el método y el trace id; lo que
+// pasa adentro (Log, Segment, Store) queda anidado en él. Si el request no
+// trae traceparent o viene mal, empieza una traza nueva. Sirve igual para
+// gRPC que para el gateway HTTP.
+#[derive(Debug, Clone, Default)]
+pub struct TraceLayer;
+
+impl<S> Layer<S> for TraceLayer {
+    type Service = Trace<S>;
+
+    fn layer(&self, inner: S) -> Trace<S> {
+        Trace { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Trace<S> {
+    inner: S,
+}
+
+impl<S, B> Service<http::Request<B>> for Trace<S>
+where
+    S: Service<http::Request<B>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        let ctx = req
+            .headers()
+            .get(TRACEPARENT)
+            .and_then(|v| v.to_str().ok())
+            .and_then(TraceContext::parse)
+            .unwrap_or_default();
+        let span = tracing::info_span!(
+            "rpc",
+            method = %req.uri().path(),
+            trace_id = %ctx.trace_id,
+            parent_id = %ctx.parent_id,
+        );
+        // para los handlers que hacen otras llamadas y quieren seguir la traza
+        req.extensions_mut().insert(ctx);
+        let fut = span.in_scope(|| self.inner.call(req));
+        Box::pin(fut.instrument(span))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+
+    #[test]
+    fn parse_traceparent() {
+        let ctx =
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(ctx.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(ctx.parent_id, "00f067aa0ba902b7");
+
+        let child = TraceContext::parse(&ctx.child()).unwrap();
+        assert_eq!(child.trace_id, ctx.trace_id);
+        assert_ne!(child.parent_id, ctx.parent_id);
+
+        for bad in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(TraceContext::parse(bad), None, "{}", bad);
+        }
+        assert!(TraceContext::parse(&TraceContext::new().child()).is_some());
+    }
+
+    #[derive(Clone, Default)]
+    struct Buf(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buf {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn span_carries_trace_id() {
+        let buf = Buf::default();
+        let writer = buf.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let service = TraceLayer.layer(tower::service_fn(|req: http::Request<()>| async move {
+            tracing::info!("adentro");
+            let ctx = req.extensions().get::<TraceContext>().cloned();
+            Ok::<_, io::Error>(ctx)
+        }));
+        let req = http::Request::builder()
+            .uri("/record.Log/Produce")
+            .header(
+                TRACEPARENT,
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            )
+            .body(())
+            .unwrap();
+        let ctx = service.clone().oneshot(req).await.unwrap().unwrap();
+        assert_eq!(ctx.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+
+        let out = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        assert!(out.contains("method=/record.Log/Produce"), "{}", out);
+        assert!(
+            out.contains("trace_id=4bf92f3577b34da6a3ce929d0e0e4736"),
+            "{}",
+            out
+        );
+        assert!(out.contains("adentro"), "{}", out);
+
+        // sin header la traza es nueva
+        let req = http::Request::builder().body(()).unwrap();
+        let ctx = service.oneshot(req).await.unwrap().unwrap();
+        assert_eq!(ctx.trace_id.len(), 32);
+    }
+}
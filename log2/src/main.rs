@@ -1,67 +1,393 @@
 mod comp {
+    pub mod admin;
+    pub mod auth;
+    pub mod broker;
+    pub mod compression;
     pub mod config;
+    pub mod distributed;
+    pub mod encryption;
+    pub mod filter;
+    pub mod gateway;
+    pub mod health;
     pub mod index;
+    pub mod loadbalance;
     pub mod log;
+    pub mod membership;
+    pub mod metrics;
+    pub mod mux;
+    pub mod offsets;
+    pub mod quota;
+    pub mod raft;
     pub mod record;
+    pub mod replicator;
     pub mod segments;
+    pub mod server;
+    pub mod settings;
+    pub mod snapshot;
+    pub mod storage;
     pub mod store;
+    pub mod tiered;
+    pub mod trace;
 }
-use comp::config::Config;
-use comp::index::Index;
-// use comp::log::Log;
-use comp::segments::Segment;
-use comp::store::Store;
+use comp::admin::{new_admin_server, AdminConfig};
+use comp::auth::Authorizer;
+use comp::broker::Broker;
+use comp::config::{MembershipConfig, RaftConfig};
+use comp::distributed::DistributedLog;
+use comp::gateway;
+use comp::health::{new_health_server, new_reflection_server, set_serving, Deferred, DeferredLog};
+use comp::loadbalance::Client;
+use comp::log::Log;
+use comp::membership::{Handler, Membership, RPC_ADDR_TAG};
+use comp::metrics::RpcMetricsLayer;
+use comp::mux::Mux;
+use comp::offsets::OffsetStore;
+use comp::record::Record;
+use comp::replicator::Replicator;
+use comp::record::log_server::LogServer;
+use comp::server::{new_log_service, CommitLog, ServerConfig};
+use comp::settings::Settings;
+use comp::tiered;
+use comp::trace::TraceLayer;
+use std::collections::HashMap;
 use std::error::Error;
-use tokio;
-use tokio::fs::OpenOptions;
-use tokio::net::TcpListener;
-use tokio::task::spawn_blocking;
+use std::fmt::Display;
+use std::io;
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{watch, RwLock};
+use tokio::time::{timeout, Duration};
+use tonic::transport::Server;
+use tracing_subscriber::EnvFilter;
 
+// lo que esperamos a que terminen los streams y las conexiones al apagar
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+const USO: &str = "uso: log [<id> <data_dir> <rpc_addr>] [--config <archivo.toml|yaml>] \
+[--print-config] [--raft [--bootstrap]] [--peer <id>=<addr>]... \
+[--gossip <addr> [--join <addr>]...] [--compression none|zstd|lz4|snappy] \
+[--keyfile <path>] [--tier-dir <path>] [--http <addr>]
+     log client <rpc_addr> (servers | produce <valor> | consume <offset>)
+     log restore <snapshot.tar> <los argumentos del nodo>";
+
+// Con --raft el nodo usa el log replicado con raft, en el mismo puerto que
+// gRPC. Sin él es un Log normal que jala con el Replicator lo que se produce
+// en cada --peer. Con --gossip los nodos se descubren solos: entran a la
+// configuración de raft o se replican, según el modo. Todo se puede poner
+// también en el archivo de --config o en variables LOG_* (ver comp/settings.rs).
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let file_path = "temp_file.bin";
-    let file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(file_path)
-        .await?;
+    // RUST_LOG elige el nivel, por ejemplo RUST_LOG=log=debug para ver cada
+    // append con su trace id; va a stderr para no ensuciar lo que imprime el cliente
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
+        .with_writer(std::io::stderr)
+        .init();
 
-    let mut store = Store::new(file, file_path.to_string()).await?;
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).is_some_and(|a| a == "client") {
+        return client(&args[2..]).await;
+    }
+    if args.get(1).is_some_and(|a| a == "restore") {
+        return restore(&args[2..]).await;
+    }
+    let settings =
+        Settings::load(&args[1..], |name| std::env::var(name).ok()).unwrap_or_else(|e| usage(e));
+    if args.iter().any(|a| a == "--print-config") {
+        print!("{}", settings.to_toml());
+        return Ok(());
+    }
+    if let Err(e) = settings.check() {
+        usage(e);
+    }
+    let (id, data_dir, rpc_addr) = (&settings.id, &settings.data_dir, &settings.rpc_addr);
 
-    let data = b"hello  hasldkfhjalsdk  ";
+    if settings.raft && !settings.peers.is_empty() {
+        eprintln!("con --raft los nodos se unen por raft, no con --peer");
+        std::process::exit(2);
+    }
 
-    let (n, pos) = store.append(data).await?;
-    println!("Data appended at position: {}, bytes written: {}", pos, n);
+    let config = settings.config().unwrap_or_else(|e| usage(e));
+    let authorizer = match (&settings.acl.model_file, &settings.acl.policy_file) {
+        (Some(model), Some(policy)) => Some(Arc::new(Authorizer::new(model, policy)?)),
+        _ => None,
+    };
 
-    let read_data = store.read(pos).await?;
-    println!("Data read: {:?}", String::from_utf8(read_data)?);
+    let mux = Mux::bind(rpc_addr).await?;
+    let (layer, grpc) = mux.split()?;
 
-    let data = b"hello";
+    // gRPC empieza a aceptar antes de recuperar los logs, así health y
+    // reflection contestan desde ya; el Log dice UNAVAILABLE hasta que esté
+    let (mut health, health_server) = new_health_server().await;
+    let deferred = DeferredLog::default();
+    let admin = Deferred::default();
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let mut stopped = stop_rx.clone();
+    let mut builder = Server::builder();
+    if let Some(tls) = settings.server_tls()? {
+        builder = builder.tls_config(tls)?;
+    }
+    let mut server = tokio::spawn(
+        builder
+            .layer(RpcMetricsLayer)
+            .layer(TraceLayer)
+            .add_service(health_server)
+            .add_service(new_reflection_server())
+            .add_service(deferred.clone())
+            .add_service(admin.clone())
+            .serve_with_incoming_shutdown(grpc, async move {
+                let _ = stopped.wait_for(|s| *s).await;
+            }),
+    );
+    tracing::info!(id, %rpc_addr, "nodo escuchando, recuperando los logs");
 
-    let (n, pos) = store.append(data).await?;
-    println!("Data appended at position: {}, bytes written: {}", pos, n);
+    // cada log deja sus segmentos bajo su propio prefix en el object store
+    let log_config = tiered::under(&config, "log");
+    let mut replicator = None;
+    // el Log de este nodo, para el servicio Admin
+    let local_log: Arc<RwLock<Log>>;
+    let (commit_log, handler): (Arc<dyn CommitLog>, Arc<dyn Handler>) = if settings.raft {
+        let raft_config = RaftConfig {
+            local_id: id.clone(),
+            bind_addr: layer.local_addr()?,
+            rpc_addr: rpc_addr.clone(),
+            bootstrap: settings.bootstrap,
+            ..RaftConfig::default()
+        };
+        let log = Arc::new(DistributedLog::new(data_dir, log_config, raft_config, layer).await?);
+        // el que arranca el cluster se elige solo; así los primeros Produce no
+        // fallan con "not the raft leader"
+        if settings.bootstrap {
+            log.wait_for_leader(Duration::from_secs(3)).await?;
+        }
+        local_log = log.local_log();
+        (log.clone(), log)
+    } else {
+        std::fs::create_dir_all(data_dir)?;
+        local_log = Arc::new(RwLock::new(Log::new(data_dir, log_config).await?));
+        let log: Arc<dyn CommitLog> = local_log.clone();
+        // hasta dónde se replicó de cada peer
+        let cursors_dir = std::path::Path::new(data_dir).join("replication");
+        let cursors = OffsetStore::new(cursors_dir.to_str().unwrap(), config.clone()).await?;
+        let r = Arc::new(Replicator::new(id, Arc::clone(&log), Arc::new(cursors)));
+        for (name, addr) in &settings.peers()? {
+            r.join(name, addr);
+        }
+        replicator = Some(r.clone());
+        (log, r)
+    };
 
-    let mut read_data = store.read(pos).await?;
-    println!("Data read: {:?}", String::from_utf8(read_data.clone())?);
+    let membership = match settings.gossip_addr.clone() {
+        Some(bind_addr) => {
+            let config = MembershipConfig {
+                node_name: id.clone(),
+                bind_addr,
+                tags: HashMap::from([(RPC_ADDR_TAG.to_string(), rpc_addr.clone())]),
+                start_join_addrs: settings.join_addrs.clone(),
+                ..MembershipConfig::default()
+            };
+            let membership = Membership::new(config, handler).await?;
+            tracing::info!(addr = membership.addr(), "membresía escuchando");
+            Some(membership)
+        }
+        None => None,
+    };
 
-    println!(
-        "EL tamaño es de {}",
-        store.reat_at(&mut read_data[..], pos).await.unwrap() as u64
-    );
+    // los topics viven aparte del log del nodo y no se replican
+    let topics_dir = std::path::Path::new(data_dir).join("topics");
+    let topics_config = tiered::under(&config, "topics");
+    let broker = Arc::new(Broker::new(topics_dir.to_str().unwrap(), topics_config).await?);
+    let offsets_dir = std::path::Path::new(data_dir).join("offsets");
+    let offsets = Arc::new(OffsetStore::new(offsets_dir.to_str().unwrap(), config).await?);
+
+    let service = new_log_service(ServerConfig {
+        broker: Some(broker.clone()),
+        offsets: Some(offsets.clone()),
+        authorizer: authorizer.clone(),
+        quotas: settings.quotas().map(Arc::new),
+        ..ServerConfig::new(commit_log.clone())
+    });
+
+    // la retención no aplica al log de raft, solo a los topics
+    let mut retention = None;
+    if settings.retention.max_records > 0 {
+        let (commit_log, broker) = (commit_log.clone(), broker.clone());
+        let (max_records, every) = (
+            settings.retention.max_records,
+            Duration::from_secs(settings.retention.interval_secs),
+        );
+        let mut stopped = stop_rx.clone();
+        retention = Some(tokio::spawn(async move {
+            let mut tick = tokio::time::interval(every);
+            loop {
+                tokio::select! {
+                    _ = tick.tick() => {}
+                    _ = stopped.wait_for(|s| *s) => return,
+                }
+                match commit_log.retain(max_records).await {
+                    Err(e) if e.kind() != io::ErrorKind::Unsupported => {
+                        tracing::warn!(error = %e, "no se pudo aplicar la retención al log");
+                    }
+                    _ => {}
+                }
+                if let Err(e) = broker.retain(max_records).await {
+                    tracing::warn!(error = %e, "no se pudo aplicar la retención a los topics");
+                }
+            }
+        }));
+    }
+
+    // el gateway HTTP usa el mismo servicio, y con él los mismos logs; también
+    // sirve /metrics. Va sin TLS, así que con ACL no puede producir, consumir ni
+    // tocar los topics
+    let mut http = None;
+    if let Some(addr) = &settings.http_addr {
+        let listener = std::net::TcpListener::bind(addr)?;
+        let mut stopped = stop_rx.clone();
+        let server = axum::Server::from_tcp(listener)?
+            .serve(gateway::router(service.clone()).into_make_service())
+            .with_graceful_shutdown(async move {
+                let _ = stopped.wait_for(|s| *s).await;
+            });
+        tracing::info!(%addr, "gateway HTTP escuchando");
+        http = Some(tokio::spawn(async move {
+            if let Err(e) = server.await {
+                tracing::error!(error = %e, "el gateway HTTP se cayó");
+            }
+        }));
+    }
+
+    deferred.set(LogServer::new(service.clone()));
+    admin.set(new_admin_server(AdminConfig {
+        broker: Some(broker.clone()),
+        authorizer,
+        ..AdminConfig::new(local_log)
+    }));
+    set_serving(&mut health, true).await;
+    tracing::info!("logs recuperados, sirviendo");
 
-    store.close().await?;
-    println!("Store closed successfully.");
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        res = &mut server => res??,
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
 
+    // NOT_SERVING primero para que los balanceadores dejen de mandarnos
+    // tráfico; luego se dejan de aceptar RPCs y los streams terminan con lo
+    // que ya estaba escrito
+    tracing::info!("apagando");
+    set_serving(&mut health, false).await;
+    service.shutdown();
+    stop_tx.send_replace(true);
+    let drained = timeout(SHUTDOWN_TIMEOUT, async {
+        // si gRPC ya había terminado el select de arriba lo consumió
+        let res = match server.is_finished() {
+            true => Ok(Ok(())),
+            false => (&mut server).await,
+        };
+        if let Some(http) = http {
+            let _ = http.await;
+        }
+        res
+    })
+    .await;
+    match drained {
+        Ok(res) => res??,
+        Err(_) => {
+            tracing::warn!("los streams no terminaron a tiempo, se cortan");
+            server.abort();
+        }
+    }
+
+    // ya nadie escribe: primero los que traen registros de fuera y al final
+    // los logs, así close corta los index a su tamaño real
+    if let Some(retention) = retention {
+        let _ = retention.await;
+    }
+    if let Some(membership) = membership {
+        membership.leave().await?;
+    }
+    if let Some(replicator) = replicator {
+        replicator.close().await?;
+    }
+    commit_log.close().await?;
+    broker.close().await?;
+    offsets.close().await?;
+    tracing::info!("logs cerrados");
     Ok(())
 }
 
-use std::fs::File;
-use std::io::{self, Write};
-use std::path::Path;
+fn usage(e: impl Display) -> ! {
+    eprintln!("{}\n{}", e, USO);
+    std::process::exit(2);
+}
 
-use crate::comp::config::SegmentConfig;
-use crate::comp::record::Record;
+// Arma el log de un nodo nuevo con un snapshot del rpc Admin.Snapshot, antes de
+// arrancarlo. Lleva los mismos argumentos que el nodo para saber dónde va el
+// log y con qué llaves se abre.
+async fn restore(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (archive, args) = match args {
+        [archive, args @ ..] => (archive, args),
+        _ => usage("falta el snapshot"),
+    };
+    let settings =
+        Settings::load(args, |name| std::env::var(name).ok()).unwrap_or_else(|e| usage(e));
+    if let Err(e) = settings.check() {
+        usage(e);
+    }
+    // el log de raft se llena desde el lider, no de un snapshot del fsm
+    if settings.raft {
+        usage("restore no funciona con --raft");
+    }
+    let config = tiered::under(&settings.config().unwrap_or_else(|e| usage(e)), "log");
+    let mut log = Log::restore(archive, &settings.data_dir, config).await?;
+    println!(
+        "{} restaurado, offsets {} a {}",
+        settings.data_dir,
+        log.lowest_offset().await?,
+        log.highest_offset().await?
+    );
+    log.close().await?;
+    Ok(())
+}
+
+// El cliente descubre el cluster a partir de cualquier nodo: produce le
+// llega al lider y consume a los followers.
+async fn client(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (addr, cmd) = match args {
+        [addr, cmd, ..] => (addr, cmd.as_str()),
+        _ => {
+            eprintln!("{}", USO);
+            std::process::exit(2);
+        }
+    };
+    let client = Client::connect(addr).await?;
+    match (cmd, args.get(2)) {
+        ("servers", None) => {
+            for server in client.servers() {
+                let leader = if server.is_leader { " (lider)" } else { "" };
+                println!("{} {}{}", server.id, server.rpc_addr, leader);
+            }
+        }
+        ("produce", Some(value)) => {
+            let record = Record {
+                value: value.as_bytes().to_vec(),
+                ..Record::default()
+            };
+            println!("{}", client.produce(record).await?);
+        }
+        ("consume", Some(offset)) => {
+            let record = client.consume(offset.parse()?).await?;
+            println!("{}", String::from_utf8_lossy(&record.value));
+        }
+        _ => {
+            eprintln!("{}", USO);
+            std::process::exit(2);
+        }
+    }
+    Ok(())
+}
 
 // #[derive(Clone)]
 // struct Log {